@@ -117,7 +117,22 @@ export const getPackageFromRemote = async (origin: string, packageType: PackageT
 		default:
 			throw new Error(`${packageType} download not implemented`);
 	}
-	link.href = `${origin}/packages/${target}`;
+
+	// Packaging runs as a job on the server, wait for it to finish before downloading the result
+	const response = await fetch(`${origin}/packages/${target}`, { method: "POST" });
+	if (!response.ok) {
+		throw new Error(`Failed to start packaging (${response.status})`);
+	}
+	let status = await response.json();
+	while (status.state === "running") {
+		await new Promise(resolve => setTimeout(resolve, 500));
+		status = await (await fetch(`${origin}/jobs/${status.id}`)).json();
+	}
+	if (status.state !== "finished" || !status.uri) {
+		throw new Error(status.error?.message || `Packaging ${status.state}`);
+	}
+
+	link.href = `${origin}${status.uri}`;
 	document.body.appendChild(link);
 	link.click();
 	document.body.removeChild(link);
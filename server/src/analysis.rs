@@ -0,0 +1,188 @@
+use {
+    crate::audio,
+    ebur128::{EbuR128, Mode},
+    rocket::serde::{Deserialize, Serialize},
+    std::{
+        path::{Path, PathBuf},
+        time::UNIX_EPOCH,
+    },
+};
+
+//samples per peak of the finest level, each following level is LEVEL_FACTOR times coarser
+const BASE_SAMPLES_PER_PEAK: usize = 256;
+const LEVEL_FACTOR: usize = 4;
+const LEVELS: usize = 4;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct PeakLevel {
+    pub samples_per_peak: usize,
+    //per channel, [min, max] of each block of samples
+    pub peaks: Vec<Vec<[f32; 2]>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Analysis {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub frames: u64,
+    //the loudness figures are null for silence
+    pub peak_dbfs: Option<f64>,
+    pub rms_dbfs: Option<f64>,
+    pub integrated_lufs: Option<f64>,
+    pub levels: Vec<PeakLevel>,
+}
+
+//what is stored next to the data, the analysis is reused while size and mtime match
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Cached {
+    size: u64,
+    modified: u128,
+    analysis: Analysis,
+}
+
+//hidden, so that it doesn't show up in listings or archives
+fn cache_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    Some(path.with_file_name(format!(".{name}.analysis.json")))
+}
+
+fn cache_key(path: &Path) -> Option<(u64, u128)> {
+    let metadata = path.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_nanos()))
+}
+
+fn db(value: f64) -> Option<f64> {
+    (value > 0.0).then(|| 20.0 * value.log10())
+}
+
+/// Analyze the audio file at `path`, reusing the cached analysis if the file hasn't changed.
+/// A new analysis is only cached if `store` is set, for directories that may be written to.
+pub fn analyze(path: &Path, store: bool) -> Result<Analysis, String> {
+    let key = cache_key(path).ok_or("cannot read file")?;
+    let cache = cache_path(path).ok_or("invalid file name")?;
+    if let Ok(cached) = std::fs::read(&cache)
+        && let Ok(cached) = serde_json::from_slice::<Cached>(&cached)
+        && (cached.size, cached.modified) == key
+    {
+        return Ok(cached.analysis);
+    }
+
+    let analysis = compute(path)?;
+    if !store {
+        return Ok(analysis);
+    }
+    let cached = Cached {
+        size: key.0,
+        modified: key.1,
+        analysis,
+    };
+    //a cache that can't be written only costs time
+    if let Err(e) = serde_json::to_vec(&cached)
+        .map_err(std::io::Error::from)
+        .and_then(|data| std::fs::write(&cache, data))
+    {
+        eprintln!("failed to cache analysis of {}: {e}", path.display());
+    }
+    Ok(cached.analysis)
+}
+
+/// Remove the cached analysis of `path`, if any.
+pub fn remove_cache(path: &Path) {
+    if let Some(cache) = cache_path(path) {
+        let _ = std::fs::remove_file(cache);
+    }
+}
+
+/// Move the cached analysis of `from` to go with `to`, which it has been renamed to. A rename
+/// keeps the size and modification time, so the analysis stays valid.
+pub fn move_cache(from: &Path, to: &Path) {
+    remove_cache(to);
+    if let (Some(from), Some(to)) = (cache_path(from), cache_path(to)) {
+        let _ = std::fs::rename(from, to);
+    }
+}
+
+fn compute(path: &Path) -> Result<Analysis, String> {
+    let (sample_rate, channels) = audio::probe(path).map(|i| (i.sample_rate, i.channels))?;
+    let mut loudness = EbuR128::new(channels as u32, sample_rate, Mode::I)
+        .map_err(|e| format!("cannot measure loudness: {e}"))?;
+
+    let mut peaks: Vec<Vec<[f32; 2]>> = vec![Vec::new(); channels];
+    //the block being filled, per channel
+    let mut block = vec![[f32::MAX, f32::MIN]; channels];
+    let mut block_len = 0;
+    let mut frames = 0u64;
+    let mut peak = 0f32;
+    let mut sum_squares = 0f64;
+
+    audio::decode(path, |samples| {
+        let _ = loudness.add_frames_f32(samples);
+        for frame in samples.chunks_exact(channels) {
+            for (c, &s) in frame.iter().enumerate() {
+                block[c][0] = block[c][0].min(s);
+                block[c][1] = block[c][1].max(s);
+                peak = peak.max(s.abs());
+                sum_squares += (s as f64) * (s as f64);
+            }
+            block_len += 1;
+            frames += 1;
+            if block_len == BASE_SAMPLES_PER_PEAK {
+                for (c, b) in block.iter_mut().enumerate() {
+                    peaks[c].push(*b);
+                    *b = [f32::MAX, f32::MIN];
+                }
+                block_len = 0;
+            }
+        }
+        Ok(())
+    })?;
+    if block_len > 0 {
+        for (c, b) in block.into_iter().enumerate() {
+            peaks[c].push(b);
+        }
+    }
+
+    //coarser levels merge the blocks of the finer ones
+    let mut levels = vec![PeakLevel {
+        samples_per_peak: BASE_SAMPLES_PER_PEAK,
+        peaks,
+    }];
+    for _ in 1..LEVELS {
+        let finer = levels.last().expect("to get level");
+        let peaks = finer
+            .peaks
+            .iter()
+            .map(|channel| {
+                channel
+                    .chunks(LEVEL_FACTOR)
+                    .map(|c| {
+                        c.iter().fold([f32::MAX, f32::MIN], |acc, p| {
+                            [acc[0].min(p[0]), acc[1].max(p[1])]
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        levels.push(PeakLevel {
+            samples_per_peak: finer.samples_per_peak * LEVEL_FACTOR,
+            peaks,
+        });
+    }
+
+    let samples = frames * channels as u64;
+    Ok(Analysis {
+        sample_rate,
+        channels,
+        frames,
+        peak_dbfs: db(peak as f64),
+        rms_dbfs: (samples > 0)
+            .then(|| db((sum_squares / samples as f64).sqrt()))
+            .flatten(),
+        integrated_lufs: loudness.loudness_global().ok().filter(|l| l.is_finite()),
+        levels,
+    })
+}
@@ -0,0 +1,558 @@
+use {
+    crate::{
+        filelist,
+        usage::{SPACE_CHECK_INTERVAL, Space},
+    },
+    rocket::{
+        FromFormField, Request, Response,
+        http::ContentType,
+        response::{self, Responder},
+        serde::{Deserialize, Serialize},
+        time::OffsetDateTime,
+    },
+    std::{
+        fs::File,
+        io::{self, BufWriter, Read, Write},
+        path::{Component, Path, PathBuf},
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tokio::{
+        io::{AsyncRead, ReadBuf},
+        sync::mpsc,
+    },
+};
+
+//size of the chunks handed to the response
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(FromFormField, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArchiveFormat {
+    #[field(value = "zip")]
+    Zip,
+    #[field(value = "tar")]
+    Tar,
+    #[field(value = "tar.gz")]
+    #[field(value = "tgz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            Self::Zip => ContentType::ZIP,
+            Self::Tar => ContentType::TAR,
+            Self::TarGz => ContentType::GZIP,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// The bytes of an archive as they are written, see [`stream`]. Reading fails if writing the
+/// archive does, so that the response is cut off rather than ending like a complete archive.
+pub struct ArchiveStream {
+    rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+    //the chunk being read and how much of it has been
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for ArchiveStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.pos >= self.chunk.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(e)),
+                //the writer is done
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let n = buf.remaining().min(self.chunk.len() - self.pos);
+        buf.put_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ArchiveStream {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build().streamed_body(self).ok()
+    }
+}
+
+//hands everything written to it over to the stream, fails once the stream is dropped
+struct ChannelWriter(mpsc::Sender<io::Result<Vec<u8>>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Archive the directory `dir`, leaving out hidden files and those not `included`. The archive
+/// is written on a blocking thread as the stream is consumed, entries are placed in a directory
+/// named `name`.
+pub fn stream<F>(dir: PathBuf, name: String, format: ArchiveFormat, included: F) -> ArchiveStream
+where
+    F: Fn(&Path) -> bool + Send + 'static,
+{
+    //a couple of chunks in flight is enough to keep the connection busy
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let failed = tx.clone();
+        let writer = BufWriter::with_capacity(CHUNK_SIZE, ChannelWriter(tx));
+        let written = match format {
+            ArchiveFormat::Zip => write_zip(&dir, &name, &included, writer),
+            ArchiveFormat::Tar => {
+                write_tar(&dir, &name, &included, writer).and_then(|mut w| w.flush())
+            }
+            ArchiveFormat::TarGz => write_tar(
+                &dir,
+                &name,
+                &included,
+                flate2::write::GzEncoder::new(writer, flate2::Compression::fast()),
+            )
+            .and_then(|w| w.finish())
+            .and_then(|mut w| w.flush()),
+        };
+        //a broken pipe just means the client went away
+        if let Err(e) = written
+            && e.kind() != io::ErrorKind::BrokenPipe
+        {
+            eprintln!("failed to archive {}: {e}", dir.display());
+            let _ = failed.blocking_send(Err(e));
+        }
+    });
+    ArchiveStream {
+        rx,
+        chunk: Vec::new(),
+        pos: 0,
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    //relative to the archive root, with `/` separators
+    name: String,
+    dir: bool,
+}
+
+//the visible contents of `dir`, parents before their children. Symlinked directories aren't
+//descended into so that a link cycle can't produce an endless archive.
+fn entries(
+    dir: &Path,
+    prefix: &str,
+    included: &dyn Fn(&Path) -> bool,
+    out: &mut Vec<Entry>,
+) -> io::Result<()> {
+    let mut children: Vec<_> = std::fs::read_dir(dir)?.flatten().collect();
+    children.sort_by_key(|e| e.file_name());
+    for child in children {
+        let Some(name) = child.file_name().to_str().map(|n| n.to_string()) else {
+            continue;
+        };
+        if filelist::is_hidden(&name) {
+            continue;
+        }
+        let path = child.path();
+        if !included(&path) {
+            continue;
+        }
+        let name = format!("{prefix}/{name}");
+        if child.file_type()?.is_dir() {
+            out.push(Entry {
+                path: path.clone(),
+                name: name.clone(),
+                dir: true,
+            });
+            entries(&path, &name, included, out)?;
+        } else if path.is_file() {
+            out.push(Entry {
+                path,
+                name,
+                dir: false,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn write_tar<W: Write>(
+    dir: &Path,
+    name: &str,
+    included: &dyn Fn(&Path) -> bool,
+    writer: W,
+) -> io::Result<W> {
+    let mut entries_list = Vec::new();
+    entries(dir, name, included, &mut entries_list)?;
+
+    let mut builder = tar::Builder::new(writer);
+    builder.append_dir(name, dir)?;
+    for entry in entries_list {
+        if entry.dir {
+            builder.append_dir(&entry.name, &entry.path)?;
+        } else {
+            builder.append_path_with_name(&entry.path, &entry.name)?;
+        }
+    }
+    builder.into_inner()
+}
+
+fn write_zip<W: Write>(
+    dir: &Path,
+    name: &str,
+    included: &dyn Fn(&Path) -> bool,
+    writer: W,
+) -> io::Result<()> {
+    use zip::{CompressionMethod, DateTime, ZipWriter, write::SimpleFileOptions};
+
+    let mut entries_list = Vec::new();
+    entries(dir, name, included, &mut entries_list)?;
+
+    let options = |path: &Path| -> io::Result<SimpleFileOptions> {
+        let metadata = path.metadata()?;
+        let mut options = SimpleFileOptions::default()
+            //most datafiles are audio, which doesn't compress well
+            .compression_method(CompressionMethod::Stored)
+            .large_file(metadata.len() >= u32::MAX as u64);
+        if let Ok(modified) = metadata.modified() {
+            let t = OffsetDateTime::from(modified);
+            if let Ok(t) = DateTime::from_date_and_time(
+                t.year().try_into().unwrap_or_default(),
+                t.month().into(),
+                t.day(),
+                t.hour(),
+                t.minute(),
+                t.second(),
+            ) {
+                options = options.last_modified_time(t);
+            }
+        }
+        Ok(options)
+    };
+
+    let mut zip = ZipWriter::new_stream(writer);
+    zip.add_directory(name, options(dir)?)?;
+    for entry in entries_list {
+        if entry.dir {
+            zip.add_directory(&entry.name, options(&entry.path)?)?;
+        } else {
+            zip.start_file(&entry.name, options(&entry.path)?)?;
+            io::copy(&mut File::open(&entry.path)?, &mut zip)?;
+        }
+    }
+    zip.finish()?.into_inner().flush()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum EntryStatus {
+    Extracted,
+    //hidden files and directories aren't extracted, like they aren't listed
+    Skipped,
+    Rejected,
+    //a file of that name already exists and is left as it is
+    Conflict,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ExtractedEntry {
+    //as named in the archive
+    pub name: String,
+    pub status: EntryStatus,
+    //bytes written, for extracted files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ExtractReport {
+    pub entries: Vec<ExtractedEntry>,
+    //total bytes written
+    pub size: u64,
+    //set when the size limit stopped the extraction
+    pub truncated: bool,
+    //set when the free space fell below the reserve and stopped the extraction
+    pub disk_full: bool,
+}
+
+impl ExtractReport {
+    pub fn is_ok(&self) -> bool {
+        !self.truncated
+            && !self.disk_full
+            && self
+                .entries
+                .iter()
+                .all(|e| matches!(e.status, EntryStatus::Extracted | EntryStatus::Skipped))
+    }
+
+    /// Whether entries were only left out because files of their name already exist.
+    pub fn only_conflicts(&self) -> bool {
+        !self.truncated
+            && !self.disk_full
+            && self
+                .entries
+                .iter()
+                .all(|e| e.status != EntryStatus::Rejected)
+    }
+
+    fn push<N: Into<String>>(&mut self, name: N, status: EntryStatus) -> &mut ExtractedEntry {
+        self.entries.push(ExtractedEntry {
+            name: name.into(),
+            status,
+            size: None,
+            error: None,
+        });
+        self.entries.last_mut().expect("to get entry")
+    }
+
+    fn reject<N: Into<String>, E: ToString>(&mut self, name: N, error: E) {
+        self.push(name, EntryStatus::Rejected).error = Some(error.to_string());
+    }
+}
+
+//where an entry ends up relative to the destination, refusing anything that could escape it
+fn entry_path(name: &str) -> Result<Option<PathBuf>, &'static str> {
+    let mut path = PathBuf::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(c) => {
+                if c.to_str().is_some_and(filelist::is_hidden) {
+                    return Ok(None);
+                }
+                path.push(c)
+            }
+            Component::CurDir => {}
+            Component::ParentDir => return Err("path contains `..`"),
+            Component::RootDir | Component::Prefix(_) => return Err("path is absolute"),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(path))
+    }
+}
+
+/// Unpack the archive at `archive` into the directory `dest`, writing at most `limit` bytes.
+/// Entries that would end up outside of `dest`, links, special files and entries that `check`
+/// refuses are rejected, files that already exist are left as they are. `check` is given the path
+/// relative to `dest` and whether it is a directory.
+pub fn extract<F: Fn(&Path, bool) -> Result<(), &'static str>>(
+    archive: &Path,
+    format: ArchiveFormat,
+    dest: &Path,
+    limit: u64,
+    reserve: u64,
+    check: F,
+) -> io::Result<ExtractReport> {
+    let mut extractor = Extractor {
+        dest,
+        limit,
+        reserve,
+        check: &check,
+        report: ExtractReport::default(),
+    };
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i)?;
+                let name = entry.name()?.to_string();
+                let kind = if entry.is_dir() {
+                    EntryKind::Dir
+                } else if entry.is_file() {
+                    EntryKind::File
+                } else {
+                    EntryKind::Other
+                };
+                if !extractor.entry(&name, kind, &mut entry)? {
+                    break;
+                }
+            }
+        }
+        ArchiveFormat::Tar => {
+            extract_tar(tar::Archive::new(File::open(archive)?), &mut extractor)?;
+        }
+        ArchiveFormat::TarGz => {
+            let file = flate2::read::GzDecoder::new(File::open(archive)?);
+            extract_tar(tar::Archive::new(file), &mut extractor)?;
+        }
+    }
+    Ok(extractor.report)
+}
+
+fn extract_tar<R: Read>(mut archive: tar::Archive<R>, extractor: &mut Extractor) -> io::Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let kind = match entry.header().entry_type() {
+            tar::EntryType::Directory => EntryKind::Dir,
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+            //pax and gnu headers are handled by the tar crate
+            _ => EntryKind::Other,
+        };
+        if !extractor.entry(&name, kind, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+enum EntryKind {
+    File,
+    Dir,
+    Other,
+}
+
+struct Extractor<'a> {
+    dest: &'a Path,
+    limit: u64,
+    //the free space to leave on the filesystem
+    reserve: u64,
+    //whether an entry may be stored at the given path, and why not
+    check: &'a dyn Fn(&Path, bool) -> Result<(), &'static str>,
+    report: ExtractReport,
+}
+
+impl Extractor<'_> {
+    //extract one entry, false once the size limit has been reached
+    fn entry<R: Read>(&mut self, name: &str, kind: EntryKind, data: &mut R) -> io::Result<bool> {
+        let path = match entry_path(name) {
+            Ok(Some(path)) => path,
+            Ok(None) => {
+                self.report.push(name, EntryStatus::Skipped);
+                return Ok(true);
+            }
+            Err(e) => {
+                self.report.reject(name, e);
+                return Ok(true);
+            }
+        };
+        let path = match (self.check)(&path, matches!(kind, EntryKind::Dir)) {
+            Ok(()) => self.dest.join(path),
+            Err(e) => {
+                self.report.reject(name, e);
+                return Ok(true);
+            }
+        };
+        match kind {
+            EntryKind::Dir => {
+                if let Err(e) = std::fs::create_dir_all(&path) {
+                    self.report.reject(name, e);
+                } else {
+                    self.report.push(name, EntryStatus::Extracted);
+                }
+            }
+            EntryKind::Other => self
+                .report
+                .reject(name, "only files and directories are supported"),
+            EntryKind::File => {
+                let remaining = self.limit.saturating_sub(self.report.size);
+                let parent = path.parent().expect("to get parent path");
+                //files that are already there are never written over
+                let created = std::fs::create_dir_all(parent)
+                    .and_then(|_| File::options().write(true).create_new(true).open(&path));
+                if let Err(e) = &created
+                    && e.kind() == io::ErrorKind::AlreadyExists
+                {
+                    self.report.push(name, EntryStatus::Conflict).error =
+                        Some("a file of that name already exists".to_string());
+                    return Ok(true);
+                }
+                let written = created.and_then(|file| {
+                    let mut file = Reserved::new(file, parent, self.reserve);
+                    //read one byte more than allowed to tell if the entry fits
+                    io::copy(&mut data.take(remaining + 1), &mut file)
+                });
+                match written {
+                    Err(e) if e.kind() == io::ErrorKind::StorageFull => {
+                        let _ = std::fs::remove_file(&path);
+                        self.report.reject(name, "not enough free space");
+                        self.report.disk_full = true;
+                        return Ok(false);
+                    }
+                    Ok(n) if n > remaining => {
+                        let _ = std::fs::remove_file(&path);
+                        self.report.reject(name, "size limit exceeded");
+                        self.report.truncated = true;
+                        return Ok(false);
+                    }
+                    Ok(n) => {
+                        self.report.size += n;
+                        self.report.push(name, EntryStatus::Extracted).size = Some(n);
+                    }
+                    Err(e) => {
+                        let _ = std::fs::remove_file(&path);
+                        self.report.reject(name, e);
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+//a file that refuses writes once the filesystem has less than `reserve` free
+struct Reserved<'a> {
+    file: File,
+    dir: &'a Path,
+    reserve: u64,
+    //bytes until the free space is checked again
+    until_check: u64,
+}
+
+impl<'a> Reserved<'a> {
+    fn new(file: File, dir: &'a Path, reserve: u64) -> Self {
+        Self {
+            file,
+            dir,
+            reserve,
+            until_check: 0,
+        }
+    }
+}
+
+impl Write for Reserved<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.until_check == 0 {
+            if Space::of(self.dir).is_some_and(|space| space.free < self.reserve) {
+                return Err(io::ErrorKind::StorageFull.into());
+            }
+            self.until_check = SPACE_CHECK_INTERVAL;
+        }
+        let n = self.file.write(buf)?;
+        self.until_check = self.until_check.saturating_sub(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
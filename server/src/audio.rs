@@ -0,0 +1,132 @@
+use {
+    rocket::serde::{Deserialize, Serialize},
+    std::{fs::File, path::Path},
+    symphonia::core::{
+        codecs::audio::AudioDecoderOptions,
+        errors::Error,
+        formats::{FormatOptions, FormatReader, TrackType, probe::Hint},
+        io::MediaSourceStream,
+        meta::MetadataOptions,
+    },
+};
+
+//extensions of the audio files the runner can load into buffers
+const AUDIO_EXTENSIONS: &[&str] = &["wav", "wave", "aif", "aiff", "aifc", "flac"];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct AudioInfo {
+    //container format, "wave", "aiff" or "flac"
+    pub format: String,
+    pub sample_rate: u32,
+    pub channels: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bit_depth: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frames: Option<u64>,
+    //in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+}
+
+/// Whether `path` names an audio file, judging by its extension.
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn open(path: &Path) -> Result<Box<dyn FormatReader>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    symphonia::default::get_probe()
+        .probe(
+            &hint,
+            mss,
+            FormatOptions::default(),
+            MetadataOptions::default(),
+        )
+        .map_err(|e| e.to_string())
+}
+
+/// Read the header of the audio file at `path`.
+pub fn probe(path: &Path) -> Result<AudioInfo, String> {
+    let format = open(path)?;
+    let track = format
+        .default_track(TrackType::Audio)
+        .ok_or("no audio track")?;
+    let params = track
+        .codec_params
+        .as_ref()
+        .and_then(|p| p.audio())
+        .ok_or("unknown codec")?;
+    let sample_rate = params.sample_rate.ok_or("unknown sample rate")?;
+    let channels = params
+        .channels
+        .as_ref()
+        .map(|c| c.count())
+        .ok_or("unknown channel count")?;
+    let frames = track.num_frames;
+    Ok(AudioInfo {
+        format: format.format_info().short_name.to_string(),
+        sample_rate,
+        channels,
+        bit_depth: params.bits_per_sample.or(params.bits_per_coded_sample),
+        frames,
+        duration: frames.map(|f| f as f64 / sample_rate as f64),
+    })
+}
+
+/// Decode the audio file at `path`, handing blocks of interleaved samples to `f`,
+/// which can stop the decoding by returning an error.
+/// Returns the sample rate and channel count.
+pub fn decode<F: FnMut(&[f32]) -> Result<(), String>>(
+    path: &Path,
+    mut f: F,
+) -> Result<(u32, usize), String> {
+    let mut format = open(path)?;
+    let track = format
+        .default_track(TrackType::Audio)
+        .ok_or("no audio track")?;
+    let track_id = track.id;
+    let params = track
+        .codec_params
+        .as_ref()
+        .and_then(|p| p.audio())
+        .ok_or("unknown codec")?
+        .clone();
+    let mut decoder = symphonia::default::get_codecs()
+        .make_audio_decoder(&params, &AudioDecoderOptions::default())
+        .map_err(|e| e.to_string())?;
+
+    let mut spec = params
+        .sample_rate
+        .zip(params.channels.as_ref().map(|c| c.count()));
+    let mut samples: Vec<f32> = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(e) => return Err(e.to_string()),
+        };
+        if packet.track_id != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let decoded_spec = decoded.spec();
+                spec = Some((decoded_spec.rate(), decoded_spec.channels().count()));
+                decoded.copy_to_vec_interleaved(&mut samples);
+                f(&samples)?;
+            }
+            //skip over corrupt packets like a player would
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    spec.ok_or_else(|| "unknown sample rate or channel count".to_string())
+}
@@ -0,0 +1,236 @@
+use {
+    crate::filelist::is_hidden,
+    rocket::serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fs, io,
+        path::{Path, PathBuf},
+        time::SystemTime,
+    },
+};
+
+/// The runner's caches, by filetype name.
+pub const CACHE_FILETYPES: [&str; 2] = ["compile_cache", "source_cache"];
+
+/// The files the runner keeps for one patcher, grouped by their name.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct CacheEntry {
+    pub patcher: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rnbo_version: Option<String>,
+    //relative to the cache directory
+    pub files: Vec<String>,
+    //in bytes
+    pub size: u64,
+    //RFC 3339, the last time one of the files was read or written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<String>,
+    //whether the runner still knows the patcher, when it could be asked and the patcher is
+    //known for certain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_use: Option<bool>,
+    //the name the files share
+    #[serde(skip)]
+    name: String,
+    //whether the patcher comes from a description rather than the file name
+    #[serde(skip)]
+    described: bool,
+}
+
+/// What a garbage collection of the caches removed, or would remove.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub removed: BTreeMap<String, Vec<CacheEntry>>,
+    //in bytes
+    pub freed: u64,
+}
+
+//the parts of an exported patcher description we care about
+#[derive(Deserialize, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct Description {
+    meta: DescriptionMeta,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct DescriptionMeta {
+    name: Option<String>,
+    rnboversion: Option<String>,
+}
+
+//the name files of an entry share, `libsynth.so`, `synth.cpp` and `synth.json` all belong to `synth`
+fn entry_name(file_name: &str) -> &str {
+    let (stem, ext) = file_name.split_once('.').unwrap_or((file_name, ""));
+    match ext {
+        "so" | "dylib" => stem.strip_prefix("lib").unwrap_or(stem),
+        _ => stem,
+    }
+}
+
+//the runner keeps files for different RNBO versions in directories named after them
+fn version_dir(dir: &Path, root: &Path) -> Option<String> {
+    dir.strip_prefix(root)
+        .ok()?
+        .components()
+        .filter_map(|c| c.as_os_str().to_str())
+        .rfind(|name| name.starts_with(|c: char| c.is_ascii_digit()) && name.contains('.'))
+        .map(str::to_string)
+}
+
+#[derive(Default)]
+struct Group {
+    files: Vec<PathBuf>,
+    size: u64,
+    last_used: Option<SystemTime>,
+    description: Option<Description>,
+}
+
+/// Group the files in a cache directory into entries. This blocks.
+pub fn entries(root: &Path) -> Vec<CacheEntry> {
+    let mut groups: BTreeMap<(PathBuf, String), Group> = BTreeMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if is_hidden(&name) {
+                continue;
+            }
+            let path = entry.path();
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let group = groups
+                .entry((dir.clone(), entry_name(&name).to_string()))
+                .or_default();
+            group.size += metadata.len();
+            //atime may not be kept up to date, so the later of the two is taken
+            let used = [metadata.accessed(), metadata.modified()]
+                .into_iter()
+                .flatten()
+                .max();
+            group.last_used = group.last_used.max(used);
+            if name.ends_with(".json") {
+                group.description = fs::read(&path)
+                    .ok()
+                    .and_then(|d| serde_json::from_slice(&d).ok());
+            }
+            group.files.push(path);
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|((dir, name), mut group)| {
+            group.files.sort();
+            let meta = group.description.map(|d| d.meta).unwrap_or_default();
+            CacheEntry {
+                described: meta.name.is_some(),
+                patcher: meta.name.unwrap_or_else(|| name.clone()),
+                name,
+                rnbo_version: meta.rnboversion.or_else(|| version_dir(&dir, root)),
+                files: group
+                    .files
+                    .iter()
+                    .filter_map(|f| f.strip_prefix(root).ok())
+                    .map(|f| f.to_string_lossy().to_string())
+                    .collect(),
+                size: group.size,
+                last_used: group
+                    .last_used
+                    .map(|t| humantime::format_rfc3339_seconds(t).to_string()),
+                in_use: None,
+            }
+        })
+        .collect()
+}
+
+/// The entries of each of the named cache directories. A compiled patcher has no description
+/// of its own, so it takes the one of the sources with the same name. This blocks.
+pub fn all_entries(dirs: &[(String, PathBuf)]) -> BTreeMap<String, Vec<CacheEntry>> {
+    let mut caches: BTreeMap<_, _> = dirs
+        .iter()
+        .map(|(name, path)| (name.clone(), entries(path)))
+        .collect();
+    //names that more than one description claims are left alone
+    let mut described: HashMap<String, Option<CacheEntry>> = HashMap::new();
+    for entry in caches.values().flatten().filter(|e| e.described) {
+        described
+            .entry(entry.name.clone())
+            .and_modify(|d| {
+                if d.as_ref().is_some_and(|d| d.patcher != entry.patcher) {
+                    *d = None;
+                }
+            })
+            .or_insert_with(|| Some(entry.clone()));
+    }
+    for entry in caches.values_mut().flatten().filter(|e| !e.described) {
+        if let Some(Some(description)) = described.get(&entry.name) {
+            entry.patcher = description.patcher.clone();
+            entry.described = true;
+            if entry.rnbo_version.is_none() {
+                entry.rnbo_version = description.rnbo_version.clone();
+            }
+        }
+    }
+    caches
+}
+
+/// Mark the entries whose patcher the runner knows. An entry only counts as unused when a
+/// description names its patcher and neither that nor the file name is among `patchers`,
+/// the ones that can't be told are left unmarked.
+pub fn mark_in_use(entries: &mut [CacheEntry], patchers: &HashSet<String>) {
+    for entry in entries {
+        entry.in_use = if patchers.contains(&entry.patcher) || patchers.contains(&entry.name) {
+            Some(true)
+        } else if entry.described {
+            Some(false)
+        } else {
+            None
+        };
+    }
+}
+
+/// Find the entries of patchers that aren't among `patchers` and remove them, unless this is a
+/// dry run. This blocks.
+pub fn gc(dirs: &[(String, PathBuf)], patchers: &HashSet<String>, dry_run: bool) -> GcReport {
+    let mut removed = all_entries(dirs);
+    for entries in removed.values_mut() {
+        mark_in_use(entries, patchers);
+        entries.retain(|entry| entry.in_use == Some(false));
+    }
+    if !dry_run {
+        for (name, root) in dirs {
+            for entry in removed.get(name).into_iter().flatten() {
+                if let Err(e) = remove(root, entry) {
+                    eprintln!("failed to remove {} from {name}: {e}", entry.patcher);
+                }
+            }
+        }
+    }
+    GcReport {
+        dry_run,
+        freed: removed.values().flatten().map(|entry| entry.size).sum(),
+        removed,
+    }
+}
+
+/// Remove the files of an entry from the cache directory. This blocks.
+pub fn remove(root: &Path, entry: &CacheEntry) -> io::Result<()> {
+    for file in &entry.files {
+        match fs::remove_file(root.join(file)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
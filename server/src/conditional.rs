@@ -0,0 +1,340 @@
+use {
+    crate::{filelist, problem::Problem},
+    rocket::{
+        Request, Response,
+        http::{Method, Status},
+        request::{FromRequest, Outcome},
+        response::{self, Responder},
+    },
+    std::{
+        fs::Metadata,
+        io::{self, SeekFrom},
+        path::{Path, PathBuf},
+        pin::Pin,
+        task::{Context, Poll},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+    tokio::{
+        fs::File,
+        io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf, Take},
+    },
+};
+
+/// The ETag and Last-Modified of a file, derived from its size and modification time.
+#[derive(Clone)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    pub fn new(metadata: &Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Self {
+            etag: format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos()),
+            //http dates only have second resolution
+            last_modified: UNIX_EPOCH + Duration::from_secs(modified.as_secs()),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        std::fs::metadata(path).ok().map(|m| Self::new(&m))
+    }
+}
+
+fn is_weak(tag: &str) -> bool {
+    tag.starts_with("W/")
+}
+
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+//does the comma separated list of entity tags `list` match `etag`
+fn etag_matches(list: &str, etag: &str, weak: bool) -> bool {
+    list.split(',').map(str::trim).any(|tag| {
+        tag == "*" || (opaque(tag) == opaque(etag) && (weak || !(is_weak(tag) || is_weak(etag))))
+    })
+}
+
+/// The conditional and range headers of a request.
+pub struct Preconditions {
+    method: Method,
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<SystemTime>,
+    if_unmodified_since: Option<SystemTime>,
+    range: Option<String>,
+    if_range: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Preconditions {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = req.headers();
+        let header = |name: &str| headers.get_one(name).map(|v| v.to_string());
+        //invalid dates are ignored, as RFC 9110 asks
+        let date = |name: &str| {
+            headers
+                .get_one(name)
+                .and_then(|v| httpdate::parse_http_date(v).ok())
+        };
+        Outcome::Success(Self {
+            method: req.method(),
+            if_match: header("If-Match"),
+            if_none_match: header("If-None-Match"),
+            if_modified_since: date("If-Modified-Since"),
+            if_unmodified_since: date("If-Unmodified-Since"),
+            range: header("Range"),
+            if_range: header("If-Range"),
+        })
+    }
+}
+
+enum Evaluation {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+impl Preconditions {
+    //RFC 9110 section 13.2.2, `current` is None when the target doesn't exist
+    fn evaluate(&self, current: Option<&Validators>) -> Evaluation {
+        if let Some(if_match) = &self.if_match {
+            if !current.is_some_and(|v| etag_matches(if_match, &v.etag, false)) {
+                return Evaluation::Failed;
+            }
+        } else if let Some(since) = self.if_unmodified_since
+            && let Some(v) = current
+            && v.last_modified > since
+        {
+            return Evaluation::Failed;
+        }
+
+        let read = self.method == Method::Get || self.method == Method::Head;
+        if let Some(if_none_match) = &self.if_none_match {
+            if let Some(v) = current
+                && etag_matches(if_none_match, &v.etag, true)
+            {
+                return if read {
+                    Evaluation::NotModified
+                } else {
+                    Evaluation::Failed
+                };
+            }
+        } else if read
+            && let Some(since) = self.if_modified_since
+            && let Some(v) = current
+            && v.last_modified <= since
+        {
+            return Evaluation::NotModified;
+        }
+        Evaluation::Proceed
+    }
+
+    /// Check If-Match and friends before changing or deleting `path`.
+    pub fn check_write(&self, path: &Path) -> Result<(), Status> {
+        match self.evaluate(Validators::from_path(path).as_ref()) {
+            Evaluation::Proceed => Ok(()),
+            _ => Err(Status::PreconditionFailed),
+        }
+    }
+
+    //the inclusive byte range to respond with, None for the whole file
+    fn range(&self, validators: &Validators, len: u64) -> Result<Option<(u64, u64)>, ()> {
+        let Some(range) = &self.range else {
+            return Ok(None);
+        };
+        if let Some(if_range) = &self.if_range {
+            let current = if if_range.starts_with('"') || is_weak(if_range) {
+                !is_weak(if_range) && if_range.trim() == validators.etag
+            } else {
+                httpdate::parse_http_date(if_range).is_ok_and(|d| d == validators.last_modified)
+            };
+            if !current {
+                return Ok(None);
+            }
+        }
+        //only single ranges are supported, anything else gets the whole file
+        let Some(spec) = range.trim().strip_prefix("bytes=") else {
+            return Ok(None);
+        };
+        if spec.contains(',') {
+            return Ok(None);
+        }
+        let Some((start, end)) = spec.split_once('-') else {
+            return Ok(None);
+        };
+        let (start, end) = (start.trim(), end.trim());
+        let range = if start.is_empty() {
+            //suffix range, the last `end` bytes
+            match end.parse::<u64>() {
+                Ok(0) => return Err(()),
+                Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+                Err(_) => return Ok(None),
+            }
+        } else {
+            let Ok(start) = start.parse::<u64>() else {
+                return Ok(None);
+            };
+            let end = if end.is_empty() {
+                len.saturating_sub(1)
+            } else {
+                match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+                    _ => return Ok(None),
+                }
+            };
+            (start, end)
+        };
+        if len == 0 || range.0 >= len {
+            Err(())
+        } else {
+            Ok(Some(range))
+        }
+    }
+}
+
+enum Body {
+    Full(File),
+    Partial {
+        file: FileRange,
+        start: u64,
+        end: u64,
+    },
+    NotModified,
+    PreconditionFailed,
+    Unsatisfiable,
+}
+
+/// A file response that honors conditional and range requests.
+pub struct ConditionalFile {
+    path: PathBuf,
+    len: u64,
+    validators: Validators,
+    body: Body,
+}
+
+impl ConditionalFile {
+    pub async fn open<P: AsRef<Path>>(path: P, preconditions: &Preconditions) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::open(&path).await?;
+        let metadata = file.metadata().await?;
+        if !metadata.is_file() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let len = metadata.len();
+        let validators = Validators::new(&metadata);
+        let body = match preconditions.evaluate(Some(&validators)) {
+            Evaluation::NotModified => Body::NotModified,
+            Evaluation::Failed => Body::PreconditionFailed,
+            Evaluation::Proceed => match preconditions.range(&validators, len) {
+                Err(()) => Body::Unsatisfiable,
+                Ok(None) => Body::Full(file),
+                Ok(Some((start, end))) => {
+                    file.seek(SeekFrom::Start(start)).await?;
+                    Body::Partial {
+                        file: FileRange(file.take(end - start + 1)),
+                        start,
+                        end,
+                    }
+                }
+            },
+        };
+        Ok(Self {
+            path,
+            len,
+            validators,
+            body,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the response carries (part of) the file, rather than just a status.
+    pub fn has_body(&self) -> bool {
+        matches!(self.body, Body::Full(_) | Body::Partial { .. })
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ConditionalFile {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        //refusals are errors like any other, described by a problem rather than the file
+        match self.body {
+            Body::PreconditionFailed => {
+                let mut response = Problem::new(Status::PreconditionFailed).respond_to(req)?;
+                response.set_raw_header("ETag", self.validators.etag);
+                return Ok(response);
+            }
+            Body::Unsatisfiable => {
+                let mut response = Problem::new(Status::RangeNotSatisfiable)
+                    .detail(format!("the file is {} bytes long", self.len))
+                    .respond_to(req)?;
+                response.set_raw_header("Content-Range", format!("bytes */{}", self.len));
+                return Ok(response);
+            }
+            _ => (),
+        }
+        let mut response = Response::build();
+        response
+            .raw_header("ETag", self.validators.etag)
+            .raw_header(
+                "Last-Modified",
+                httpdate::fmt_http_date(self.validators.last_modified),
+            )
+            .raw_header("Accept-Ranges", "bytes");
+        match self.body {
+            Body::Full(file) => {
+                if let Some(content_type) = filelist::mime_type(&self.path) {
+                    response.header(content_type);
+                }
+                response.sized_body(self.len as usize, file);
+            }
+            Body::Partial { file, start, end } => {
+                if let Some(content_type) = filelist::mime_type(&self.path) {
+                    response.header(content_type);
+                }
+                response
+                    .status(Status::PartialContent)
+                    .raw_header("Content-Range", format!("bytes {start}-{end}/{}", self.len))
+                    .sized_body((end - start + 1) as usize, file);
+            }
+            Body::NotModified => {
+                response.status(Status::NotModified);
+            }
+            Body::PreconditionFailed | Body::Unsatisfiable => unreachable!("answered above"),
+        }
+        response.ok()
+    }
+}
+
+//a sized body has to be seekable, but rocket only seeks to find the size which we always give
+struct FileRange(Take<File>);
+
+impl AsyncRead for FileRange {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for FileRange {
+    fn start_seek(self: Pin<&mut Self>, _: SeekFrom) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Err(io::ErrorKind::Unsupported.into()))
+    }
+}
@@ -1,19 +1,64 @@
 use {
-    serde::Deserialize,
+    crate::{
+        paths::{Sandbox, SymlinkPolicy},
+        trash::TrashLimits,
+    },
+    rocket::http::Status,
+    serde::{Deserialize, Serialize},
     std::{
-        collections::{HashMap, HashSet},
+        collections::HashMap,
         fs::File,
-        io::BufReader,
-        path::PathBuf,
+        io::{self, BufReader},
+        path::{Path, PathBuf},
+        time::Duration,
     },
 };
 
+//the first path segments of the /files routes that aren't about a filetype, a filetype named
+//like one of them couldn't be reached
+const RESERVED_FILETYPES: [&str; 8] = [
+    "uploads", "trash", "move", "copy", "mkdir", "process", "events", "usage",
+];
+
 pub struct Config {
-    pub filetype_paths: HashMap<String, PathBuf>,
-    pub deleteable_filetypes: HashSet<String>,
+    filetypes: HashMap<String, Filetype>,
+    symlinks: SymlinkPolicy,
     pub _package_dir: Option<PathBuf>,
 }
 
+/// What a route wants to do with the files of a filetype.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    Delete,
+}
+
+/// A directory served under `/files` and what may be done with the files in it.
+#[derive(Clone, Debug)]
+pub struct Filetype {
+    pub path: PathBuf,
+    pub readable: bool,
+    pub writable: bool,
+    pub deletable: bool,
+    //lowercase and without the dot, None allows any extension
+    pub extensions: Option<Vec<String>>,
+    //in bytes
+    pub max_upload_size: Option<u64>,
+}
+
+//a filetype as declared in runner.json, for the built in filetypes only the given fields
+//replace the defaults
+#[derive(Deserialize, Default, Clone)]
+struct FiletypeEntry {
+    path: Option<PathBuf>,
+    readable: Option<bool>,
+    writable: Option<bool>,
+    deletable: Option<bool>,
+    extensions: Option<Vec<String>>,
+    max_upload_size: Option<u64>,
+}
+
 #[derive(Deserialize, Default)]
 pub struct RunnerConfig {
     backup_dir: Option<PathBuf>,
@@ -21,12 +66,84 @@ pub struct RunnerConfig {
     compile_cache_dir: Option<PathBuf>,
     package_dir: Option<PathBuf>,
     source_cache_dir: Option<PathBuf>,
+    #[serde(default)]
+    filetypes: HashMap<String, FiletypeEntry>,
     //pub save_dir: Option<PathBuf>,
 
     //file path
     //pub db_path: Option<PathBuf>,
 }
 
+/// Settings for the panel itself, read from the rocket figment built in main, so they can come
+/// from the panel config file, `RNBO_PANEL_` environment variables or command line flags.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct PanelConfig {
+    pub runner_host: String,
+    pub runner_port: u16,
+    /// milliseconds to wait for the runner to connect or answer a query
+    pub runner_timeout: u64,
+    /// seconds a package job waits for the runner to create the package
+    pub package_timeout: u64,
+    /// mebibytes an uploaded archive may unpack to
+    pub extract_limit: u64,
+    /// how symbolic links inside the filetype directories are treated
+    pub symlinks: SymlinkPolicy,
+    /// days deleted files are kept in the trash
+    pub trash_max_age: u64,
+    /// mebibytes of deleted files kept in the trash of each filetype
+    pub trash_max_size: u64,
+    /// mebibytes that uploads must leave free on the filesystem
+    pub disk_reserve: u64,
+}
+
+impl Default for PanelConfig {
+    fn default() -> Self {
+        Self {
+            runner_host: "127.0.0.1".to_string(),
+            runner_port: 5678,
+            runner_timeout: 2_000,
+            package_timeout: 15 * 60,
+            extract_limit: 1024,
+            symlinks: SymlinkPolicy::default(),
+            trash_max_age: 30,
+            trash_max_size: 1024,
+            disk_reserve: 256,
+        }
+    }
+}
+
+impl PanelConfig {
+    pub fn runner_url(&self) -> String {
+        format!("http://{}:{}", self.runner_host, self.runner_port)
+    }
+
+    pub fn runner_timeout(&self) -> Duration {
+        Duration::from_millis(self.runner_timeout)
+    }
+
+    pub fn package_timeout(&self) -> Duration {
+        Duration::from_secs(self.package_timeout)
+    }
+
+    /// The most bytes an uploaded archive may unpack to.
+    pub fn extract_limit(&self) -> u64 {
+        self.extract_limit.saturating_mul(1024 * 1024)
+    }
+
+    /// The bytes uploads must leave free.
+    pub fn disk_reserve(&self) -> u64 {
+        self.disk_reserve.saturating_mul(1024 * 1024)
+    }
+
+    pub fn trash_limits(&self) -> TrashLimits {
+        TrashLimits {
+            max_age: Duration::from_secs(self.trash_max_age.saturating_mul(24 * 60 * 60)),
+            max_size: self.trash_max_size.saturating_mul(1024 * 1024),
+        }
+    }
+}
+
 fn rnbodir() -> PathBuf {
     home::home_dir()
         .expect("to get home directory")
@@ -35,17 +152,14 @@ fn rnbodir() -> PathBuf {
 }
 
 impl RunnerConfig {
-    pub fn read_or_default(config_path: &PathBuf) -> Self {
-        if std::path::Path::exists(config_path) {
-            if let Ok(file) = File::open(config_path) {
-                let reader = BufReader::new(file);
-                serde_json::from_reader(reader).unwrap_or_default()
-            } else {
-                Self::default()
-            }
-        } else {
-            Self::default()
+    /// Read the runner configuration, the defaults if there is none. A file that can't be read
+    /// or parsed is an error rather than the defaults, which would serve other directories.
+    pub fn read_or_default(config_path: &Path) -> io::Result<Self> {
+        if !config_path.exists() {
+            return Ok(Self::default());
         }
+        let reader = BufReader::new(File::open(config_path)?);
+        Ok(serde_json::from_reader(reader)?)
     }
 
     pub fn backup_dir(&self) -> PathBuf {
@@ -73,17 +187,125 @@ impl RunnerConfig {
             .clone()
             .unwrap_or_else(|| rnbodir().join("packages"))
     }
+
+    /// The built in filetypes along with the ones declared under `filetypes`.
+    pub fn filetypes(&self) -> HashMap<String, Filetype> {
+        let mut filetypes = HashMap::from([
+            (
+                "datafiles".to_string(),
+                Filetype::new(self.datafile_dir(), true),
+            ),
+            (
+                "packages".to_string(),
+                Filetype::new(self.package_dir(), true),
+            ),
+            (
+                "backup".to_string(),
+                Filetype::new(self.backup_dir(), false),
+            ),
+            (
+                "compile_cache".to_string(),
+                Filetype::new(self.compile_cache_dir(), false),
+            ),
+            (
+                "source_cache".to_string(),
+                Filetype::new(self.source_cache_dir(), false),
+            ),
+        ]);
+        for (name, entry) in &self.filetypes {
+            if RESERVED_FILETYPES.contains(&name.as_str()) {
+                eprintln!("ignoring filetype {name}, the name is used by the /files routes");
+                continue;
+            }
+            let filetype = match (filetypes.get(name), &entry.path) {
+                (Some(builtin), _) => builtin.clone(),
+                (None, Some(path)) => Filetype::new(path.clone(), false),
+                (None, None) => {
+                    eprintln!("ignoring filetype {name}, it needs a path");
+                    continue;
+                }
+            };
+            filetypes.insert(name.clone(), entry.apply(filetype));
+        }
+        filetypes
+    }
+}
+
+impl FiletypeEntry {
+    fn apply(&self, filetype: Filetype) -> Filetype {
+        Filetype {
+            path: self.path.clone().unwrap_or(filetype.path),
+            readable: self.readable.unwrap_or(filetype.readable),
+            writable: self.writable.unwrap_or(filetype.writable),
+            deletable: self.deletable.unwrap_or(filetype.deletable),
+            extensions: self
+                .extensions
+                .as_ref()
+                .map(|e| {
+                    e.iter()
+                        .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
+                        .collect()
+                })
+                .or(filetype.extensions),
+            max_upload_size: self.max_upload_size.or(filetype.max_upload_size),
+        }
+    }
+}
+
+impl Filetype {
+    /// A readable filetype that is either managed through the panel or left alone.
+    pub fn new(path: PathBuf, managed: bool) -> Self {
+        Self {
+            path,
+            readable: true,
+            writable: managed,
+            deletable: managed,
+            extensions: None,
+            max_upload_size: None,
+        }
+    }
+
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.readable,
+            Access::Write => self.writable,
+            Access::Delete => self.deletable,
+        }
+    }
+
+    /// Whether a file named like `path` may be stored.
+    pub fn allows_extension(&self, path: &Path) -> bool {
+        self.extensions.as_ref().is_none_or(|extensions| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| extensions.contains(&e.to_ascii_lowercase()))
+        })
+    }
+
+    /// Check a file about to be stored at `path`, `size` is the number of bytes if known.
+    pub fn check_upload(&self, path: &Path, size: Option<u64>) -> Result<(), Status> {
+        if !self.allows_extension(path) {
+            eprintln!("{} has an extension that isn't allowed", path.display());
+            return Err(Status::UnsupportedMediaType);
+        }
+        if let (Some(max), Some(size)) = (self.max_upload_size, size)
+            && size > max
+        {
+            return Err(Status::PayloadTooLarge);
+        }
+        Ok(())
+    }
 }
 
 impl Config {
     pub fn new(
-        filetype_paths: HashMap<String, PathBuf>,
-        deleteable_filetypes: HashSet<String>,
+        filetypes: HashMap<String, Filetype>,
+        symlinks: SymlinkPolicy,
         _package_dir: Option<PathBuf>,
     ) -> Self {
         Self {
-            filetype_paths,
-            deleteable_filetypes,
+            filetypes,
+            symlinks,
             _package_dir,
         }
     }
@@ -94,19 +316,42 @@ impl Config {
     }
     */
 
+    /// The filetypes that can be listed.
     pub fn filetypelist(&self) -> Vec<String> {
-        self.filetype_paths.keys().map(|k| k.to_string()).collect()
+        self.filetypes
+            .iter()
+            .filter(|(_, f)| f.readable)
+            .map(|(k, _)| k.to_string())
+            .collect()
     }
 
-    pub fn filetype_path(&self, filetype: &str) -> Option<&PathBuf> {
-        self.filetype_paths.get(filetype)
+    /// The directories of the filetypes that can be listed.
+    pub fn filetype_paths(&self) -> HashMap<String, PathBuf> {
+        self.filetypes
+            .iter()
+            .filter(|(_, f)| f.readable)
+            .map(|(k, f)| (k.clone(), f.path.clone()))
+            .collect()
     }
 
-    pub fn deleteable_filetype_path(&self, filetype: &str) -> Option<&PathBuf> {
-        if self.deleteable_filetypes.contains(filetype) {
-            self.filetype_paths.get(filetype)
-        } else {
-            None
+    /// The directories of all filetypes.
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.filetypes.values().map(|f| &f.path)
+    }
+
+    /// Look up `filetype` for `access`. Unknown filetypes are not found when reading and a bad
+    /// request otherwise, filetypes that don't allow `access` are unauthorized.
+    pub fn filetype(&self, filetype: &str, access: Access) -> Result<&Filetype, Status> {
+        match self.filetypes.get(filetype) {
+            Some(f) if f.allows(access) => Ok(f),
+            Some(_) => Err(Status::Unauthorized),
+            None if access == Access::Read => Err(Status::NotFound),
+            None => Err(Status::BadRequest),
         }
     }
+
+    /// Resolves paths inside the directory of `filetype` according to the symlink policy.
+    pub fn sandbox(&self, filetype: &Filetype) -> Sandbox {
+        Sandbox::new(&filetype.path, self.symlinks)
+    }
 }
@@ -0,0 +1,89 @@
+use {
+    base64::{Engine, engine::general_purpose::STANDARD},
+    rocket::{
+        Request,
+        http::{Header, Status},
+        request::{FromRequest, Outcome},
+    },
+    sha2::{Digest, Sha256},
+    std::{fs::File, io, path::Path},
+};
+
+/// The sha-256 of a stored file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sha256Digest([u8; 32]);
+
+impl Sha256Digest {
+    pub fn hex(&self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// The digest as a `Content-Digest` header, RFC 9530.
+    pub fn header(&self) -> Header<'static> {
+        Header::new(
+            "Content-Digest",
+            format!("sha-256=:{}:", STANDARD.encode(self.0)),
+        )
+    }
+}
+
+/// The sha-256 a client says its upload has, from a `Content-Digest` header.
+/// Other algorithms are ignored, a sha-256 that can't be read is a bad request.
+pub struct ContentDigest(pub Option<Sha256Digest>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ContentDigest {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.headers().get_one("Content-Digest").map(parse) {
+            None => Outcome::Success(Self(None)),
+            Some(Ok(digest)) => Outcome::Success(Self(digest)),
+            Some(Err(e)) => Outcome::Error((Status::BadRequest, e)),
+        }
+    }
+}
+
+//a structured field dictionary of byte sequences, `sha-256=:<base64>:, sha-512=:...:`
+fn parse(value: &str) -> Result<Option<Sha256Digest>, &'static str> {
+    for member in value.split(',') {
+        let Some((algorithm, digest)) = member.trim().split_once('=') else {
+            return Err("malformed Content-Digest");
+        };
+        if !algorithm.eq_ignore_ascii_case("sha-256") {
+            continue;
+        }
+        let bytes = digest
+            .strip_prefix(':')
+            .and_then(|d| d.strip_suffix(':'))
+            .and_then(|d| STANDARD.decode(d).ok())
+            .ok_or("malformed sha-256 in Content-Digest")?;
+        return <[u8; 32]>::try_from(bytes)
+            .map(|b| Some(Sha256Digest(b)))
+            .map_err(|_| "sha-256 in Content-Digest has the wrong length");
+    }
+    Ok(None)
+}
+
+/// The sha-256 of the file at `path`.
+pub fn file(path: &Path) -> io::Result<Sha256Digest> {
+    hash(&mut File::open(path)?)
+}
+
+/// Flush a written file to disk and hash what ended up there.
+pub fn sync_file(path: &Path) -> io::Result<Sha256Digest> {
+    let mut file = File::open(path)?;
+    file.sync_all()?;
+    hash(&mut file)
+}
+
+fn hash(file: &mut File) -> io::Result<Sha256Digest> {
+    let mut hasher = Sha256::new();
+    io::copy(file, &mut hasher)?;
+    Ok(Sha256Digest(hasher.finalize().into()))
+}
+
+/// Flush a directory so that a rename into it survives a power cut.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()
+}
@@ -0,0 +1,233 @@
+//a stand-in for the runner for the tests, serving OSCQuery values over http and answering
+//`/rnbo/cmd` messages on a websocket at the same address
+use {
+    rosc::{OscMessage, OscPacket, OscType},
+    serde_json::{Value, json},
+    std::{
+        collections::{HashMap, HashSet},
+        io::{self, BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+        thread,
+        time::Duration,
+    },
+    tungstenite::Message,
+};
+
+//how often an open websocket checks whether it should be dropped
+const POLL: Duration = Duration::from_millis(10);
+
+type Handler = Box<dyn Fn(&Value) -> Vec<Value> + Send>;
+
+#[derive(Default)]
+struct State {
+    version: String,
+    patchers: HashSet<String>,
+    //how to answer each method, given its params
+    handlers: HashMap<String, Handler>,
+    //every command received, as method and params
+    commands: Vec<(String, Value)>,
+    //bumped to drop the open websockets
+    generation: usize,
+}
+
+pub struct FakeRunner {
+    port: u16,
+    state: Arc<Mutex<State>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl FakeRunner {
+    /// Listen on a free local port, reporting `version` as the RNBO version.
+    pub fn start(version: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("to bind fake runner");
+        let port = listener.local_addr().expect("to get address").port();
+        let state = Arc::new(Mutex::new(State {
+            version: version.to_string(),
+            ..Default::default()
+        }));
+        let connections = Arc::new(AtomicUsize::new(0));
+        {
+            let (state, connections) = (state.clone(), connections.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let (state, connections) = (state.clone(), connections.clone());
+                    thread::spawn(move || serve(stream, &state, &connections));
+                }
+            });
+        }
+        Self {
+            port,
+            state,
+            connections,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    pub fn set_version(&self, version: &str) {
+        self.state.lock().unwrap().version = version.to_string();
+    }
+
+    pub fn set_patchers(&self, patchers: &[&str]) {
+        self.state.lock().unwrap().patchers = patchers.iter().map(|p| p.to_string()).collect();
+    }
+
+    /// Answer `method` with the responses `handler` gives for its params, in order. Each is a
+    /// JSON-RPC response object, the id of the command is filled in unless it has one.
+    /// Methods without a handler are answered with a JSON-RPC error.
+    pub fn on<F: Fn(&Value) -> Vec<Value> + Send + 'static>(&self, method: &str, handler: F) {
+        self.state
+            .lock()
+            .unwrap()
+            .handlers
+            .insert(method.to_string(), Box::new(handler));
+    }
+
+    /// The method and params of every command received so far.
+    pub fn commands(&self) -> Vec<(String, Value)> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// How many websockets have been opened.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Close the open websockets, like a runner that restarts.
+    pub fn disconnect(&self) {
+        self.state.lock().unwrap().generation += 1;
+    }
+}
+
+fn serve(stream: TcpStream, state: &Mutex<State>, connections: &AtomicUsize) {
+    //look at the request head without taking it, the websocket handshake wants to read it
+    let mut head = [0u8; 4096];
+    let upgrade = loop {
+        let Ok(n) = stream.peek(&mut head) else {
+            return;
+        };
+        let text = String::from_utf8_lossy(&head[..n]).to_ascii_lowercase();
+        if n == 0 || text.contains("\r\n\r\n") {
+            break text.contains("upgrade: websocket");
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    let _ = if upgrade {
+        websocket(stream, state, connections)
+    } else {
+        http(stream, state)
+    };
+}
+
+fn http(stream: TcpStream, state: &Mutex<State>) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let path = line.split(' ').nth(1).unwrap_or_default().to_string();
+    //skip the rest of the head
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let body = {
+        let state = state.lock().unwrap();
+        match path.split('?').next() {
+            Some("/rnbo/info/version") => Some(json!({ "VALUE": state.version })),
+            Some("/rnbo/patchers") => {
+                let contents: serde_json::Map<String, Value> = state
+                    .patchers
+                    .iter()
+                    .map(|p| (p.clone(), json!({})))
+                    .collect();
+                Some(json!({ "CONTENTS": contents }))
+            }
+            _ => None,
+        }
+    };
+    let (status, body) = match body {
+        Some(body) => ("200 OK", body.to_string()),
+        None => ("404 Not Found", String::new()),
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn websocket(stream: TcpStream, state: &Mutex<State>, connections: &AtomicUsize) -> io::Result<()> {
+    let mut ws = tungstenite::accept(stream).map_err(io::Error::other)?;
+    ws.get_ref().set_read_timeout(Some(POLL))?;
+    let generation = state.lock().unwrap().generation;
+    connections.fetch_add(1, Ordering::SeqCst);
+    loop {
+        if state.lock().unwrap().generation != generation {
+            //drop it without a close handshake, as if the runner went away
+            return Ok(());
+        }
+        match ws.read() {
+            Ok(Message::Binary(data)) => {
+                for reply in answer(state, &data) {
+                    ws.send(Message::binary(reply)).map_err(io::Error::other)?;
+                }
+            }
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(io::Error::other(e)),
+        }
+    }
+}
+
+//the encoded `/rnbo/resp` messages for a `/rnbo/cmd` message
+fn answer(state: &Mutex<State>, data: &[u8]) -> Vec<Vec<u8>> {
+    let Ok((_, OscPacket::Message(msg))) = rosc::decoder::decode_udp(data) else {
+        return Vec::new();
+    };
+    let Some(OscType::String(cmd)) = msg.args.first() else {
+        return Vec::new();
+    };
+    let Ok(cmd) = serde_json::from_str::<Value>(cmd) else {
+        return Vec::new();
+    };
+    if msg.addr != "/rnbo/cmd" {
+        return Vec::new();
+    }
+    let method = cmd["method"].as_str().unwrap_or_default().to_string();
+    let params = cmd["params"].clone();
+
+    let mut state = state.lock().unwrap();
+    state.commands.push((method.clone(), params.clone()));
+    let replies = match state.handlers.get(&method) {
+        Some(handler) => handler(&params),
+        None => vec![json!({ "error": { "code": -32601, "message": "Method not found" } })],
+    };
+    replies
+        .into_iter()
+        .filter_map(|mut reply| {
+            if let Some(reply) = reply.as_object_mut() {
+                reply.entry("id").or_insert_with(|| cmd["id"].clone());
+                reply.insert("jsonrpc".to_string(), json!("2.0"));
+            }
+            rosc::encoder::encode(&OscPacket::Message(OscMessage {
+                addr: "/rnbo/resp".to_string(),
+                args: vec![OscType::String(reply.to_string())],
+            }))
+            .ok()
+        })
+        .collect()
+}
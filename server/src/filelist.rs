@@ -1,4 +1,11 @@
-use rocket::serde::{Deserialize, Serialize};
+use {
+    crate::digest,
+    rocket::{
+        http::ContentType,
+        serde::{Deserialize, Serialize},
+    },
+    std::path::Path,
+};
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -6,6 +13,20 @@ pub struct FileListItem {
     pub name: String,
     pub uri: String,
     pub dir: bool,
+    //in bytes, only for files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    //RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime: Option<String>,
+    //hex encoded sha256, only when asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    //the contents of a directory in a recursive listing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<FileListItem>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -15,12 +36,91 @@ pub struct FileList {
     pub items: Vec<FileListItem>,
 }
 
+impl FileListItem {
+    pub fn new<N: Into<String>, U: Into<String>>(name: N, uri: U, dir: bool) -> Self {
+        Self {
+            name: name.into(),
+            uri: uri.into(),
+            dir,
+            size: None,
+            modified: None,
+            mime: None,
+            hash: None,
+            items: None,
+        }
+    }
+
+    /// Describe the file or directory at `path`, hashing file contents if `hash` is set.
+    pub fn from_path<N: Into<String>, U: Into<String>>(
+        name: N,
+        uri: U,
+        path: &Path,
+        hash: bool,
+    ) -> Self {
+        let mut item = Self::new(name, uri, path.is_dir());
+        if let Ok(metadata) = path.metadata() {
+            item.modified = metadata
+                .modified()
+                .ok()
+                .map(|t| humantime::format_rfc3339_seconds(t).to_string());
+            if metadata.is_file() {
+                item.size = Some(metadata.len());
+                item.mime = mime_type(path).map(|t| t.to_string());
+                if hash {
+                    item.hash = digest::file(path).ok().map(|d| d.hex());
+                }
+            }
+        }
+        item
+    }
+}
+
 impl FileList {
     pub fn new_sorted<T: Into<String>>(filetype: T, mut items: Vec<FileListItem>) -> Self {
-        items.sort_by_cached_key(|i| i.name.to_owned());
+        sort_items(&mut items);
         Self {
             filetype: filetype.into(),
             items,
         }
     }
 }
+
+fn sort_items(items: &mut [FileListItem]) {
+    items.sort_by_cached_key(|i| i.name.to_owned());
+    for item in items.iter_mut() {
+        if let Some(items) = item.items.as_mut() {
+            sort_items(items);
+        }
+    }
+}
+
+/// Hidden files are left out of listings.
+pub fn is_hidden(name: &str) -> bool {
+    name.starts_with(".")
+}
+
+/// The content type a file is served with.
+pub fn mime_type(path: &Path) -> Option<ContentType> {
+    let ext = path.extension()?.to_str()?;
+    if ext == "rnbopack" {
+        Some(ContentType::TAR)
+    } else {
+        ContentType::from_extension(ext)
+    }
+}
+
+/// The bytes taken up by a file, or by everything inside a directory. Links aren't followed.
+pub fn tree_size(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| tree_size(&entry.path()))
+        .sum()
+}
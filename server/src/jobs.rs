@@ -0,0 +1,225 @@
+use {
+    crate::{problem::ErrorKind, runner::RunnerFault},
+    futures_util::{Stream, stream},
+    rocket::serde::Serialize,
+    std::{
+        collections::HashMap,
+        future::Future,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+    tokio::{sync::watch, task::AbortHandle},
+    uuid::Uuid,
+};
+
+//how long a finished job is kept around so clients can still poll its result
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_done(&self) -> bool {
+        *self != JobState::Running
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct JobError {
+    pub status: u16,
+    pub kind: ErrorKind,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runner: Option<RunnerFault>,
+}
+
+impl JobError {
+    pub fn new<T: Into<String>>(status: rocket::http::Status, message: T) -> Self {
+        Self {
+            status: status.code,
+            kind: ErrorKind::from_status(status),
+            message: message.into(),
+            runner: None,
+        }
+    }
+
+    pub fn kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Keep the error the runner answered with.
+    pub fn runner(mut self, fault: RunnerFault) -> Self {
+        self.kind = ErrorKind::RunnerError;
+        self.runner = Some(fault);
+        self
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct JobStatus {
+    pub id: Uuid,
+    pub kind: &'static str,
+    pub state: JobState,
+    pub progress: f32,
+    //where to find the result, if the job produces one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JobError>,
+}
+
+struct Job {
+    status: watch::Sender<JobStatus>,
+    abort: Mutex<Option<AbortHandle>>,
+    finished_at: Mutex<Option<Instant>>,
+}
+
+impl Job {
+    //only transitions a running job, so a late result can't overwrite a cancel
+    fn complete(&self, state: JobState, uri: Option<String>, error: Option<JobError>) -> bool {
+        let changed = self.status.send_if_modified(|s| {
+            if s.state.is_done() {
+                false
+            } else {
+                s.state = state;
+                if state == JobState::Finished {
+                    s.progress = 100.0;
+                }
+                s.uri = uri;
+                s.error = error;
+                true
+            }
+        });
+        if changed {
+            *self.finished_at.lock().unwrap() = Some(Instant::now());
+        }
+        changed
+    }
+}
+
+/// Given to the task doing the work so that it can report progress.
+#[derive(Clone)]
+pub struct JobHandle {
+    job: Arc<Job>,
+}
+
+impl JobHandle {
+    pub fn progress(&self, progress: f32) {
+        self.job.status.send_if_modified(|s| {
+            if s.state.is_done() || s.progress == progress {
+                false
+            } else {
+                s.progress = progress;
+                true
+            }
+        });
+    }
+
+    /// Whether the job has been cancelled, for work that carries on after its task is
+    /// aborted, such as on a blocking thread, to check between steps.
+    pub fn is_cancelled(&self) -> bool {
+        self.job.status.borrow().state == JobState::Cancelled
+    }
+}
+
+/// Registry of background jobs, held in rocket managed state.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<HashMap<Uuid, Arc<Job>>>,
+}
+
+impl Jobs {
+    /// Start `work` on the runtime and return its initial status.
+    /// On success the work returns an optional uri where its result can be found.
+    pub fn spawn<F, Fut>(&self, kind: &'static str, work: F) -> JobStatus
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = Result<Option<String>, JobError>> + Send + 'static,
+    {
+        let id = Uuid::new_v4();
+        let status = JobStatus {
+            id,
+            kind,
+            state: JobState::Running,
+            progress: 0.0,
+            uri: None,
+            error: None,
+        };
+        let job = Arc::new(Job {
+            status: watch::Sender::new(status.clone()),
+            abort: Mutex::new(None),
+            finished_at: Mutex::new(None),
+        });
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.retain(|_, j| {
+                j.finished_at
+                    .lock()
+                    .unwrap()
+                    .is_none_or(|t| t.elapsed() < FINISHED_JOB_TTL)
+            });
+            jobs.insert(id, job.clone());
+        }
+
+        let fut = work(JobHandle { job: job.clone() });
+        let task = {
+            let job = job.clone();
+            tokio::spawn(async move {
+                match fut.await {
+                    Ok(uri) => job.complete(JobState::Finished, uri, None),
+                    Err(e) => job.complete(JobState::Failed, None, Some(e)),
+                };
+            })
+        };
+        *job.abort.lock().unwrap() = Some(task.abort_handle());
+        status
+    }
+
+    pub fn status(&self, id: Uuid) -> Option<JobStatus> {
+        self.get(id).map(|j| j.status.borrow().clone())
+    }
+
+    pub fn subscribe(&self, id: Uuid) -> Option<watch::Receiver<JobStatus>> {
+        self.get(id).map(|j| j.status.subscribe())
+    }
+
+    /// The status of a job and every change to it, ending once the job is done.
+    pub fn updates(&self, id: Uuid) -> Option<impl Stream<Item = JobStatus> + use<>> {
+        let rx = self.subscribe(id)?;
+        Some(stream::unfold((Some(rx), true), |(rx, first)| async move {
+            let mut rx = rx?;
+            if !first {
+                rx.changed().await.ok()?;
+            }
+            let status = rx.borrow_and_update().clone();
+            let rx = (!status.state.is_done()).then_some(rx);
+            Some((status, (rx, false)))
+        }))
+    }
+
+    /// Abort a running job, finished jobs are left as they are.
+    pub fn cancel(&self, id: Uuid) -> Option<JobStatus> {
+        let job = self.get(id)?;
+        if job.complete(JobState::Cancelled, None, None)
+            && let Some(abort) = job.abort.lock().unwrap().take()
+        {
+            abort.abort();
+        }
+        let status = job.status.borrow().clone();
+        Some(status)
+    }
+
+    fn get(&self, id: Uuid) -> Option<Arc<Job>> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+}
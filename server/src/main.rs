@@ -1,17 +1,44 @@
 use {
-    crate::config::RunnerConfig,
+    crate::config::{PanelConfig, RunnerConfig},
     clap::Parser,
-    rocket::{fs::FileServer, main},
+    rocket::{
+        config::Config,
+        figment::{
+            Figment, Profile,
+            providers::{Env, Format, Serialized, Toml},
+        },
+        fs::FileServer,
+        main,
+    },
     rocket_dyn_templates::Template,
+    serde::Serialize,
     std::{
-        collections::{HashMap, HashSet},
-        path::PathBuf,
+        net::{IpAddr, Ipv4Addr},
+        path::{Path, PathBuf},
     },
 };
 
+mod analysis;
+mod archive;
+mod audio;
+mod cache;
+mod conditional;
 mod config;
+mod digest;
+#[cfg(test)]
+mod fake_runner;
 mod filelist;
+mod jobs;
+mod paths;
+mod problem;
+mod processing;
+mod rnbopack;
 mod routes;
+mod runner;
+mod trash;
+mod uploads;
+mod usage;
+mod watch;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -20,50 +47,149 @@ struct Args {
     #[arg(short, long, default_value = "~/.config/rnbo/runner.json")]
     runner_config: String,
 
+    /// path to the panel's own configuration toml
+    #[arg(long, default_value = "~/.config/rnbo/panel.toml")]
+    panel_config: String,
+
     #[arg(short, long, default_value = None)]
     template_dir: Option<PathBuf>,
 
     #[arg(short, long, default_value = None)]
     static_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    overrides: Overrides,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[main]
-async fn main() -> Result<(), rocket::Error> {
-    let args = Args::parse();
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// print the manifest of a .rnbopack as json and exit
+    Inspect {
+        /// path to the package
+        package: PathBuf,
+    },
+}
+
+/// Command line settings, these take precedence over the config file and environment.
+#[derive(clap::Args, Serialize, Debug)]
+struct Overrides {
+    /// address for the panel to listen on
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<std::net::IpAddr>,
+
+    /// port for the panel to listen on
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+
+    /// host of the runner to talk to
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    runner_host: Option<String>,
+
+    /// port of the runner to talk to
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    runner_port: Option<u16>,
 
-    let config_path = args.runner_config;
-    let config_path = if let Some(config_path) = config_path.strip_prefix("~/") {
+    /// milliseconds to wait for the runner to connect or answer a query
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    runner_timeout: Option<u64>,
+
+    /// seconds to wait for the runner to create a package
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    package_timeout: Option<u64>,
+
+    /// mebibytes an uploaded archive may unpack to
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extract_limit: Option<u64>,
+
+    /// how symbolic links inside the filetype directories are treated
+    #[arg(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symlinks: Option<crate::paths::SymlinkPolicy>,
+
+    /// days deleted files are kept in the trash
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trash_max_age: Option<u64>,
+
+    /// mebibytes of deleted files kept in the trash of each filetype
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trash_max_size: Option<u64>,
+
+    /// mebibytes that uploads must leave free on the filesystem
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disk_reserve: Option<u64>,
+}
+
+fn expand_home(path: String) -> PathBuf {
+    if let Some(path) = path.strip_prefix("~/") {
         let homedir = home::home_dir().expect("to get home directory");
         let mut p = homedir.clone();
-        p.push(config_path);
+        p.push(path);
         p
     } else {
-        PathBuf::from(config_path)
-    };
+        PathBuf::from(path)
+    }
+}
 
-    let runner_config = RunnerConfig::read_or_default(&config_path);
-    let filetype_paths = HashMap::from([
-        ("datafiles".to_string(), runner_config.datafile_dir()),
-        ("backup".to_string(), runner_config.backup_dir()),
-        (
-            "compile_cache".to_string(),
-            runner_config.compile_cache_dir(),
-        ),
-        ("source_cache".to_string(), runner_config.source_cache_dir()),
-        ("packages".to_string(), runner_config.package_dir()),
-    ]);
+/// The rocket configuration with the panel's settings, taken from the file at `panel_config`,
+/// `RNBO_PANEL_` environment variables and `overrides`, later sources overriding earlier ones.
+fn figment(panel_config: &Path, overrides: Overrides) -> Figment {
+    //the panel's sources are global so that they override the port and address defaults
+    //regardless of the rocket profile
+    Config::figment()
+        .merge((Config::PORT, 3000))
+        .merge((Config::ADDRESS, IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))))
+        .merge(Toml::file(panel_config).profile(Profile::Global))
+        .merge(Env::prefixed("RNBO_PANEL_").global())
+        .merge(Serialized::globals(overrides))
+}
 
-    let deleteable_filetypes = HashSet::from(["packages".to_string(), "datafiles".to_string()]);
+#[main]
+async fn main() -> Result<(), Box<rocket::Error>> {
+    let args = Args::parse();
 
-    {
-        use {
-            core::net::{IpAddr, Ipv4Addr},
-            rocket::config::Config,
-        };
+    if let Some(Command::Inspect { package }) = args.command {
+        match crate::rnbopack::inspect(&package, u64::MAX) {
+            Ok(manifest) => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&manifest).expect("to serialize manifest")
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("failed to inspect {}: {e}", package.display());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let config_path = expand_home(args.runner_config);
 
-        let mut config = Config::figment()
-            .merge((Config::PORT, 3000))
-            .merge((Config::ADDRESS, IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))));
+    let runner_config = match RunnerConfig::read_or_default(&config_path) {
+        Ok(runner_config) => runner_config,
+        Err(e) => {
+            eprintln!(
+                "invalid runner configuration {}: {e}",
+                config_path.display()
+            );
+            std::process::exit(1);
+        }
+    };
+    {
+        let mut config = figment(&expand_home(args.panel_config), args.overrides);
         if let Some(dir) = args.template_dir {
             config = config.merge(("template_dir", dir));
         }
@@ -71,19 +197,103 @@ async fn main() -> Result<(), rocket::Error> {
             .static_dir
             .unwrap_or_else(|| PathBuf::from("../client/out"));
 
+        let panel_config: PanelConfig = match config.extract() {
+            Ok(panel_config) => panel_config,
+            Err(e) => {
+                eprintln!("invalid panel configuration: {e}");
+                std::process::exit(1);
+            }
+        };
+        let files = crate::config::Config::new(
+            runner_config.filetypes(),
+            panel_config.symlinks,
+            Some(runner_config.package_dir()),
+        );
+        let watch = crate::watch::FsWatch::new(&files.filetype_paths());
+
         rocket::build()
             .configure(config)
             .mount("/", FileServer::from(static_dir))
             .mount("/files", crate::routes::file_routes())
             .mount("/packages", crate::routes::package_routes())
-            .manage(crate::config::Config::new(
-                filetype_paths,
-                deleteable_filetypes,
-                Some(runner_config.package_dir()),
+            .mount("/jobs", crate::routes::job_routes())
+            .mount("/cache", crate::routes::cache_routes())
+            .register("/files", crate::routes::error_catchers())
+            .register("/packages", crate::routes::error_catchers())
+            .manage(crate::usage::DiskUsage::new(&watch))
+            .manage(watch)
+            .manage(files)
+            .manage(crate::jobs::Jobs::default())
+            .manage(crate::uploads::Uploads::default())
+            .manage(crate::runner::Runner::new(
+                panel_config.runner_url(),
+                panel_config.runner_timeout(),
             ))
+            .manage(panel_config)
             .attach(Template::fairing())
             .launch()
             .await?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        std::{fs, net::IpAddr},
+        tempdir::TempDir,
+    };
+
+    #[test]
+    fn panel_figment() {
+        let dir = TempDir::new("runner-panel").expect("to create temp dir");
+        let path = dir.path().join("panel.toml");
+        fs::write(
+            &path,
+            "port = 8000\nrunner_host = \"runner.local\"\nrunner_port = 6000\ntrash_max_age = 7\n",
+        )
+        .expect("to write");
+        //SAFETY: no other test reads or writes these variables
+        unsafe {
+            std::env::set_var("RNBO_PANEL_RUNNER_PORT", "6500");
+            std::env::set_var("RNBO_PANEL_EXTRACT_LIMIT", "10");
+        }
+        let args = Args::parse_from([
+            "rnbo-runner-panel",
+            "--runner-port",
+            "7000",
+            "--disk-reserve",
+            "1",
+        ]);
+        let config = figment(&path, args.overrides);
+        unsafe {
+            std::env::remove_var("RNBO_PANEL_RUNNER_PORT");
+            std::env::remove_var("RNBO_PANEL_EXTRACT_LIMIT");
+        }
+
+        let panel: PanelConfig = config.extract().expect("to extract panel config");
+        //from the file, the environment and the command line, each overriding the one before
+        assert_eq!(panel.runner_host, "runner.local");
+        assert_eq!(panel.trash_max_age, 7);
+        assert_eq!(panel.extract_limit, 10);
+        assert_eq!(panel.runner_port, 7000);
+        assert_eq!(panel.disk_reserve, 1);
+        assert_eq!(
+            panel.package_timeout,
+            PanelConfig::default().package_timeout
+        );
+        let rocket: Config = config.extract().expect("to extract rocket config");
+        assert_eq!(rocket.port, 8000);
+        assert_eq!(rocket.address, IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)));
+
+        //an invalid value is an error rather than the default
+        fs::write(&path, "runner_port = \"runner\"\n").expect("to write");
+        let args = Args::parse_from(["rnbo-runner-panel"]);
+        assert!(
+            figment(&path, args.overrides)
+                .extract::<PanelConfig>()
+                .is_err()
+        );
+    }
+}
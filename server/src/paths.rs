@@ -0,0 +1,128 @@
+use {
+    crate::problem::{ErrorKind, Problem},
+    rocket::{
+        Request,
+        http::Status,
+        response::{self, Responder},
+        serde::{Deserialize, Serialize},
+    },
+    std::path::{Component, Path, PathBuf},
+};
+
+/// How symbolic links inside the filetype directories are treated.
+#[derive(Deserialize, Serialize, clap::ValueEnum, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// follow links that lead somewhere inside the filetype directory
+    #[default]
+    #[value(name = "within_root")]
+    WithinRoot,
+    /// refuse any path that goes through a link
+    Deny,
+    /// follow links wherever they lead
+    Allow,
+}
+
+/// A path that was refused because it leads outside of its filetype directory.
+#[derive(Serialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct PathEscape {
+    pub error: &'static str,
+    //as requested, relative to the filetype directory
+    pub path: String,
+}
+
+impl PathEscape {
+    fn new(error: &'static str, path: &Path) -> Self {
+        Self {
+            error,
+            path: path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for PathEscape {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        eprintln!("refused {}: {}", self.path, self.error);
+        Problem::new(Status::Forbidden)
+            .kind(ErrorKind::PathEscape)
+            .detail(self.error)
+            .extend(&serde_json::json!({ "path": self.path }))
+            .respond_to(req)
+    }
+}
+
+/// Keeps paths inside a filetype directory.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    root: PathBuf,
+    //the root with its own links resolved, links are compared against this
+    canonical_root: PathBuf,
+    policy: SymlinkPolicy,
+}
+
+impl Sandbox {
+    pub fn new(root: &Path, policy: SymlinkPolicy) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            canonical_root: root.canonicalize().unwrap_or_else(|_| root.to_path_buf()),
+            policy,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Join `relative` onto the root, checking every part of it that exists against the policy.
+    /// The result need not exist, links are left in place so that deleting one removes the link.
+    pub fn resolve(&self, relative: &Path) -> Result<PathBuf, PathEscape> {
+        let mut path = self.root.clone();
+        let mut exists = true;
+        for component in relative.components() {
+            match component {
+                Component::Normal(c) => path.push(c),
+                Component::CurDir => continue,
+                _ => {
+                    return Err(PathEscape::new(
+                        "path leaves the filetype directory",
+                        relative,
+                    ));
+                }
+            }
+            //once a part is missing nothing below it can be a link
+            if exists {
+                exists = path.symlink_metadata().is_ok();
+                if exists && !self.permits(&path) {
+                    return Err(PathEscape::new(self.refusal(), relative));
+                }
+            }
+        }
+        Ok(path)
+    }
+
+    /// Whether the entry at `path`, whose parent has already been checked, may be used.
+    pub fn permits(&self, path: &Path) -> bool {
+        match (is_link(path), self.policy) {
+            (false, _) | (true, SymlinkPolicy::Allow) => true,
+            (true, SymlinkPolicy::Deny) => false,
+            //dangling links are refused too, writing through one would create its target
+            (true, SymlinkPolicy::WithinRoot) => path
+                .canonicalize()
+                .is_ok_and(|target| target.starts_with(&self.canonical_root)),
+        }
+    }
+
+    fn refusal(&self) -> &'static str {
+        match self.policy {
+            SymlinkPolicy::Deny => "symbolic links are not allowed",
+            _ => "symbolic link leads outside the filetype directory",
+        }
+    }
+}
+
+/// Whether `path` is a symbolic link, without following it.
+pub fn is_link(path: &Path) -> bool {
+    path.symlink_metadata()
+        .is_ok_and(|m| m.file_type().is_symlink())
+}
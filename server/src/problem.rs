@@ -0,0 +1,166 @@
+use {
+    crate::{
+        jobs::JobError,
+        runner::{RunnerError, RunnerFault},
+    },
+    rocket::{
+        Request,
+        http::{ContentType, Status, StatusClass},
+        response::{self, Responder},
+        serde::{Deserialize, Serialize, json::Json},
+    },
+};
+
+/// What went wrong, for clients to act on without reading the message.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum ErrorKind {
+    BadRequest,
+    //the filetype doesn't allow the operation
+    AccessDenied,
+    //the path leads out of its filetype directory
+    PathEscape,
+    NotFound,
+    Conflict,
+    PreconditionFailed,
+    TooLarge,
+    UnsupportedType,
+    DigestMismatch,
+    InvalidAudio,
+    InvalidArchive,
+    InvalidPackage,
+    Unprocessable,
+    InsufficientStorage,
+    //a dependency other than the runner, usually the filesystem
+    DependencyFailed,
+    Timeout,
+    RunnerUnavailable,
+    RunnerTimeout,
+    //the runner answered with an error of its own
+    RunnerError,
+    ClientError,
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn from_status(status: Status) -> Self {
+        match status.code {
+            400 => Self::BadRequest,
+            401 | 403 => Self::AccessDenied,
+            404 => Self::NotFound,
+            409 => Self::Conflict,
+            412 => Self::PreconditionFailed,
+            413 => Self::TooLarge,
+            415 => Self::UnsupportedType,
+            422 => Self::Unprocessable,
+            424 => Self::DependencyFailed,
+            504 => Self::Timeout,
+            507 => Self::InsufficientStorage,
+            _ if status.class() == StatusClass::ClientError => Self::ClientError,
+            _ => Self::Internal,
+        }
+    }
+}
+
+/// An RFC 9457 problem details body, the shape of every error from `/files` and `/packages`.
+/// Anything particular to the error is added next to the standard members.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub kind: ErrorKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    //the path that was requested, filled in when responding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runner: Option<RunnerFault>,
+    #[serde(flatten)]
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Problem {
+    pub fn new(status: Status) -> Self {
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: status.reason_lossy().to_string(),
+            status: status.code,
+            kind: ErrorKind::from_status(status),
+            detail: None,
+            instance: None,
+            runner: None,
+            extensions: Default::default(),
+        }
+    }
+
+    pub fn kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn detail<T: Into<String>>(mut self, detail: T) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn runner(mut self, fault: Option<RunnerFault>) -> Self {
+        if fault.is_some() {
+            self.kind = ErrorKind::RunnerError;
+        }
+        self.runner = fault;
+        self
+    }
+
+    /// Add the members of `value`, which should serialize to an object, to the body.
+    pub fn extend<T: Serialize>(mut self, value: &T) -> Self {
+        if let Ok(serde_json::Value::Object(members)) = serde_json::to_value(value) {
+            self.extensions.extend(members);
+        }
+        self
+    }
+}
+
+impl From<Status> for Problem {
+    fn from(status: Status) -> Self {
+        Self::new(status)
+    }
+}
+
+impl From<RunnerError> for ErrorKind {
+    fn from(e: RunnerError) -> Self {
+        match e {
+            RunnerError::Unavailable | RunnerError::Disconnected => Self::RunnerUnavailable,
+            RunnerError::Timeout => Self::RunnerTimeout,
+        }
+    }
+}
+
+impl From<RunnerError> for Problem {
+    fn from(e: RunnerError) -> Self {
+        Self::new(e.into()).kind(e.into()).detail(e.to_string())
+    }
+}
+
+impl From<JobError> for Problem {
+    fn from(e: JobError) -> Self {
+        Self::new(Status::from_code(e.status).unwrap_or(Status::InternalServerError))
+            .kind(e.kind)
+            .detail(e.message)
+            .runner(e.runner)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Problem {
+    fn respond_to(mut self, req: &'r Request<'_>) -> response::Result<'static> {
+        if self.instance.is_none() {
+            self.instance = Some(req.uri().path().to_string());
+        }
+        let status = Status::from_code(self.status).unwrap_or(Status::InternalServerError);
+        let content_type = ContentType::new("application", "problem+json");
+        (status, (content_type, Json(self))).respond_to(req)
+    }
+}
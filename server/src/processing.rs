@@ -0,0 +1,399 @@
+use {
+    crate::audio,
+    rubato::{Fft, FixedSync, Indexing, Resampler, audioadapter_buffers::owned::InterleavedOwned},
+    serde::Deserialize,
+    std::path::Path,
+};
+
+//frames the resampler works on at a time
+const RESAMPLE_CHUNK: usize = 1024;
+
+/// One step of processing, applied in the order they are given.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    /// Scale so that the highest peak reaches `peak_dbfs`.
+    Normalize {
+        #[serde(default)]
+        peak_dbfs: f64,
+    },
+    /// Keep the audio between `start` and `end`, in seconds.
+    Trim {
+        #[serde(default)]
+        start: f64,
+        end: Option<f64>,
+    },
+    /// Linear fades over the given number of seconds.
+    Fade {
+        #[serde(default, rename = "in")]
+        fade_in: f64,
+        #[serde(default, rename = "out")]
+        fade_out: f64,
+    },
+    /// Mix all channels down to one.
+    Mono,
+    /// Keep only one channel, counting from 0.
+    Channel { channel: usize },
+    /// Convert to another sample rate.
+    Resample { sample_rate: u32 },
+}
+
+/// The shape of the audio going into or coming out of an operation.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub frames: usize,
+}
+
+impl Layout {
+    fn frame_at(&self, seconds: f64) -> usize {
+        ((seconds.max(0.0) * self.sample_rate as f64).round() as usize).min(self.frames)
+    }
+}
+
+//resamples a stream, dropping the resampler's delay from the start and its padding from the end
+struct Resample {
+    resampler: Fft<f32>,
+    channels: usize,
+    //input waiting for a whole chunk
+    pending: Vec<f32>,
+    //output frames still to drop, and still to keep
+    delay: usize,
+    remaining: usize,
+}
+
+impl Resample {
+    fn new(input: Layout, sample_rate: u32) -> Result<Self, String> {
+        let resampler = Fft::<f32>::new(
+            input.sample_rate as usize,
+            sample_rate as usize,
+            RESAMPLE_CHUNK,
+            input.channels,
+            FixedSync::Input,
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Self {
+            remaining: (resampler.resample_ratio() * input.frames as f64).ceil() as usize,
+            delay: resampler.output_delay(),
+            resampler,
+            channels: input.channels,
+            pending: Vec::new(),
+        })
+    }
+
+    //resample the next chunk, `partial` is how many pending frames to use when there
+    //isn't a whole chunk left, the rest is silence
+    fn chunk(&mut self, partial: Option<usize>, out: &mut Vec<f32>) -> Result<(), String> {
+        let channels = self.channels;
+        let needed = self.resampler.input_frames_next();
+        let mut input: Vec<f32> = self
+            .pending
+            .drain(..partial.unwrap_or(needed) * channels)
+            .collect();
+        input.resize(needed * channels, 0.0);
+        let input =
+            InterleavedOwned::new_from(input, channels, needed).map_err(|e| e.to_string())?;
+        let mut indexing = Indexing::new();
+        indexing.partial_len = partial;
+        let output = self
+            .resampler
+            .process(&input, Some(&indexing))
+            .map_err(|e| e.to_string())?
+            .take_data();
+        let frames = output.len() / channels;
+        let skip = self.delay.min(frames);
+        let keep = (frames - skip).min(self.remaining);
+        self.delay -= skip;
+        self.remaining -= keep;
+        out.extend_from_slice(&output[skip * channels..(skip + keep) * channels]);
+        Ok(())
+    }
+
+    fn push(&mut self, samples: &[f32]) -> Result<Vec<f32>, String> {
+        self.pending.extend_from_slice(samples);
+        let mut out = Vec::new();
+        while self.pending.len() >= self.resampler.input_frames_next() * self.channels {
+            self.chunk(None, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    fn finish(&mut self) -> Result<Vec<f32>, String> {
+        let mut out = Vec::new();
+        if !self.pending.is_empty() {
+            self.chunk(Some(self.pending.len() / self.channels), &mut out)?;
+        }
+        while self.remaining > 0 {
+            self.chunk(Some(0), &mut out)?;
+        }
+        Ok(out)
+    }
+}
+
+//an operation working on one block at a time, positions count frames of its input
+enum Stage {
+    Pass,
+    Gain(f32),
+    Trim {
+        start: usize,
+        end: usize,
+        at: usize,
+    },
+    Fade {
+        fade_in: usize,
+        fade_out: usize,
+        frames: usize,
+        at: usize,
+    },
+    Mono,
+    Channel(usize),
+    Resample(Box<Resample>),
+}
+
+impl Stage {
+    //the stage for `op` and the layout of what it produces,
+    //`gain` is the one a normalize has been measured to need
+    fn new(op: &Operation, input: Layout, gain: Option<f32>) -> Result<(Self, Layout), String> {
+        let mut output = input;
+        let stage = match *op {
+            Operation::Normalize { .. } => Stage::Gain(gain.unwrap_or(1.0)),
+            Operation::Trim { start, end } => {
+                let start = input.frame_at(start);
+                let end = end.map_or(input.frames, |end| input.frame_at(end));
+                if end < start {
+                    return Err("trim ends before it starts".to_string());
+                }
+                output.frames = end - start;
+                Stage::Trim { start, end, at: 0 }
+            }
+            Operation::Fade { fade_in, fade_out } => Stage::Fade {
+                fade_in: input.frame_at(fade_in),
+                fade_out: input.frame_at(fade_out),
+                frames: input.frames,
+                at: 0,
+            },
+            Operation::Mono => {
+                output.channels = 1;
+                Stage::Mono
+            }
+            Operation::Channel { channel } => {
+                if channel >= input.channels {
+                    return Err(format!("there is no channel {channel}"));
+                }
+                output.channels = 1;
+                Stage::Channel(channel)
+            }
+            Operation::Resample { sample_rate } => {
+                output.sample_rate = sample_rate;
+                if sample_rate == input.sample_rate || input.frames == 0 {
+                    Stage::Pass
+                } else {
+                    let resample = Resample::new(input, sample_rate)?;
+                    output.frames = resample.remaining;
+                    Stage::Resample(Box::new(resample))
+                }
+            }
+        };
+        Ok((stage, output))
+    }
+
+    fn push(&mut self, channels: usize, mut samples: Vec<f32>) -> Result<Vec<f32>, String> {
+        let frames = samples.len() / channels;
+        Ok(match self {
+            Stage::Pass => samples,
+            Stage::Gain(gain) => {
+                samples.iter_mut().for_each(|s| *s *= *gain);
+                samples
+            }
+            Stage::Trim { start, end, at } => {
+                let from = (*start).clamp(*at, *at + frames) - *at;
+                let to = (*end).clamp(*at, *at + frames) - *at;
+                *at += frames;
+                samples.truncate(to * channels);
+                samples.drain(..from * channels);
+                samples
+            }
+            Stage::Fade {
+                fade_in,
+                fade_out,
+                frames: total,
+                at,
+            } => {
+                for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+                    let i = *at + i;
+                    let mut gain = 1.0f32;
+                    if i < *fade_in {
+                        gain *= i as f32 / *fade_in as f32;
+                    }
+                    let from_end = total.saturating_sub(i + 1);
+                    if from_end < *fade_out {
+                        gain *= from_end as f32 / *fade_out as f32;
+                    }
+                    frame.iter_mut().for_each(|s| *s *= gain);
+                }
+                *at += frames;
+                samples
+            }
+            Stage::Mono => samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect(),
+            Stage::Channel(channel) => samples
+                .chunks_exact(channels)
+                .map(|frame| frame[*channel])
+                .collect(),
+            Stage::Resample(resample) => resample.push(&samples)?,
+        })
+    }
+
+    //whatever the stage was holding on to once its input has ended
+    fn finish(&mut self) -> Result<Vec<f32>, String> {
+        match self {
+            Stage::Resample(resample) => resample.finish(),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+//the stages with the channel count of their input
+fn feed(stages: &mut [(Stage, usize)], mut samples: Vec<f32>) -> Result<Vec<f32>, String> {
+    for (stage, channels) in stages {
+        samples = stage.push(*channels, samples)?;
+    }
+    Ok(samples)
+}
+
+/// Operations applied to an audio file a block at a time, so that memory use stays the
+/// same however long the file is. Normalizing takes an extra pass to find the peak.
+pub struct Processing<'a, P: Fn(f32) -> bool> {
+    path: &'a Path,
+    operations: &'a [Operation],
+    source: Layout,
+    //the gain of each normalize, measured before the audio is written
+    gains: Vec<Option<f32>>,
+    //told how far along the work is in percent, stops it by returning false
+    progress: P,
+    passes: usize,
+    pass: usize,
+}
+
+impl<'a, P: Fn(f32) -> bool> Processing<'a, P> {
+    /// Read through the file at `path` to find its length and check that the operations apply.
+    pub fn new(path: &'a Path, operations: &'a [Operation], progress: P) -> Result<Self, String> {
+        let mut samples = 0;
+        let (sample_rate, channels) = audio::decode(path, |s| {
+            samples += s.len();
+            progress(0.0)
+                .then_some(())
+                .ok_or_else(|| "cancelled".to_string())
+        })?;
+        let passes = 2 + operations
+            .iter()
+            .filter(|op| matches!(op, Operation::Normalize { .. }))
+            .count();
+        let processing = Self {
+            path,
+            operations,
+            source: Layout {
+                sample_rate,
+                channels,
+                frames: samples / channels.max(1),
+            },
+            gains: vec![None; operations.len()],
+            progress,
+            passes,
+            pass: 1,
+        };
+        processing.stages(operations.len())?;
+        Ok(processing)
+    }
+
+    //the first `count` operations as stages, along with the layout they produce
+    fn stages(&self, count: usize) -> Result<(Vec<(Stage, usize)>, Layout), String> {
+        let mut layout = self.source;
+        let mut stages = Vec::with_capacity(count);
+        for (op, gain) in self.operations[..count].iter().zip(&self.gains) {
+            let (stage, output) = Stage::new(op, layout, *gain)?;
+            stages.push((stage, layout.channels));
+            layout = output;
+        }
+        Ok((stages, layout))
+    }
+
+    //decode the file and run it through `stages`, handing what comes out to `sink`
+    fn run<F>(&mut self, mut stages: Vec<(Stage, usize)>, mut sink: F) -> Result<(), String>
+    where
+        F: FnMut(&[f32]) -> Result<(), String>,
+    {
+        let (channels, frames) = (self.source.channels, self.source.frames.max(1));
+        let (pass, passes) = (self.pass as f32, self.passes as f32);
+        let progress = &self.progress;
+        let mut done = 0;
+        audio::decode(self.path, |samples| {
+            done += samples.len() / channels;
+            if !progress(100.0 * (pass + done as f32 / frames as f32) / passes) {
+                return Err("cancelled".to_string());
+            }
+            sink(&feed(&mut stages, samples.to_vec())?)
+        })?;
+        for i in 0..stages.len() {
+            let tail = stages[i].0.finish()?;
+            sink(&feed(&mut stages[i + 1..], tail)?)?;
+        }
+        self.pass += 1;
+        Ok(())
+    }
+
+    //find the gain of every normalize, each one measured after the operations before it
+    fn measure(&mut self) -> Result<(), String> {
+        for i in 0..self.operations.len() {
+            let Operation::Normalize { peak_dbfs } = self.operations[i] else {
+                continue;
+            };
+            let (stages, _) = self.stages(i)?;
+            let mut peak = 0f32;
+            self.run(stages, |samples| {
+                peak = samples.iter().fold(peak, |p, s| p.max(s.abs()));
+                Ok(())
+            })?;
+            //silence stays silent
+            self.gains[i] = Some(if peak > 0.0 {
+                10f64.powf(peak_dbfs / 20.0) as f32 / peak
+            } else {
+                1.0
+            });
+        }
+        Ok(())
+    }
+
+    /// Write the result to a wav file, as 32 bit float or as integer pcm with `bit_depth` bits.
+    pub fn write_wav(mut self, path: &Path, float: bool, bit_depth: u16) -> Result<(), String> {
+        self.measure()?;
+        let (stages, layout) = self.stages(self.operations.len())?;
+        let spec = hound::WavSpec {
+            channels: layout.channels as u16,
+            sample_rate: layout.sample_rate,
+            bits_per_sample: if float { 32 } else { bit_depth },
+            sample_format: if float {
+                hound::SampleFormat::Float
+            } else {
+                hound::SampleFormat::Int
+            },
+        };
+        let mut writer = hound::WavWriter::create(path, spec).map_err(|e| e.to_string())?;
+        let scale = ((1i64 << (bit_depth - 1)) - 1) as f32;
+        self.run(stages, |samples| {
+            for &s in samples {
+                if float {
+                    writer.write_sample(s)
+                } else {
+                    writer.write_sample((s.clamp(-1.0, 1.0) * scale).round() as i32)
+                }
+                .map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        })?;
+        writer.finalize().map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,750 @@
+use {
+    rocket::serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fs::File,
+        io::{self, Read},
+        path::{Component, Path, PathBuf},
+        time::SystemTime,
+    },
+};
+
+//json files larger than this aren't read for presets and views
+const MAX_JSON_SIZE: u64 = 4 * 1024 * 1024;
+//nor once the json files read so far take up this much together
+const MAX_JSON_TOTAL: u64 = 16 * 1024 * 1024;
+
+//info.json at the root of the package, as the runner writes it
+#[derive(Deserialize, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct Info {
+    name: String,
+    rnbo_version: Option<String>,
+    rnbo_compatibility_version: Option<String>,
+    runner_version: Option<String>,
+    schema_version: Option<u32>,
+    sets: Vec<SetInfo>,
+    patchers: Vec<PatcherInfo>,
+    datafiles: Vec<DataFileInfo>,
+    targets: BTreeMap<String, Target>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct SetInfo {
+    name: String,
+    uuid: Option<String>,
+    created_at: Option<String>,
+    location: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct PatcherInfo {
+    name: String,
+    uuid: Option<String>,
+    rnbo_version: Option<String>,
+    created_at: Option<String>,
+    patcher: Option<String>,
+    config: Option<String>,
+    presets: Option<String>,
+    binaries: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(crate = "rocket::serde", default)]
+struct DataFileInfo {
+    name: String,
+    location: String,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Target {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_processor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compiler_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compiler_version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct SetItem {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    pub location: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct PatcherItem {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rnbo_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    //the patcher and its config, presets and binaries are listed on their own
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct PresetsItem {
+    pub patcher: String,
+    pub location: String,
+    pub size: u64,
+    //when the presets file could be read
+    #[serde(default)]
+    pub names: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct ViewItem {
+    pub set: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct DataFileItem {
+    pub name: String,
+    pub location: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct BinaryItem {
+    pub patcher: String,
+    //the target triple the binary was built for
+    pub target: String,
+    pub location: String,
+    pub size: u64,
+}
+
+/// Bytes taken up by each kind of content, unpacked.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Sizes {
+    pub sets: u64,
+    pub patchers: u64,
+    pub presets: u64,
+    pub datafiles: u64,
+    pub binaries: u64,
+    //everything in the package, including what isn't listed
+    pub total: u64,
+}
+
+/// What a package holds, read from its info.json and the sizes of its entries.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Manifest {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rnbo_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rnbo_compatibility_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runner_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>,
+    pub sets: Vec<SetItem>,
+    pub patchers: Vec<PatcherItem>,
+    pub presets: Vec<PresetsItem>,
+    pub views: Vec<ViewItem>,
+    pub datafiles: Vec<DataFileItem>,
+    pub binaries: Vec<BinaryItem>,
+    pub targets: BTreeMap<String, Target>,
+    pub sizes: Sizes,
+    //the size of the package file itself
+    pub file_size: u64,
+}
+
+/// What is wrong with a package, by the check that found it.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum ProblemKind {
+    //the tar can't be read to the end
+    Integrity,
+    //an entry would end up outside of the package
+    Path,
+    //links to outside the package, devices, sparse files and the like
+    Entry,
+    //info.json is missing, unreadable or lists files that aren't there
+    Manifest,
+    //the package was exported for another RNBO version than the directory it's stored in
+    Version,
+    Size,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Problem {
+    pub kind: ProblemKind,
+    //as named in the tar, when the problem is with one entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    pub error: String,
+}
+
+impl Problem {
+    fn new<E: ToString>(kind: ProblemKind, entry: Option<&str>, error: E) -> Self {
+        Self {
+            kind,
+            entry: entry.map(str::to_string),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// The outcome of validating a package, with its manifest when info.json could be read.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct PackageReport {
+    pub valid: bool,
+    //the RNBO version of the directory the package is stored in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_rnbo_version: Option<String>,
+    pub problems: Vec<Problem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<Manifest>,
+}
+
+//the entries of a package, relative to its root directory
+struct Contents {
+    sizes: BTreeMap<String, u64>,
+    json: HashMap<String, Vec<u8>>,
+    problems: Vec<Problem>,
+}
+
+impl Contents {
+    //the size of a file, or of everything in a directory
+    fn size(&self, location: &str) -> u64 {
+        self.files(location).map(|(_, size)| size).sum()
+    }
+
+    fn contains(&self, location: &str) -> bool {
+        self.files(location).next().is_some()
+    }
+
+    fn files(&self, location: &str) -> impl Iterator<Item = (&String, &u64)> {
+        let location = location.trim_matches('/').to_string();
+        let dir = format!("{location}/");
+        self.sizes
+            .range(location.clone()..)
+            .take_while(move |(name, _)| name.starts_with(&location))
+            .filter(move |(name, _)| name.len() == dir.len() - 1 || name.starts_with(&dir))
+    }
+
+    fn json<T: for<'de> Deserialize<'de>>(&self, location: &str) -> Option<T> {
+        serde_json::from_slice(self.json.get(location.trim_matches('/'))?).ok()
+    }
+
+    //why the package wasn't read to the end
+    fn unread(&self) -> Option<&Problem> {
+        self.problems
+            .iter()
+            .find(|p| matches!(p.kind, ProblemKind::Integrity | ProblemKind::Size))
+    }
+}
+
+//the components of an entry path, refusing anything that could escape the package
+fn entry_parts(path: &Path) -> Result<Vec<String>, &'static str> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => parts.push(c.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            Component::ParentDir => return Err("path contains `..`"),
+            Component::RootDir | Component::Prefix(_) => return Err("path is absolute"),
+        }
+    }
+    Ok(parts)
+}
+
+//read through the whole package, `limit` is the most its files may unpack to
+fn scan(path: &Path, limit: u64) -> io::Result<Contents> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut contents = Contents {
+        sizes: BTreeMap::new(),
+        json: HashMap::new(),
+        problems: Vec::new(),
+    };
+    let entries = match archive.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            contents
+                .problems
+                .push(Problem::new(ProblemKind::Integrity, None, e));
+            return Ok(contents);
+        }
+    };
+    let mut root: Option<String> = None;
+    let mut total = 0u64;
+    let mut json_total = 0u64;
+    for entry in entries {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                contents
+                    .problems
+                    .push(Problem::new(ProblemKind::Integrity, None, e));
+                break;
+            }
+        };
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let parts = match entry_parts(Path::new(&name)) {
+            Ok(parts) => parts,
+            Err(e) => {
+                contents
+                    .problems
+                    .push(Problem::new(ProblemKind::Path, Some(&name), e));
+                continue;
+            }
+        };
+        //everything lives in one directory named after the package
+        let Some((first, rest)) = parts.split_first() else {
+            continue;
+        };
+        if root.get_or_insert_with(|| first.clone()) != first {
+            contents.problems.push(Problem::new(
+                ProblemKind::Path,
+                Some(&name),
+                "entry is outside of the package directory",
+            ));
+            continue;
+        }
+        let entry_type = entry.header().entry_type();
+        match entry_type {
+            //pax sparse files are regular entries, their stored size isn't what they unpack to
+            tar::EntryType::GNUSparse | tar::EntryType::Regular
+                if entry_type == tar::EntryType::GNUSparse || is_pax_sparse(&mut entry) =>
+            {
+                contents.problems.push(Problem::new(
+                    ProblemKind::Entry,
+                    Some(&name),
+                    "sparse files are not supported",
+                ));
+                continue;
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous => {}
+            tar::EntryType::Directory => continue,
+            tar::EntryType::Symlink | tar::EntryType::Link => {
+                //links may only point within the package
+                let target = entry.link_name().ok().flatten().map(|t| t.to_path_buf());
+                let escapes = target.is_none_or(|target| entry_parts(&target).is_err());
+                if escapes {
+                    contents.problems.push(Problem::new(
+                        ProblemKind::Entry,
+                        Some(&name),
+                        "link points outside of the package",
+                    ));
+                }
+                continue;
+            }
+            //pax and gnu headers are handled by the tar crate
+            tar::EntryType::XGlobalHeader => continue,
+            _ => {
+                contents.problems.push(Problem::new(
+                    ProblemKind::Entry,
+                    Some(&name),
+                    "only files, directories and links are supported",
+                ));
+                continue;
+            }
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let relative = rest.join("/");
+
+        //sizes are checked against the header so that nothing too large is read
+        let size = entry.size();
+        total = total.saturating_add(size);
+        if total > limit {
+            contents.problems.push(Problem::new(
+                ProblemKind::Size,
+                Some(&name),
+                format!("the package unpacks to more than {limit} bytes"),
+            ));
+            break;
+        }
+        //info.json is always kept, it describes everything else
+        let read = if relative.ends_with(".json")
+            && size <= MAX_JSON_SIZE
+            && (relative == "info.json" || json_total.saturating_add(size) <= MAX_JSON_TOTAL)
+        {
+            json_total = json_total.saturating_add(size);
+            let mut json = Vec::with_capacity(size as usize);
+            let read = entry.read_to_end(&mut json).map(|n| n as u64);
+            contents.json.insert(relative.clone(), json);
+            read
+        } else {
+            io::copy(&mut entry, &mut io::sink())
+        };
+        match read {
+            Ok(n) if n == size => {}
+            Ok(_) => {
+                contents.problems.push(Problem::new(
+                    ProblemKind::Integrity,
+                    Some(&name),
+                    "entry is truncated",
+                ));
+                break;
+            }
+            Err(e) => {
+                contents
+                    .problems
+                    .push(Problem::new(ProblemKind::Integrity, Some(&name), e));
+                break;
+            }
+        }
+        contents.sizes.insert(relative, size);
+    }
+    Ok(contents)
+}
+
+//whether a pax header gives the entry a sparse map
+fn is_pax_sparse<R: Read>(entry: &mut tar::Entry<'_, R>) -> bool {
+    match entry.pax_extensions() {
+        Ok(Some(mut extensions)) => extensions.any(|extension| {
+            extension.is_ok_and(|e| e.key().is_ok_and(|key| key.starts_with("GNU.sparse.")))
+        }),
+        _ => false,
+    }
+}
+
+//preset names from a presets file, a list of named presets or an object keyed by name
+fn preset_names(presets: serde_json::Value) -> Vec<String> {
+    match presets {
+        serde_json::Value::Array(presets) => presets
+            .iter()
+            .filter_map(|p| p.get("name")?.as_str().map(str::to_string))
+            .collect(),
+        serde_json::Value::Object(presets) => presets.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
+
+//view names from a set file, which keeps its views keyed by index
+fn view_names(set: serde_json::Value) -> Vec<String> {
+    let views = match set.get("views") {
+        Some(serde_json::Value::Object(views)) => views.values().cloned().collect(),
+        Some(serde_json::Value::Array(views)) => views.clone(),
+        _ => Vec::new(),
+    };
+    views
+        .iter()
+        .filter_map(|v| v.get("name")?.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Read the manifest of a `.rnbopack` that unpacks to at most `limit` bytes without unpacking
+/// it. This blocks.
+pub fn inspect(path: &Path, limit: u64) -> Result<Manifest, String> {
+    let contents = scan(path, limit).map_err(|e| e.to_string())?;
+    if let Some(problem) = contents.unread() {
+        return Err(format!("not a valid package: {}", problem.error));
+    }
+    let info: Info = contents
+        .json("info.json")
+        .ok_or("the package has no readable info.json")?;
+    manifest(path, info, &contents).map_err(|e| e.to_string())
+}
+
+/// Check that a package can be read to the end, stays within its directory, describes its
+/// contents, was exported for `expected_version` when given, and unpacks to at most `limit`
+/// bytes. This blocks.
+pub fn validate(
+    path: &Path,
+    expected_version: Option<&str>,
+    limit: u64,
+) -> io::Result<PackageReport> {
+    let mut contents = scan(path, limit)?;
+    let mut problems = std::mem::take(&mut contents.problems);
+    //a package that can't be read to the end isn't described any further
+    let info: Option<Info> = if problems.iter().any(|p| p.kind == ProblemKind::Integrity) {
+        None
+    } else if !contents.sizes.contains_key("info.json") {
+        problems.push(Problem::new(
+            ProblemKind::Manifest,
+            None,
+            "the package has no info.json",
+        ));
+        None
+    } else {
+        let info = contents.json("info.json");
+        if info.is_none() {
+            problems.push(Problem::new(
+                ProblemKind::Manifest,
+                Some("info.json"),
+                "info.json can't be read",
+            ));
+        }
+        info
+    };
+
+    let manifest = match info {
+        Some(info) => {
+            let listed = info
+                .sets
+                .iter()
+                .map(|s| Some(&s.location))
+                .chain(info.patchers.iter().flat_map(|p| {
+                    [&p.patcher, &p.config, &p.presets]
+                        .into_iter()
+                        .map(Option::as_ref)
+                        .chain(p.binaries.values().map(Some))
+                }))
+                .chain(info.datafiles.iter().map(|d| Some(&d.location)))
+                .flatten();
+            for location in listed {
+                if !contents.contains(location) {
+                    problems.push(Problem::new(
+                        ProblemKind::Manifest,
+                        Some(location),
+                        "listed in info.json but not in the package",
+                    ));
+                }
+            }
+            match (expected_version, &info.rnbo_version) {
+                (Some(expected), Some(version)) if expected != version => {
+                    problems.push(Problem::new(
+                        ProblemKind::Version,
+                        None,
+                        format!("exported for RNBO {version}, not {expected}"),
+                    ));
+                }
+                (Some(_), None) => {
+                    problems.push(Problem::new(
+                        ProblemKind::Manifest,
+                        Some("info.json"),
+                        "info.json has no rnbo_version",
+                    ));
+                }
+                _ => {}
+            }
+            Some(manifest(path, info, &contents)?)
+        }
+        None => None,
+    };
+
+    Ok(PackageReport {
+        valid: problems.is_empty(),
+        expected_rnbo_version: expected_version.map(str::to_string),
+        problems,
+        manifest,
+    })
+}
+
+fn manifest(path: &Path, info: Info, contents: &Contents) -> io::Result<Manifest> {
+    let file_size = path.metadata()?.len();
+
+    let mut sizes = Sizes {
+        total: contents.sizes.values().sum(),
+        ..Default::default()
+    };
+    let mut views = Vec::new();
+    let sets = info
+        .sets
+        .into_iter()
+        .map(|set| {
+            let size = contents.size(&set.location);
+            sizes.sets += size;
+            if let Some(json) = contents.json(&set.location) {
+                views.extend(view_names(json).into_iter().map(|name| ViewItem {
+                    set: set.name.clone(),
+                    name,
+                }));
+            }
+            SetItem {
+                name: set.name,
+                uuid: set.uuid,
+                created_at: set.created_at,
+                location: set.location,
+                size,
+            }
+        })
+        .collect();
+
+    let mut presets = Vec::new();
+    let mut binaries = Vec::new();
+    let mut patchers = Vec::new();
+    for patcher in info.patchers {
+        let size = [&patcher.patcher, &patcher.config]
+            .into_iter()
+            .flatten()
+            .map(|location| contents.size(location))
+            .sum();
+        sizes.patchers += size;
+        if let Some(location) = patcher.presets {
+            let size = contents.size(&location);
+            sizes.presets += size;
+            presets.push(PresetsItem {
+                patcher: patcher.name.clone(),
+                names: contents
+                    .json(&location)
+                    .map(preset_names)
+                    .unwrap_or_default(),
+                location,
+                size,
+            });
+        }
+        for (target, location) in patcher.binaries {
+            let size = contents.size(&location);
+            sizes.binaries += size;
+            binaries.push(BinaryItem {
+                patcher: patcher.name.clone(),
+                target,
+                location,
+                size,
+            });
+        }
+        patchers.push(PatcherItem {
+            name: patcher.name,
+            uuid: patcher.uuid,
+            rnbo_version: patcher.rnbo_version,
+            created_at: patcher.created_at,
+            size,
+        });
+    }
+
+    let datafiles = info
+        .datafiles
+        .into_iter()
+        .map(|datafile| {
+            let size = contents.size(&datafile.location);
+            sizes.datafiles += size;
+            DataFileItem {
+                name: datafile.name,
+                location: datafile.location,
+                size,
+            }
+        })
+        .collect();
+
+    Ok(Manifest {
+        name: info.name,
+        rnbo_version: info.rnbo_version,
+        rnbo_compatibility_version: info.rnbo_compatibility_version,
+        runner_version: info.runner_version,
+        schema_version: info.schema_version,
+        sets,
+        patchers,
+        presets,
+        views,
+        datafiles,
+        binaries,
+        targets: info.targets,
+        sizes,
+        file_size,
+    })
+}
+
+//add the sets, patchers, datafiles and targets of `other` that `info` doesn't have yet
+fn merge_info(info: &mut serde_json::Map<String, serde_json::Value>, other: serde_json::Value) {
+    use serde_json::Value;
+
+    for (key, id) in [
+        ("sets", "name"),
+        ("patchers", "name"),
+        ("datafiles", "location"),
+    ] {
+        let Some(items) = other.get(key).and_then(Value::as_array) else {
+            continue;
+        };
+        if let Value::Array(merged) = info.entry(key).or_insert_with(|| Value::Array(Vec::new())) {
+            for item in items {
+                if !merged.iter().any(|m| m.get(id) == item.get(id)) {
+                    merged.push(item.clone());
+                }
+            }
+        }
+    }
+    if let Some(Value::Object(targets)) = other.get("targets")
+        && let Value::Object(merged) = info
+            .entry("targets")
+            .or_insert_with(|| Value::Object(Default::default()))
+    {
+        for (id, target) in targets {
+            merged.entry(id).or_insert_with(|| target.clone());
+        }
+    }
+}
+
+/// Combine `packages`, each unpacking to at most `limit` bytes, into one package called `name`
+/// at `dest`. Where they hold the same file the first package's is kept. This blocks.
+pub fn merge(packages: &[PathBuf], name: &str, dest: &Path, limit: u64) -> io::Result<()> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut info = serde_json::Map::new();
+    for (i, package) in packages.iter().enumerate() {
+        let contents = scan(package, limit)?;
+        if let Some(problem) = contents.unread() {
+            return Err(invalid(problem.error.clone()));
+        }
+        let part: serde_json::Value = contents
+            .json("info.json")
+            .filter(serde_json::Value::is_object)
+            .ok_or_else(|| invalid(format!("{} has no readable info.json", package.display())))?;
+        match part {
+            serde_json::Value::Object(part) if i == 0 => info = part,
+            part => merge_info(&mut info, part),
+        }
+    }
+    info.insert("name".to_string(), name.into());
+
+    let mut builder = tar::Builder::new(File::create(dest)?);
+    let json = serde_json::to_vec_pretty(&info)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+    );
+    builder.append_data(&mut header, format!("{name}/info.json"), json.as_slice())?;
+
+    let mut added = HashSet::from(["info.json".to_string()]);
+    for package in packages {
+        let mut archive = tar::Archive::new(File::open(package)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let Ok(parts) = entry_parts(&entry.path()?) else {
+                continue;
+            };
+            let relative = parts.get(1..).unwrap_or_default().join("/");
+            if relative.is_empty() || !added.insert(relative.clone()) {
+                continue;
+            }
+            let mut header = entry.header().clone();
+            let path = format!("{name}/{relative}");
+            match header.entry_type() {
+                tar::EntryType::Regular
+                | tar::EntryType::Continuous
+                | tar::EntryType::Directory => {
+                    builder.append_data(&mut header, path, &mut entry)?
+                }
+                tar::EntryType::Symlink => {
+                    if let Some(target) = entry.link_name()? {
+                        builder.append_link(&mut header, path, target)?;
+                    }
+                }
+                //validation refuses everything else
+                _ => {}
+            }
+        }
+    }
+    builder.into_inner()?.sync_all()
+}
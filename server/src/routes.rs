@@ -1,25 +1,38 @@
-use std::time::Duration;
-
-const PACKAGE_TIMEOUT: Duration = Duration::from_millis(2_000);
-
 mod file {
     use {
         crate::{
-            config::Config,
-            filelist::{FileList, FileListItem},
+            analysis::{self, Analysis},
+            archive::{self, ArchiveFormat, ArchiveStream, ExtractReport},
+            audio::{self, AudioInfo},
+            conditional::{ConditionalFile, Preconditions},
+            config::{Access, Config, Filetype, PanelConfig},
+            digest::{self, ContentDigest, Sha256Digest},
+            filelist::{self, FileList, FileListItem},
+            paths::{self, PathEscape, Sandbox},
+            problem::{ErrorKind, Problem},
+            rnbopack::{self, Manifest},
+            runner::{Runner, RunnerError},
+            trash::Trash,
+            uploads::{self, BodySize, ReceiveError, STAGING_DIR},
+            usage::Space,
         },
         rocket::{
-            Responder, State, delete,
-            fs::{NamedFile, TempFile},
+            FromForm, Request, Responder, State,
+            data::Data,
+            delete,
+            form::{self, FromFormField, ValueField},
             get,
             http::{ContentType, Header, Status},
+            outcome::try_outcome,
             put,
+            request::{FromRequest, Outcome},
             serde::json::Json,
             uri,
         },
         rocket_dyn_templates::{Template, context},
-        serde::Deserialize,
+        serde::Serialize,
         std::path::{Path, PathBuf},
+        uuid::Uuid,
     };
 
     // Custom responder for package files so we can attach a Content-Disposition
@@ -28,103 +41,353 @@ mod file {
     // .rnbopack name from the URL. The first field is the response body, the
     // remaining fields are emitted as headers.
     #[derive(Responder)]
-    #[response(status = 200)]
     pub struct PackageFileResponse {
-        file: NamedFile,
+        file: ConditionalFile,
+        content_type: ContentType,
+        disposition: Header<'static>,
+    }
+
+    // A directory streamed as an archive while it is being written.
+    #[derive(Responder)]
+    pub struct ArchiveResponse {
+        stream: ArchiveStream,
         content_type: ContentType,
         disposition: Header<'static>,
     }
 
+    // Why a file route refused. Bare statuses get their problem details from the catcher,
+    // anything with more to say is explained in its own.
+    #[derive(Responder)]
+    pub enum FileError {
+        Status(Status),
+        Escape(PathEscape),
+        Problem(Box<Problem>),
+    }
+
+    impl From<Problem> for FileError {
+        fn from(problem: Problem) -> Self {
+            Self::Problem(Box::new(problem))
+        }
+    }
+
+    impl From<RunnerError> for FileError {
+        fn from(e: RunnerError) -> Self {
+            Problem::from(e).into()
+        }
+    }
+
+    impl From<Status> for FileError {
+        fn from(status: Status) -> Self {
+            Self::Status(status)
+        }
+    }
+
+    impl From<form::Errors<'_>> for FileError {
+        fn from(errors: form::Errors<'_>) -> Self {
+            Problem::new(Status::BadRequest)
+                .detail(format!("invalid query: {errors}"))
+                .into()
+        }
+    }
+
+    impl From<PathEscape> for FileError {
+        fn from(escape: PathEscape) -> Self {
+            Self::Escape(escape)
+        }
+    }
+
     #[derive(Responder)]
     pub enum FileGet {
         #[response(status = 200, content_type = "json")]
         JsonListing(Json<FileList>),
         #[response(status = 200, content_type = "html")]
         HtmlListing(Template),
-        #[response(status = 200)]
-        File(NamedFile),
-        #[response(status = 200)]
+        //the status depends on the range and conditional headers
+        File(ConditionalFile),
         PackageFile(PackageFileResponse),
+        #[response(status = 200)]
+        Archive(ArchiveResponse),
+        #[response(status = 200, content_type = "json")]
+        AudioInfo(Json<AudioCheck>),
+        #[response(status = 200, content_type = "json")]
+        Analysis(Json<Analysis>),
+        #[response(status = 200, content_type = "json")]
+        Manifest(Json<Manifest>),
     }
 
-    async fn get_impl(
-        state: &State<Config>,
+    // The header of an audio file, or why it couldn't be read.
+    #[derive(Serialize)]
+    pub struct AudioCheck {
+        #[serde(flatten, skip_serializing_if = "Option::is_none")]
+        info: Option<AudioInfo>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    }
+
+    impl AudioCheck {
+        //probing reads from disk, keep it off the async workers
+        async fn probe(path: PathBuf) -> Self {
+            let info = tokio::task::spawn_blocking(move || audio::probe(&path))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            match info {
+                Ok(info) => Self {
+                    info: Some(info),
+                    error: None,
+                },
+                Err(error) => Self::failed(error),
+            }
+        }
+
+        fn failed(error: String) -> Self {
+            Self {
+                info: None,
+                error: Some(error),
+            }
+        }
+
+        //the check as it stands, or why it failed
+        fn checked(self) -> Result<Self, FileError> {
+            match &self.error {
+                Some(error) if self.info.is_none() => {
+                    Err(Problem::new(Status::UnprocessableEntity)
+                        .kind(ErrorKind::InvalidAudio)
+                        .detail(error.clone())
+                        .into())
+                }
+                _ => Ok(self),
+            }
+        }
+    }
+
+    // Escape the filename for use in a quoted Content-Disposition
+    // value: drop control characters (which could otherwise inject
+    // headers or produce a malformed response) and escape `\` and
+    // `"` per the RFC 6266 quoted-string grammar.
+    fn attachment(name: &str) -> Header<'static> {
+        let escaped: String = name
+            .chars()
+            .filter(|c| !c.is_control())
+            .flat_map(|c| match c {
+                '"' | '\\' => vec!['\\', c],
+                _ => vec![c],
+            })
+            .collect();
+        Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{escaped}\""),
+        )
+    }
+
+    //most levels a recursive listing will descend
+    const MAX_LIST_DEPTH: usize = 16;
+
+    //a query value that may be left out, unlike an Option an invalid value is an error rather
+    //than taken for a missing one
+    pub struct Param<T>(Option<T>);
+
+    impl<'v, T: FromFormField<'v>> FromFormField<'v> for Param<T> {
+        fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+            T::from_value(field).map(|v| Self(Some(v)))
+        }
+
+        fn default() -> Option<Self> {
+            Some(Self(None))
+        }
+    }
+
+    //query options for directory requests
+    #[derive(FromForm)]
+    pub struct GetQuery {
+        recursive: bool,
+        depth: Param<usize>,
+        hash: bool,
+        //download the directory as an archive instead of listing it
+        archive: Param<ArchiveFormat>,
+        //describe an audio file instead of downloading it
+        info: bool,
+        //waveform peaks and loudness of an audio file instead of the file
+        analysis: bool,
+        //what a package holds instead of the package
+        manifest: bool,
+    }
+
+    #[derive(Clone, Copy)]
+    struct ListOptions {
+        //1 lists only the requested directory
+        depth: usize,
+        hash: bool,
+    }
+
+    impl ListOptions {
+        fn new(query: &GetQuery) -> Self {
+            let depth = match (query.recursive, query.depth.0) {
+                (_, Some(depth)) => depth,
+                (true, None) => MAX_LIST_DEPTH,
+                _ => 1,
+            };
+            Self {
+                depth: depth.clamp(1, MAX_LIST_DEPTH),
+                hash: query.hash,
+            }
+        }
+    }
+
+    fn list_dir(
+        sandbox: &Sandbox,
         filetype: &str,
-        subdirs: PathBuf,
+        subdirs: &Path,
         json: bool,
-    ) -> Option<FileGet> {
-        let dir = state.filetype_path(filetype)?;
-        let fullpath = dir.join(&subdirs);
-        if fullpath.is_dir() {
-            let mut items = Vec::new();
-            let entries = std::fs::read_dir(fullpath).ok()?;
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if let Some(name) = path.file_name()
-                    && let Some(name) = name.to_str()
-                    && !name.starts_with(".")
-                {
-                    let relative = subdirs.join(name);
-                    let uri = if json {
-                        uri!("/files", get_json(filetype, relative)).to_string()
-                    } else {
-                        uri!("/files", get_html(filetype, relative)).to_string()
+        options: ListOptions,
+    ) -> Option<Vec<FileListItem>> {
+        let mut items = Vec::new();
+        let entries = std::fs::read_dir(sandbox.root().join(subdirs)).ok()?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(name) = path.file_name()
+                && let Some(name) = name.to_str()
+                && !filelist::is_hidden(name)
+                && sandbox.permits(&path)
+            {
+                let relative = subdirs.join(name);
+                let uri = if json {
+                    uri!("/files", get_json(filetype, &relative, _)).to_string()
+                } else {
+                    uri!("/files", get_html(filetype, &relative, _)).to_string()
+                };
+                let mut item = FileListItem::from_path(name, uri, &path, options.hash);
+                //linked directories are listed but not descended into, a link cycle would
+                //otherwise repeat until the depth runs out
+                if item.dir && options.depth > 1 && !paths::is_link(&path) {
+                    let options = ListOptions {
+                        depth: options.depth - 1,
+                        ..options
                     };
-                    let item = FileListItem {
-                        name: name.to_owned(),
-                        uri,
-                        dir: path.is_dir(),
-                    };
-                    items.push(item);
+                    item.items = list_dir(sandbox, filetype, &relative, json, options);
                 }
+                items.push(item);
             }
+        }
+        Some(items)
+    }
+
+    async fn get_impl(
+        state: &State<Config>,
+        panel: &PanelConfig,
+        filetype: &str,
+        subdirs: PathBuf,
+        json: bool,
+        query: GetQuery,
+        preconditions: &Preconditions,
+    ) -> Result<FileGet, FileError> {
+        let target = state.filetype(filetype, Access::Read)?;
+        let sandbox = state.sandbox(target);
+        let fullpath = sandbox.resolve(&subdirs)?;
+        let options = ListOptions::new(&query);
+        if let Some(format) = query.archive.0
+            && fullpath.is_dir()
+        {
+            let name = subdirs
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or(filetype)
+                .to_string();
+            let disposition = attachment(&format!("{name}.{}", format.extension()));
+            Ok(FileGet::Archive(ArchiveResponse {
+                stream: archive::stream(fullpath, name, format, move |p| sandbox.permits(p)),
+                content_type: format.content_type(),
+                disposition,
+            }))
+        } else if fullpath.is_dir() {
+            //walking and hashing can take a while, keep it off the async workers
+            let items = {
+                let filetype = filetype.to_string();
+                tokio::task::spawn_blocking(move || {
+                    list_dir(&sandbox, &filetype, &subdirs, json, options)
+                })
+                .await
+                .ok()
+                .flatten()
+                .ok_or(Status::NotFound)?
+            };
 
             let list = FileList::new_sorted(filetype, items);
-            Some(if json {
+            Ok(if json {
                 FileGet::JsonListing(Json(list))
             } else {
                 FileGet::HtmlListing(Template::render("filelist", context! { list }))
             })
+        } else if query.manifest {
+            if !fullpath.is_file() {
+                return Err(Status::NotFound.into());
+            }
+            if fullpath.extension().is_none_or(|e| e != "rnbopack") {
+                return Err(Status::UnsupportedMediaType.into());
+            }
+            //reads through the whole tar, keep it off the async workers
+            let limit = panel.extract_limit();
+            let manifest = tokio::task::spawn_blocking(move || rnbopack::inspect(&fullpath, limit))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            match manifest {
+                Ok(manifest) => Ok(FileGet::Manifest(Json(manifest))),
+                Err(error) => Err(Problem::new(Status::UnprocessableEntity)
+                    .kind(ErrorKind::InvalidPackage)
+                    .detail(error)
+                    .into()),
+            }
+        } else if query.analysis {
+            if !fullpath.is_file() {
+                return Err(Status::NotFound.into());
+            }
+            //decoding a whole file takes a while, keep it off the async workers.
+            //Nothing is written into directories that are only there to be read
+            let store = target.allows(Access::Write);
+            let analysis = tokio::task::spawn_blocking(move || analysis::analyze(&fullpath, store))
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()));
+            match analysis {
+                Ok(analysis) => Ok(FileGet::Analysis(Json(analysis))),
+                Err(e) => Err(Problem::new(Status::UnprocessableEntity)
+                    .kind(ErrorKind::InvalidAudio)
+                    .detail(e)
+                    .into()),
+            }
+        } else if query.info {
+            if !fullpath.is_file() {
+                return Err(Status::NotFound.into());
+            }
+            let check = AudioCheck::probe(fullpath).await.checked()?;
+            Ok(FileGet::AudioInfo(Json(check)))
         } else {
-            NamedFile::open(fullpath).await.ok().map(|f| {
-                //match extension
-                let e = f.path().extension().map(|e| {
-                    e.to_os_string()
-                        .into_string()
-                        .unwrap_or_else(|_| "".to_string())
-                });
-                match e {
-                    Some(e) if e == "rnbopack" => {
-                        let name = f
-                            .path()
-                            .file_name()
-                            .and_then(|n| n.to_str())
-                            .unwrap_or("package.rnbopack");
-                        // Escape the filename for use in a quoted Content-Disposition
-                        // value: drop control characters (which could otherwise inject
-                        // headers or produce a malformed response) and escape `\` and
-                        // `"` per the RFC 6266 quoted-string grammar.
-                        let escaped: String = name
-                            .chars()
-                            .filter(|c| !c.is_control())
-                            .flat_map(|c| match c {
-                                '"' | '\\' => vec!['\\', c],
-                                _ => vec![c],
+            ConditionalFile::open(fullpath, preconditions)
+                .await
+                .map_err(|_| FileError::from(Status::NotFound))
+                .map(|f| {
+                    //match extension
+                    let e = f.path().extension().map(|e| {
+                        e.to_os_string()
+                            .into_string()
+                            .unwrap_or_else(|_| "".to_string())
+                    });
+                    //only the file itself is described as a package download
+                    match e {
+                        Some(e) if e == "rnbopack" && f.has_body() => {
+                            let name = f
+                                .path()
+                                .file_name()
+                                .and_then(|n| n.to_str())
+                                .unwrap_or("package.rnbopack");
+                            let disposition = attachment(name);
+                            FileGet::PackageFile(PackageFileResponse {
+                                file: f,
+                                content_type: ContentType::TAR,
+                                disposition,
                             })
-                            .collect();
-                        let disposition = Header::new(
-                            "Content-Disposition",
-                            format!("attachment; filename=\"{escaped}\""),
-                        );
-                        FileGet::PackageFile(PackageFileResponse {
-                            file: f,
-                            content_type: ContentType::TAR,
-                            disposition,
-                        })
+                        }
+                        _ => FileGet::File(f),
                     }
-                    _ => FileGet::File(f),
-                }
-            })
+                })
         }
     }
 
@@ -133,10 +396,12 @@ mod file {
         let items = state
             .filetypelist()
             .iter()
-            .map(|filetype| FileListItem {
-                name: filetype.clone(),
-                uri: uri!("/files", get_html(filetype, PathBuf::default())).to_string(),
-                dir: true,
+            .map(|filetype| {
+                FileListItem::new(
+                    filetype.clone(),
+                    uri!("/files", get_html(filetype, PathBuf::default(), _)).to_string(),
+                    true,
+                )
             })
             .collect();
         let list = FileList::new_sorted("filetypes", items);
@@ -144,114 +409,592 @@ mod file {
         Template::render("filetypelist", context! { list })
     }
 
-    #[get("/<filetype>/<subdirs..>", format = "html", rank = 1)]
+    #[get("/<filetype>/<subdirs..>?<query..>", format = "html", rank = 1)]
     pub async fn get_html(
         state: &State<Config>,
+        panel: &State<PanelConfig>,
+        preconditions: Preconditions,
         filetype: &str,
         subdirs: PathBuf,
-    ) -> Option<FileGet> {
-        get_impl(state, filetype, subdirs, false).await
+        query: rocket::form::Result<'_, GetQuery>,
+    ) -> Result<FileGet, FileError> {
+        get_impl(
+            state,
+            panel,
+            filetype,
+            subdirs,
+            false,
+            query?,
+            &preconditions,
+        )
+        .await
     }
 
-    #[get("/<filetype>/<subdirs..>", format = "json", rank = 2)]
+    #[get("/<filetype>/<subdirs..>?<query..>", format = "json", rank = 2)]
     pub async fn get_json(
         state: &State<Config>,
+        panel: &State<PanelConfig>,
+        preconditions: Preconditions,
         filetype: &str,
         subdirs: PathBuf,
-    ) -> Option<FileGet> {
-        get_impl(state, filetype, subdirs, true).await
+        query: rocket::form::Result<'_, GetQuery>,
+    ) -> Result<FileGet, FileError> {
+        get_impl(
+            state,
+            panel,
+            filetype,
+            subdirs,
+            true,
+            query?,
+            &preconditions,
+        )
+        .await
     }
 
     #[delete("/<filetype>/<name..>")]
     pub async fn delete(
         state: &State<Config>,
+        panel: &State<PanelConfig>,
+        preconditions: Preconditions,
         filetype: &str,
         name: PathBuf,
-    ) -> Result<Status, Status> {
-        let dir = state
-            .deleteable_filetype_path(filetype)
-            .ok_or(Status::Unauthorized)?;
-        let path = dir.join(name);
-        if path.is_dir() {
-            if &path == dir {
-                eprintln!("cannot delete top level filetype directories");
-                return Err(Status::Forbidden);
-            }
-            tokio::fs::remove_dir_all(path)
+    ) -> Result<Status, FileError> {
+        let sandbox = state.sandbox(state.filetype(filetype, Access::Delete)?);
+        let path = sandbox.resolve(&name)?;
+        preconditions.check_write(&path)?;
+        if path == sandbox.root() {
+            eprintln!("cannot delete top level filetype directories");
+            return Err(Status::Forbidden.into());
+        }
+        //deleted files go to the trash, from where they can be restored
+        let trash = Trash::new(sandbox.root());
+        let limits = panel.trash_limits();
+        let trashed = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || trash.put(&path, &name, limits))
                 .await
-                .map_err(|_| Status::NotFound)?;
+                .map_err(|_| Status::InternalServerError)?
+        };
+        match trashed {
+            Ok(_) => {
+                analysis::remove_cache(&path);
+                Ok(Status::NoContent)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Status::NotFound.into()),
+            Err(e) => {
+                eprintln!("failed to move {} to the trash: {e}", path.display());
+                Err(Status::InternalServerError.into())
+            }
+        }
+    }
+
+    //where an upload to `name` ends up, relative to the filetype directory
+    pub(super) async fn upload_path(
+        runner: &Runner,
+        filetype: &str,
+        name: PathBuf,
+    ) -> Result<PathBuf, FileError> {
+        if filetype == "packages" && name.starts_with("current/") {
+            if name.components().count() != 2 {
+                return Err(Status::BadRequest.into());
+            }
+            let version = runner.version().await?;
+            let name = name.file_name().ok_or(Status::BadRequest)?;
+            //allow for /packages/current/filename.foo
+            Ok(Path::new(&version).join(name))
         } else {
-            tokio::fs::remove_file(path)
-                .await
-                .map_err(|_| Status::NotFound)?;
+            Ok(name)
         }
-        Ok(Status::NoContent)
     }
 
-    //helper struct to get version string
-    #[derive(Deserialize)]
-    struct VersionBody {
-        #[serde(rename = "VALUE")]
-        value: String,
+    #[derive(Responder)]
+    pub enum UploadResponse {
+        #[response(status = 201, content_type = "json")]
+        Stored(Json<StoredFile>, Header<'static>),
+        #[response(status = 201, content_type = "json")]
+        Extracted(Json<ExtractReport>),
+        Failed(FileError),
+    }
+
+    impl From<Status> for UploadResponse {
+        fn from(status: Status) -> Self {
+            Self::Failed(status.into())
+        }
+    }
+
+    impl From<FileError> for UploadResponse {
+        fn from(e: FileError) -> Self {
+            Self::Failed(e)
+        }
+    }
+
+    impl From<Problem> for UploadResponse {
+        fn from(problem: Problem) -> Self {
+            Self::Failed(problem.into())
+        }
+    }
+
+    // What was stored, audio files also report their header.
+    #[derive(Serialize)]
+    pub struct StoredFile {
+        //hex encoded
+        sha256: String,
+        #[serde(flatten, skip_serializing_if = "Option::is_none")]
+        audio: Option<AudioCheck>,
+    }
+
+    impl StoredFile {
+        pub(super) fn new(sha256: Sha256Digest, audio: Option<AudioCheck>) -> Self {
+            Self {
+                sha256: sha256.hex(),
+                audio,
+            }
+        }
+    }
+
+    // An upload that would leave less than the reserve free, sizes in bytes.
+    #[derive(Serialize)]
+    pub(super) struct InsufficientStorage {
+        free: u64,
+        reserve: u64,
+        //the announced length of the upload, if there was one
+        #[serde(skip_serializing_if = "Option::is_none")]
+        required: Option<u64>,
+    }
+
+    impl InsufficientStorage {
+        /// Refuse a body of `length` bytes that can't fit before any of it is read.
+        pub(super) fn check(
+            dir: &Path,
+            reserve: u64,
+            length: Option<u64>,
+        ) -> Result<(), FileError> {
+            match (length, Space::of(dir)) {
+                (Some(length), Some(space)) if space.free.saturating_sub(reserve) < length => {
+                    Err(Self::problem(space, reserve, Some(length)).into())
+                }
+                _ => Ok(()),
+            }
+        }
+
+        pub(super) fn problem(space: Space, reserve: u64, required: Option<u64>) -> Problem {
+            Problem::new(Status::InsufficientStorage)
+                .detail("the upload would leave too little free space")
+                .extend(&Self {
+                    free: space.free,
+                    reserve,
+                    required,
+                })
+        }
+    }
+
+    #[derive(Serialize)]
+    pub struct DigestMismatch {
+        //hex encoded, as given in Content-Digest and as received
+        expected: String,
+        sha256: String,
+    }
+
+    //unpack an uploaded archive into `dest`, relative to the filetype directory, staging it
+    //next to where it is extracted
+    async fn extract_archive(
+        target: &Filetype,
+        sandbox: Sandbox,
+        dest: PathBuf,
+        format: ArchiveFormat,
+        panel: &PanelConfig,
+        data: Data<'_>,
+        size: &BodySize,
+    ) -> Result<UploadResponse, FileError> {
+        let fulldest = sandbox.resolve(&dest)?;
+        //refused before anything is received when it says it's too large
+        if let (Some(max), Some(length)) = (target.max_upload_size, size.length)
+            && length > max
+        {
+            return Err(Status::PayloadTooLarge.into());
+        }
+        //the archive is only read, it can wait with the other uploads of the filetype
+        let staging = target.path.join(STAGING_DIR).join(format!(
+            "{}.{}",
+            Uuid::new_v4(),
+            format.extension()
+        ));
+        let staged =
+            match Staged::receive(target, staging, data, size, ContentDigest(None), panel).await {
+                Ok(staged) => staged,
+                Err(response) => return Ok(response),
+            };
+
+        let (limit, reserve) = (panel.extract_limit(), panel.disk_reserve());
+        let report = {
+            let staged = staged.path.clone();
+            let target = target.clone();
+            tokio::task::spawn_blocking(move || {
+                archive::extract(&staged, format, &fulldest, limit, reserve, |p, dir| {
+                    if !dir && !target.allows_extension(p) {
+                        return Err("file extension not allowed");
+                    }
+                    //links already in the destination could lead entries elsewhere
+                    sandbox
+                        .resolve(&dest.join(p))
+                        .map(|_| ())
+                        .map_err(|e| e.error)
+                })
+            })
+            .await
+            .map_err(|_| Status::InternalServerError)?
+        };
+        staged.discard().await;
+        let report = report.map_err(|e| {
+            eprintln!("failed to extract archive: {e}");
+            Status::UnprocessableEntity
+        })?;
+        if report.is_ok() {
+            Ok(UploadResponse::Extracted(Json(report)))
+        } else if report.disk_full {
+            let space = Space::of(&target.path).ok_or(Status::InsufficientStorage)?;
+            Err(InsufficientStorage::problem(space, reserve, None)
+                .detail("the disk filled up while extracting the archive")
+                .extend(&report)
+                .into())
+        } else if report.only_conflicts() {
+            Err(Problem::new(Status::Conflict)
+                .detail("files of the same name already exist and were left as they are")
+                .extend(&report)
+                .into())
+        } else {
+            Err(Problem::new(Status::UnprocessableEntity)
+                .kind(ErrorKind::InvalidArchive)
+                .detail("the archive could not be extracted completely")
+                .extend(&report)
+                .into())
+        }
+    }
+
+    #[put("/<filetype>/<name..>?<extract>", data = "<data>", rank = 1)]
+    pub async fn extract(
+        ctx: UploadContext<'_>,
+        size: BodySize,
+        filetype: &str,
+        name: PathBuf,
+        extract: ArchiveFormat,
+        data: Data<'_>,
+    ) -> Result<UploadResponse, FileError> {
+        //the extensions are checked for each entry
+        let target = ctx.config.filetype(filetype, Access::Write)?;
+        let dest = upload_path(ctx.runner, filetype, name).await?;
+        let sandbox = ctx.config.sandbox(target);
+        extract_archive(target, sandbox, dest, extract, ctx.panel, data, &size).await
+    }
+
+    // The managed state the upload routes need, taken as one guard.
+    pub struct UploadContext<'r> {
+        pub config: &'r Config,
+        pub panel: &'r PanelConfig,
+        pub runner: &'r Runner,
+    }
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for UploadContext<'r> {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let rocket = req.rocket();
+            match (rocket.state(), rocket.state(), rocket.state()) {
+                (Some(config), Some(panel), Some(runner)) => Outcome::Success(Self {
+                    config,
+                    panel,
+                    runner,
+                }),
+                _ => Outcome::Error((Status::InternalServerError, ())),
+            }
+        }
+    }
+
+    // The headers that say what an upload may replace and what it should be.
+    pub struct UploadHeaders {
+        pub preconditions: Preconditions,
+        pub digest: ContentDigest,
+        pub size: BodySize,
+    }
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for UploadHeaders {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let preconditions = try_outcome!(req.guard::<Preconditions>().await);
+            let digest = try_outcome!(
+                req.guard::<ContentDigest>()
+                    .await
+                    .map_error(|(status, _)| (status, ()))
+            );
+            let size = try_outcome!(req.guard::<BodySize>().await);
+            Outcome::Success(Self {
+                preconditions,
+                digest,
+                size,
+            })
+        }
+    }
+
+    //query options for plain uploads
+    #[derive(FromForm)]
+    pub struct PutQuery<'r> {
+        //only set when the format wasn't one the extract route accepts
+        extract: Option<&'r str>,
+        //refuse audio files whose header can't be read
+        validate: Option<bool>,
     }
 
-    #[put("/<filetype>/<name..>", data = "<file>")]
+    #[put("/<filetype>/<name..>?<query..>", data = "<data>", rank = 2)]
     pub async fn upload(
-        state: &State<Config>,
+        ctx: UploadContext<'_>,
+        headers: UploadHeaders,
         filetype: &str,
         name: PathBuf,
-        mut file: TempFile<'_>,
-    ) -> Result<Status, Status> {
-        let dir = state.filetype_path(filetype).ok_or(Status::BadRequest)?;
+        query: PutQuery<'_>,
+        data: Data<'_>,
+    ) -> Result<UploadResponse, FileError> {
+        if query.extract.is_some() {
+            return Err(Status::BadRequest.into());
+        }
+        let UploadHeaders {
+            preconditions,
+            digest,
+            size,
+        } = headers;
+        let target = ctx.config.filetype(filetype, Access::Write)?;
+        let relative = upload_path(ctx.runner, filetype, name).await?;
+        let fullpath = ctx.config.sandbox(target).resolve(&relative)?;
+        target.check_upload(&fullpath, size.length)?;
+        preconditions.check_write(&fullpath)?;
 
-        let fullpath = if filetype == "packages" && name.starts_with("current/") {
-            if name.components().count() != 2 {
-                return Err(Status::BadRequest);
+        let staging = Staged::path_for(&fullpath);
+        let staged = match Staged::receive(target, staging, data, &size, digest, ctx.panel).await {
+            Ok(staged) => staged,
+            Err(response) => return Ok(response),
+        };
+        let staged = match staged.check_package(filetype, &relative, ctx.panel).await {
+            Ok(staged) => staged,
+            Err(response) => return Ok(response),
+        };
+        let audio = if audio::is_audio(&fullpath) {
+            let check = AudioCheck::probe(staged.path.clone()).await;
+            if query.validate.unwrap_or(false) {
+                match check.checked() {
+                    Ok(check) => Some(check),
+                    Err(e) => {
+                        staged.discard().await;
+                        return Ok(e.into());
+                    }
+                }
+            } else {
+                Some(check)
             }
-            let body: VersionBody = reqwest::get("http://127.0.0.1:5678/rnbo/info/version?VALUE")
-                .await
-                .map_err(|_| Status::FailedDependency)?
-                .json()
-                .await
-                .map_err(|_| Status::FailedDependency)?;
-            let name = name.file_name().ok_or(Status::FailedDependency)?;
-            //allow for /packages/current/filename.foo
-            Path::new(dir).join(body.value).join(name)
         } else {
-            Path::new(dir).join(name)
+            None
         };
+        let sha256 = staged.place(&fullpath).await?;
+        Ok(UploadResponse::Stored(
+            Json(StoredFile::new(sha256, audio)),
+            sha256.header(),
+        ))
+    }
 
-        tokio::fs::create_dir_all(fullpath.parent().expect("to get parent path"))
-            .await
-            .map_err(|_| Status::FailedDependency)?;
+    // An upload received into a staging file, flushed to disk and checked against the
+    // client's digest. Files are staged next to where they go so that the rename into place
+    // stays on one filesystem, and a file is either the old one or the complete new one.
+    pub(super) struct Staged {
+        pub path: PathBuf,
+        pub sha256: Sha256Digest,
+    }
+
+    impl Staged {
+        /// A hidden name next to `fullpath` to receive an upload to it under.
+        pub(super) fn path_for(fullpath: &Path) -> PathBuf {
+            let name = fullpath
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("upload");
+            fullpath.with_file_name(format!(".{}.{name}", Uuid::new_v4()))
+        }
+
+        /// Receive the body into `path`, creating the directory it is in.
+        pub(super) async fn receive(
+            target: &Filetype,
+            path: PathBuf,
+            data: Data<'_>,
+            size: &BodySize,
+            digest: ContentDigest,
+            panel: &PanelConfig,
+        ) -> Result<Self, UploadResponse> {
+            let reserve = panel.disk_reserve();
+            InsufficientStorage::check(&target.path, reserve, size.length)?;
+
+            tokio::fs::create_dir_all(path.parent().expect("to get parent path"))
+                .await
+                .map_err(|_| Status::FailedDependency)?;
+            let limit = target.max_upload_size.unwrap_or(u64::MAX).min(size.limit);
+            match uploads::receive(data, &path, limit, reserve).await {
+                Ok(_) => {}
+                Err(ReceiveError::TooLarge) => return Err(Status::PayloadTooLarge.into()),
+                Err(ReceiveError::Full(space)) => {
+                    eprintln!("stopped upload to {}, the disk is full", path.display());
+                    return Err(InsufficientStorage::problem(space, reserve, size.length).into());
+                }
+                Err(ReceiveError::Io(e)) => {
+                    eprintln!("failed to receive upload: {e}");
+                    return Err(Status::InternalServerError.into());
+                }
+            }
+            Self::hash(path).await?.check_digest(digest).await
+        }
 
-        //persist_to doesn't work across filesystems, but it is faster
-        //so try that first and the fallback to copy_to
-        if let Err(e) = file.persist_to(&fullpath).await {
-            if e.kind() == std::io::ErrorKind::CrossesDevices {
-                file.copy_to(&fullpath)
+        /// Flush a file that has been received in full and hash it.
+        pub(super) async fn hash(path: PathBuf) -> Result<Self, Status> {
+            let sha256 = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || digest::sync_file(&path))
                     .await
-                    .map_err(|_| Status::InternalServerError)?;
-            } else {
+                    .map_err(|_| Status::InternalServerError)?
+            };
+            match sha256 {
+                Ok(sha256) => Ok(Self { path, sha256 }),
+                Err(_) => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                    Err(Status::InternalServerError)
+                }
+            }
+        }
+
+        /// Compare the upload with the digest the client sent, if it sent one.
+        pub(super) async fn check_digest(
+            self,
+            digest: ContentDigest,
+        ) -> Result<Self, UploadResponse> {
+            match digest {
+                ContentDigest(Some(expected)) if expected != self.sha256 => {
+                    let sha256 = self.sha256.hex();
+                    self.discard().await;
+                    Err(Problem::new(Status::BadRequest)
+                        .kind(ErrorKind::DigestMismatch)
+                        .detail("content digest mismatch")
+                        .extend(&DigestMismatch {
+                            expected: expected.hex(),
+                            sha256,
+                        })
+                        .into())
+                }
+                _ => Ok(self),
+            }
+        }
+
+        /// Validate the upload if it is a package, `relative` is where it goes in `filetype`.
+        pub(super) async fn check_package(
+            self,
+            filetype: &str,
+            relative: &Path,
+            panel: &PanelConfig,
+        ) -> Result<Self, UploadResponse> {
+            if relative.extension().is_none_or(|e| e != "rnbopack") {
+                return Ok(self);
+            }
+            //packages are stored in a directory named after the RNBO version they are for
+            let expected = relative
+                .parent()
+                .and_then(|p| p.components().next())
+                .and_then(|c| c.as_os_str().to_str())
+                .filter(|_| filetype == "packages")
+                .map(str::to_string);
+            let limit = panel.extract_limit();
+            let report = {
+                let path = self.path.clone();
+                tokio::task::spawn_blocking(move || {
+                    rnbopack::validate(&path, expected.as_deref(), limit)
+                })
+                .await
+                .map_err(|_| Status::InternalServerError)?
+            };
+            match report {
+                Ok(report) if report.valid => Ok(self),
+                Ok(report) => {
+                    self.discard().await;
+                    Err(Problem::new(Status::UnprocessableEntity)
+                        .kind(ErrorKind::InvalidPackage)
+                        .detail("the package is not valid")
+                        .extend(&report)
+                        .into())
+                }
+                Err(e) => {
+                    eprintln!("failed to validate package: {e}");
+                    self.discard().await;
+                    Err(Status::InternalServerError.into())
+                }
+            }
+        }
+
+        pub(super) async fn discard(self) {
+            let _ = tokio::fs::remove_file(&self.path).await;
+        }
+
+        /// Rename the upload to `fullpath`, replacing whatever is there.
+        pub(super) async fn place(self, fullpath: &Path) -> Result<Sha256Digest, Status> {
+            let parent = fullpath.parent().expect("to get parent path");
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|_| Status::FailedDependency)?;
+            if tokio::fs::rename(&self.path, fullpath).await.is_err() {
+                self.discard().await;
                 return Err(Status::InternalServerError);
             }
+            let parent = parent.to_path_buf();
+            let _ = tokio::task::spawn_blocking(move || digest::sync_dir(&parent)).await;
+            Ok(self.sha256)
         }
-        Ok(Status::Created)
     }
 }
 
 mod package {
     use {
-        super::PACKAGE_TIMEOUT,
-        rocket::{get, http::Status, response::Redirect, uri},
+        super::{
+            file::{FileError, GetQuery, Staged, UploadContext, UploadHeaders, UploadResponse},
+            job::JobAccepted,
+        },
+        crate::{
+            config::{Access, Config, PanelConfig},
+            filelist,
+            jobs::{JobError, JobHandle, JobStatus, Jobs},
+            rnbopack,
+            runner::{Runner, RunnerError, RunnerFault},
+            uploads::STAGING_DIR,
+        },
+        futures_util::{StreamExt, stream::BoxStream},
+        rocket::{
+            Either, FromForm, Request, Shutdown, State,
+            data::Data,
+            get,
+            http::{Accept, ContentType, Header, MediaType, Status},
+            post,
+            response::{
+                self, Responder,
+                stream::{Event, EventStream, TextStream},
+            },
+            serde::json::Json,
+            uri,
+        },
         serde::{Deserialize, Serialize},
-        std::path::PathBuf,
+        std::{
+            collections::HashSet,
+            path::{Path, PathBuf},
+            time::Duration,
+        },
         uuid::Uuid,
     };
 
     //packages
-    #[derive(Serialize, Default, Clone)]
-    struct PackageCreateConfig {
+    #[derive(Serialize, Deserialize, FromForm, Default, Clone)]
+    pub struct PackageCreateConfig {
         //package details
         #[serde(skip_serializing_if = "Option::is_none")]
         rnbo_version: Option<String>,
@@ -279,20 +1022,18 @@ mod package {
         config: PackageCreateConfig,
     }
 
-    #[derive(Serialize)]
     struct PackageCmd {
         method: &'static str,
-        id: Uuid,
         params: PackageParams,
     }
 
     impl PackageCmd {
-        fn all(_config: PackageCreateConfig) -> Self {
+        fn all(config: PackageCreateConfig) -> Self {
             PackageCmd {
                 method: "package_create",
-                id: Uuid::new_v4(),
                 params: PackageParams {
                     all: Some(true),
+                    config,
                     ..Default::default()
                 },
             }
@@ -301,7 +1042,6 @@ mod package {
         fn graph(name: &str, config: PackageCreateConfig) -> Self {
             PackageCmd {
                 method: "package_create",
-                id: Uuid::new_v4(),
                 params: PackageParams {
                     set: Some(name.to_string()),
                     config,
@@ -313,7 +1053,6 @@ mod package {
         fn patcher(name: &str, config: PackageCreateConfig) -> Self {
             PackageCmd {
                 method: "package_create",
-                id: Uuid::new_v4(),
                 params: PackageParams {
                     patcher: Some(name.to_string()),
                     config,
@@ -323,385 +1062,4020 @@ mod package {
         }
     }
 
-    #[derive(Deserialize)]
-    struct ErrorBody; //don't care
-    //
     #[derive(Deserialize)]
     struct ResultBody {
-        filename: String,
+        //only present once the package has been written
+        filename: Option<String>,
         progress: f32,
         //packagename: String,
         //don't care about the rest
     }
 
-    #[derive(Deserialize)]
-    struct PackageResponse {
-        id: Uuid,
-        error: Option<ErrorBody>,
-        result: Option<ResultBody>,
+    async fn create_package<F: Fn(f32)>(
+        runner: &Runner,
+        cmd: PackageCmd,
+        progress: F,
+    ) -> Result<PathBuf, JobError> {
+        let mut responses = runner.cmd(cmd.method, &cmd.params).await?;
+        //wait for responses, reporting progress until the package is written
+        loop {
+            let resp = responses.next().await?;
+            if let Some(fault) = resp.error.map(RunnerFault::from) {
+                eprintln!("error with package_create: {}", fault.message);
+                return Err(JobError::new(
+                    Status::NotFound,
+                    "the runner could not create the package",
+                )
+                .runner(fault));
+            }
+            let result: ResultBody = parse_result(resp.result, "package_create")?;
+            if result.progress >= 100.0
+                && let Some(filename) = result.filename
+            {
+                return Ok(PathBuf::from(filename));
+            }
+            progress(result.progress);
+        }
     }
 
-    async fn compute_and_redirect(cmd: PackageCmd) -> Result<Redirect, Status> {
-        use {
-            futures_util::{SinkExt, TryStreamExt},
-            reqwest_websocket::{Message, RequestBuilderExt},
-            rosc::{OscMessage, OscPacket, OscType},
-        };
-        if let Ok(res) = reqwest::Client::new()
-            .get("http://127.0.0.1:5678")
-            .upgrade()
-            .send()
-            .await
-            && let Ok(mut ws) = res.into_websocket().await
-        {
-            let id = cmd.id;
+    //a response without an error has to carry a result we understand, anything else would
+    //leave the job waiting for the runner until it times out
+    fn parse_result<T: serde::de::DeserializeOwned>(
+        result: Option<serde_json::Value>,
+        method: &str,
+    ) -> Result<T, JobError> {
+        result
+            .and_then(|result| serde_json::from_value(result).ok())
+            .ok_or_else(|| {
+                eprintln!("unexpected response to {method}");
+                RunnerError::Unavailable.into()
+            })
+    }
 
-            let cmd = serde_json::to_string(&cmd).unwrap();
-            let packet = OscPacket::Message(OscMessage {
-                addr: "/rnbo/cmd".to_string(),
-                args: vec![OscType::String(cmd)],
-            });
+    fn package_cmd(
+        packagetype: &str,
+        name: Option<&str>,
+        config: PackageCreateConfig,
+    ) -> Result<PackageCmd, Status> {
+        match (packagetype, name) {
+            ("all", _) => Ok(PackageCmd::all(config)),
+            ("graphs", Some(name)) => Ok(PackageCmd::graph(name, config)),
+            ("patchers", Some(name)) => Ok(PackageCmd::patcher(name, config)),
+            _ => Err(Status::NotFound),
+        }
+    }
 
-            //send cmd
-            if let Ok(msg) = rosc::encoder::encode(&packet) {
-                let _ = ws.send(Message::Binary(msg.into())).await;
-                //wait for response, TIMEOUT!
-                while let Some(message) =
-                    ws.try_next().await.map_err(|_| Status::FailedDependency)?
-                {
-                    if let Message::Binary(vec) = message
-                        && let Ok((_, OscPacket::Message(m))) =
-                            rosc::decoder::decode_udp(vec.as_ref())
-                        && m.addr == "/rnbo/resp"
-                        && !m.args.is_empty()
-                        && let OscType::String(resp) = &m.args[0]
-                        && let Ok(resp) = serde_json::from_str::<PackageResponse>(resp.as_str())
-                        && resp.id == id
-                    {
-                        if let Some(result) = resp.result
-                            && result.progress >= 100.0
-                        {
-                            let path = PathBuf::from(result.filename);
-                            return Ok(Redirect::to(uri!(
-                                "/files",
-                                super::file::get_html("packages", path)
-                            )));
-                        } else if let Some(_err) = resp.error {
-                            eprintln!("error with package_create");
-                            return Err(Status::NotFound);
-                        }
+    //the runner can't be told to stop a package it has started, so cancelling the job only
+    //stops waiting for it and the runner may still write the package
+    fn start(jobs: &Jobs, runner: &Runner, timeout: Duration, cmd: PackageCmd) -> JobAccepted {
+        let runner = runner.clone();
+        let status = jobs.spawn("package", move |job| async move {
+            let created = create_package(&runner, cmd, |p| job.progress(p));
+            let path = tokio::time::timeout(timeout, created).await.map_err(|_| {
+                JobError::new(Status::GatewayTimeout, "timed out waiting for the runner")
+            })??;
+            Ok(Some(
+                uri!("/files", super::file::get_html("packages", path, _)).to_string(),
+            ))
+        });
+        JobAccepted::new(status)
+    }
+
+    // Several sets and patchers to package together.
+    #[derive(Deserialize)]
+    pub struct PackageSpec {
+        #[serde(default)]
+        sets: Vec<String>,
+        #[serde(default)]
+        patchers: Vec<String>,
+        //the name of the combined package
+        name: Option<String>,
+        #[serde(flatten)]
+        config: PackageCreateConfig,
+    }
+
+    //the runner packages one item at a time, so each is packaged on its own and the
+    //packages are combined into `relative`, which is where the route found nothing
+    fn start_selection(
+        jobs: &Jobs,
+        runner: &Runner,
+        panel: &PanelConfig,
+        root: PathBuf,
+        relative: PathBuf,
+        name: String,
+        cmds: Vec<PackageCmd>,
+    ) -> JobAccepted {
+        let runner = runner.clone();
+        let (timeout, limit) = (panel.package_timeout(), panel.extract_limit());
+        let status = jobs.spawn("package", move |job| async move {
+            let conflict = |message: &str| JobError::new(Status::Conflict, message);
+            let count = cmds.len() as f32;
+            //the runner writes each item under its usual name next to the selection, packages
+            //the user already had there are overwritten but must not be removed with the new ones
+            let dest = root.join(&relative);
+            let existing = existing_files(dest.parent().expect("to get parent path")).await;
+            let mut packages = Vec::new();
+            //the single packages are only needed to make this one, whatever happens to the job
+            let mut leftovers = Leftovers(Vec::new());
+            for (i, cmd) in cmds.into_iter().enumerate() {
+                let package = create_package(&runner, cmd, |p| {
+                    //keep the last bit for combining them
+                    job.progress((i as f32 + p / 100.0) / count * 99.0)
+                });
+                let path = tokio::time::timeout(timeout, package).await.map_err(|_| {
+                    JobError::new(Status::GatewayTimeout, "timed out waiting for the runner")
+                })??;
+                let path = root.join(path);
+                //a later package written over an earlier one would be missing from the result
+                if packages.contains(&path) {
+                    return Err(conflict(
+                        "two of the items are packaged under the same name",
+                    ));
+                }
+                if !existing.contains(&path) {
+                    leftovers.0.push(path.clone());
+                }
+                packages.push(path);
+            }
+            if packages.contains(&dest) {
+                return Err(conflict(
+                    "an item is packaged under the name of the selection",
+                ));
+            }
+
+            let staging = root
+                .join(STAGING_DIR)
+                .join(format!("{}.rnbopack", Uuid::new_v4()));
+            let merged = {
+                tokio::task::spawn_blocking(move || {
+                    std::fs::create_dir_all(staging.parent().expect("to get parent path"))?;
+                    let merged =
+                        rnbopack::merge(&packages, &name, &staging, limit).and_then(|_| {
+                            //something may have been stored there while the items were packaged
+                            if dest.symlink_metadata().is_ok() {
+                                return Err(std::io::ErrorKind::AlreadyExists.into());
+                            }
+                            std::fs::rename(&staging, &dest)
+                        });
+                    if merged.is_err() {
+                        let _ = std::fs::remove_file(&staging);
                     }
+                    merged
+                })
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+            };
+            match merged {
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    Err(conflict("a package of the same name already exists"))
                 }
+                Err(e) => {
+                    eprintln!("failed to combine packages: {e}");
+                    Err(JobError::new(
+                        Status::InternalServerError,
+                        "the packages could not be combined",
+                    ))
+                }
+                Ok(()) => Ok(Some(
+                    uri!("/files", super::file::get_html("packages", relative, _)).to_string(),
+                )),
             }
-            Err(Status::FailedDependency)
-        } else {
-            Err(Status::FailedDependency)
+        });
+        JobAccepted::new(status)
+    }
+
+    #[derive(Serialize)]
+    struct InstallParams<'a> {
+        filename: &'a str,
+    }
+
+    #[derive(Deserialize)]
+    struct InstallResult {
+        #[serde(default)]
+        message: String,
+        #[serde(default)]
+        progress: f32,
+    }
+
+    async fn install_package(
+        runner: &Runner,
+        filename: &str,
+        job: &JobHandle,
+    ) -> Result<(), JobError> {
+        let mut responses = runner
+            .cmd("package_install", InstallParams { filename })
+            .await?;
+        loop {
+            let resp = responses.next().await?;
+            if let Some(fault) = resp.error.map(RunnerFault::from) {
+                eprintln!("error with package_install: {}", fault.message);
+                return Err(JobError::new(
+                    Status::UnprocessableEntity,
+                    "the runner could not install the package",
+                )
+                .runner(fault));
+            }
+            let result: InstallResult = parse_result(resp.result, "package_install")?;
+            if result.message == "completed" || result.progress >= 100.0 {
+                return Ok(());
+            }
+            job.progress(result.progress);
         }
     }
 
-    async fn get_impl(
+    //the paths of the files directly inside `dir`
+    async fn existing_files(dir: &Path) -> HashSet<PathBuf> {
+        let mut files = HashSet::new();
+        if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                files.insert(entry.path());
+            }
+        }
+        files
+    }
+
+    //files a job leaves along the way, removed unless the job gets to move them where they
+    //belong. Cancelling a job drops it wherever it is waiting, so this happens on drop
+    struct Leftovers(Vec<PathBuf>);
+
+    impl Drop for Leftovers {
+        fn drop(&mut self) {
+            for path in &self.0 {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    // The status of an install job as it changes, as server sent events or as a line of json each.
+    pub struct InstallStream {
+        stream:
+            Either<EventStream<BoxStream<'static, Event>>, TextStream<BoxStream<'static, String>>>,
+        location: Header<'static>,
+        digest: Header<'static>,
+    }
+
+    impl<'r> Responder<'r, 'r> for InstallStream {
+        fn respond_to(self, req: &'r Request<'_>) -> response::Result<'r> {
+            let mut response = match self.stream {
+                Either::Left(events) => events.respond_to(req)?,
+                Either::Right(lines) => {
+                    (ContentType::new("application", "x-ndjson"), lines).respond_to(req)?
+                }
+            };
+            response.set_header(self.location);
+            response.set_header(self.digest);
+            Ok(response)
+        }
+    }
+
+    //store an uploaded package for the runner's version and install it, streaming the progress
+    #[post("/install/<filename>", data = "<data>")]
+    pub async fn install(
+        ctx: UploadContext<'_>,
+        headers: UploadHeaders,
+        jobs: &State<Jobs>,
+        accept: Option<&Accept>,
+        filename: &str,
+        data: Data<'_>,
+        shutdown: Shutdown,
+    ) -> Result<InstallStream, UploadResponse> {
+        if !filename.ends_with(".rnbopack") || Path::new(filename).file_name().is_none() {
+            return Err(Status::BadRequest.into());
+        }
+        let (state, panel, runner) = (ctx.config, ctx.panel, ctx.runner);
+        let target = state.filetype("packages", Access::Write)?;
+        let version = runner.version().await.map_err(FileError::from)?;
+        let relative = Path::new(&version).join(filename);
+        let fullpath = state
+            .sandbox(target)
+            .resolve(&relative)
+            .map_err(|e| UploadResponse::Failed(e.into()))?;
+        target.check_upload(&fullpath, headers.size.length)?;
+        let staging = Staged::path_for(&fullpath);
+        let staged = Staged::receive(target, staging, data, &headers.size, headers.digest, panel)
+            .await?
+            .check_package("packages", &relative, panel)
+            .await?;
+        let sha256 = staged.sha256;
+
+        let status = {
+            let runner = runner.clone();
+            let timeout = panel.package_timeout();
+            let uri = uri!("/files", super::file::get_html("packages", relative, _)).to_string();
+            jobs.spawn("install", move |job| async move {
+                //the runner installs it from its hidden staging name, a package that is
+                //already there is only replaced once the install has succeeded
+                let _leftovers = Leftovers(vec![staged.path.clone()]);
+                let installing = staged
+                    .path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or_default()
+                    .to_string();
+                tokio::time::timeout(timeout, install_package(&runner, &installing, &job))
+                    .await
+                    .map_err(|_| {
+                        JobError::new(Status::GatewayTimeout, "timed out waiting for the runner")
+                    })??;
+                staged.place(&fullpath).await.map_err(|status| {
+                    JobError::new(status, "the installed package could not be stored")
+                })?;
+                Ok(Some(uri))
+            })
+        };
+        let location = Header::new(
+            "Location",
+            uri!("/jobs", super::job::get(status.id)).to_string(),
+        );
+        let updates = jobs
+            .updates(status.id)
+            .ok_or(Status::InternalServerError)?
+            .take_until(shutdown);
+        let events = accept.is_some_and(|a| a.preferred().media_type() == &MediaType::EventStream);
+        let stream = if events {
+            Either::Left(EventStream::from(
+                updates.map(|s: JobStatus| Event::json(&s)).boxed(),
+            ))
+        } else {
+            Either::Right(TextStream::from(
+                updates
+                    .map(|s: JobStatus| serde_json::to_string(&s).unwrap_or_default() + "\n")
+                    .boxed(),
+            ))
+        };
+        Ok(InstallStream {
+            stream,
+            location,
+            digest: sha256.header(),
+        })
+    }
+
+    //like the POST, packaging can take longer than a request should be held open
+    #[get("/<packagetype>/<name>?<config..>")]
+    pub fn get(
+        jobs: &State<Jobs>,
+        runner: &State<Runner>,
+        panel: &State<PanelConfig>,
         packagetype: &str,
-        name: Option<&str>,
+        name: &str,
         config: PackageCreateConfig,
-    ) -> Result<Redirect, Status> {
-        let cmd = match packagetype {
-            "all" => PackageCmd::all(config),
-            "graphs" if name.is_some() => PackageCmd::graph(name.unwrap(), config),
-            "patchers" if name.is_some() => PackageCmd::patcher(name.unwrap(), config),
-            _ => return Err(Status::NotFound),
-        };
+    ) -> Result<JobAccepted, Status> {
+        package_cmd(packagetype, Some(name), config)
+            .map(|cmd| start(jobs, runner, panel.package_timeout(), cmd))
+    }
 
-        tokio::time::timeout(PACKAGE_TIMEOUT, compute_and_redirect(cmd))
-            .await
-            .map_err(|_| Status::GatewayTimeout)?
+    #[get("/all?<config..>")]
+    pub fn get_all(
+        jobs: &State<Jobs>,
+        runner: &State<Runner>,
+        panel: &State<PanelConfig>,
+        config: PackageCreateConfig,
+    ) -> Result<JobAccepted, Status> {
+        package_cmd("all", None, config)
+            .map(|cmd| start(jobs, runner, panel.package_timeout(), cmd))
     }
 
-    #[get(
-        "/<packagetype>/<name>?<rnbo_version>&<include_presets>&<include_views>&<include_binaries>&<include_datafiles>"
-    )]
-    pub async fn get(
+    #[post("/<packagetype>/<name>?<config..>")]
+    pub fn post(
+        jobs: &State<Jobs>,
+        runner: &State<Runner>,
+        panel: &State<PanelConfig>,
         packagetype: &str,
         name: &str,
-        rnbo_version: Option<&str>,
-        include_presets: Option<bool>,
-        include_views: Option<bool>,
-        include_binaries: Option<bool>,
-        include_datafiles: Option<bool>,
-    ) -> Result<Redirect, Status> {
-        let config = PackageCreateConfig {
-            rnbo_version: rnbo_version.map(|s| s.to_string()),
-            include_presets,
-            include_views,
-            include_binaries,
-            include_datafiles,
-        };
-        return get_impl(packagetype, Some(name), config).await;
+        config: PackageCreateConfig,
+    ) -> Result<JobAccepted, Status> {
+        package_cmd(packagetype, Some(name), config)
+            .map(|cmd| start(jobs, runner, panel.package_timeout(), cmd))
     }
 
-    #[get(
-        "/all?<rnbo_version>&<include_presets>&<include_views>&<include_binaries>&<include_datafiles>"
-    )]
-    pub async fn get_all(
-        rnbo_version: Option<&str>,
-        include_presets: Option<bool>,
-        include_views: Option<bool>,
-        include_binaries: Option<bool>,
-        include_datafiles: Option<bool>,
-    ) -> Result<Redirect, Status> {
-        let config = PackageCreateConfig {
-            rnbo_version: rnbo_version.map(|s| s.to_string()),
-            include_presets,
-            include_views,
-            include_binaries,
-            include_datafiles,
-        };
-        return get_impl("all", None, config).await;
+    #[post("/", format = "json", data = "<spec>")]
+    pub async fn post_selection(
+        state: &State<Config>,
+        jobs: &State<Jobs>,
+        runner: &State<Runner>,
+        panel: &State<PanelConfig>,
+        spec: Json<PackageSpec>,
+    ) -> Result<JobAccepted, FileError> {
+        let spec = spec.into_inner();
+        let name = spec.name.unwrap_or_else(|| "selection".to_string());
+        if Path::new(&name).file_name() != Some(name.as_ref()) || filelist::is_hidden(&name) {
+            return Err(Status::BadRequest.into());
+        }
+        let cmds: Vec<PackageCmd> = spec
+            .sets
+            .iter()
+            .map(|set| PackageCmd::graph(set, spec.config.clone()))
+            .chain(
+                spec.patchers
+                    .iter()
+                    .map(|patcher| PackageCmd::patcher(patcher, spec.config.clone())),
+            )
+            .collect();
+        let timeout = panel.package_timeout();
+        Ok(match cmds.len() {
+            0 => return Err(Status::BadRequest.into()),
+            1 => start(
+                jobs,
+                runner,
+                timeout,
+                cmds.into_iter().next().expect("to get cmd"),
+            ),
+            _ => {
+                let root = state.filetype("packages", Access::Write)?.path.clone();
+                //stored with the packages of the runner's version, never over one of them
+                let version = runner.version().await?;
+                let relative = Path::new(&version).join(format!("{name}.rnbopack"));
+                if root.join(&relative).symlink_metadata().is_ok() {
+                    return Err(Status::Conflict.into());
+                }
+                start_selection(jobs, runner, panel, root, relative, name, cmds)
+            }
+        })
+    }
+
+    #[post("/all?<config..>")]
+    pub fn post_all(
+        jobs: &State<Jobs>,
+        runner: &State<Runner>,
+        panel: &State<PanelConfig>,
+        config: PackageCreateConfig,
+    ) -> JobAccepted {
+        start(
+            jobs,
+            runner,
+            panel.package_timeout(),
+            PackageCmd::all(config),
+        )
     }
 }
 
-pub fn file_routes() -> Vec<rocket::Route> {
-    rocket::routes![
-        file::get_filetypes,
-        file::get_html,
-        file::get_json,
-        file::upload,
-        file::delete
-    ]
+mod upload {
+    use {
+        super::file::{FileError, InsufficientStorage, Staged, StoredFile, UploadResponse},
+        crate::{
+            config::{Access, Config, PanelConfig},
+            digest::ContentDigest,
+            runner::Runner,
+            uploads::{BodySize, ReceiveError, Upload, UploadSession, Uploads},
+        },
+        rocket::{
+            Request, Responder, State,
+            data::Data,
+            delete, get,
+            http::{Header, Status},
+            patch, post,
+            request::{FromRequest, Outcome},
+            serde::json::Json,
+            uri,
+        },
+        std::path::PathBuf,
+        uuid::Uuid,
+    };
+
+    fn header_u64(req: &Request<'_>, name: &str) -> Option<Result<u64, Status>> {
+        req.headers()
+            .get_one(name)
+            .map(|v| v.trim().parse::<u64>().map_err(|_| Status::BadRequest))
+    }
+
+    //the offset the client thinks the upload is at, required when appending
+    pub struct UploadOffset(u64);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for UploadOffset {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            match header_u64(req, "Upload-Offset") {
+                Some(Ok(offset)) => Outcome::Success(UploadOffset(offset)),
+                Some(Err(status)) => Outcome::Error((status, ())),
+                None => Outcome::Error((Status::BadRequest, ())),
+            }
+        }
+    }
+
+    //the total size of an upload, optional when creating one
+    pub struct UploadLength(Option<u64>);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for UploadLength {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            match header_u64(req, "Upload-Length") {
+                Some(Ok(length)) => Outcome::Success(UploadLength(Some(length))),
+                Some(Err(status)) => Outcome::Error((status, ())),
+                None => Outcome::Success(UploadLength(None)),
+            }
+        }
+    }
+
+    #[derive(Responder)]
+    #[response(content_type = "json")]
+    pub struct SessionResponse {
+        session: Json<UploadSession>,
+        offset: Header<'static>,
+        location: Header<'static>,
+    }
+
+    impl From<UploadSession> for SessionResponse {
+        fn from(session: UploadSession) -> Self {
+            Self {
+                offset: Header::new("Upload-Offset", session.offset.to_string()),
+                location: Header::new("Location", uri!("/files", status(session.id)).to_string()),
+                session: Json(session),
+            }
+        }
+    }
+
+    async fn open(state: &Config, id: Uuid) -> Result<Upload, Status> {
+        Upload::open(state.paths(), id)
+            .await
+            .ok_or(Status::NotFound)
+    }
+
+    #[post("/<filetype>/<name..>")]
+    pub async fn create(
+        state: &State<Config>,
+        runner: &State<Runner>,
+        filetype: &str,
+        name: PathBuf,
+        length: UploadLength,
+    ) -> Result<(Status, SessionResponse), FileError> {
+        let target = state.filetype(filetype, Access::Write)?;
+        let path = super::file::upload_path(runner, filetype, name).await?;
+        if path.file_name().is_none() {
+            return Err(Status::BadRequest.into());
+        }
+        target.check_upload(&path, length.0)?;
+        state.sandbox(target).resolve(&path)?;
+        let upload = Upload::create(&target.path, filetype, path, length.0)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        Ok((Status::Created, upload.session.into()))
+    }
+
+    #[get("/uploads/<id>")]
+    pub async fn status(state: &State<Config>, id: Uuid) -> Result<SessionResponse, Status> {
+        Ok(open(state, id).await?.session.into())
+    }
+
+    #[patch("/uploads/<id>", data = "<data>")]
+    pub async fn append(
+        state: &State<Config>,
+        panel: &State<PanelConfig>,
+        uploads: &State<Uploads>,
+        size: BodySize,
+        id: Uuid,
+        offset: UploadOffset,
+        data: Data<'_>,
+    ) -> Result<SessionResponse, FileError> {
+        let _lock = uploads.lock(id).ok_or(Status::Conflict)?;
+        let mut upload = open(state, id).await?;
+        if upload.session.offset != offset.0 {
+            return Err(Status::Conflict.into());
+        }
+        //uploads without a length are held to the filetype's limit as they grow
+        let target = state.filetype(&upload.session.filetype, Access::Write)?;
+        let mut limit = size.limit;
+        if let Some(max) = target.max_upload_size {
+            limit = limit.min(max.saturating_sub(upload.session.offset));
+        }
+        let reserve = panel.disk_reserve();
+        InsufficientStorage::check(&target.path, reserve, size.length)?;
+        upload
+            .append(data, limit, reserve)
+            .await
+            .map_err(|e| match e {
+                ReceiveError::TooLarge => Status::PayloadTooLarge.into(),
+                ReceiveError::Full(space) => {
+                    eprintln!("stopped upload {id}, the disk is full");
+                    InsufficientStorage::problem(space, reserve, size.length).into()
+                }
+                ReceiveError::Io(e) => {
+                    eprintln!("failed to append to upload {id}: {e}");
+                    FileError::from(Status::InternalServerError)
+                }
+            })?;
+        Ok(upload.session.into())
+    }
+
+    //the upload is checked like one that arrived in a single PUT before it is moved into place,
+    //one that fails the checks is abandoned
+    #[post("/uploads/<id>")]
+    pub async fn finish(
+        state: &State<Config>,
+        panel: &State<PanelConfig>,
+        uploads: &State<Uploads>,
+        digest: ContentDigest,
+        id: Uuid,
+    ) -> Result<UploadResponse, FileError> {
+        let _lock = uploads.lock(id).ok_or(Status::Conflict)?;
+        let upload = open(state, id).await?;
+        if !upload.is_complete() {
+            return Err(Status::Conflict.into());
+        }
+        //links may have appeared since the upload was created
+        let session = &upload.session;
+        let target = state.filetype(&session.filetype, Access::Write)?;
+        state.sandbox(target).resolve(&session.path)?;
+        let checked = match Staged::hash(upload.part_path()).await {
+            Ok(staged) => match staged.check_digest(digest).await {
+                Ok(staged) => {
+                    staged
+                        .check_package(&session.filetype, &session.path, panel)
+                        .await
+                }
+                Err(response) => Err(response),
+            },
+            Err(status) => Err(status.into()),
+        };
+        let sha256 = match checked {
+            Ok(staged) => staged.sha256,
+            Err(response) => {
+                let _ = upload.abort().await;
+                return Ok(response);
+            }
+        };
+        upload
+            .finish()
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        Ok(UploadResponse::Stored(
+            Json(StoredFile::new(sha256, None)),
+            sha256.header(),
+        ))
+    }
+
+    #[delete("/uploads/<id>")]
+    pub async fn abort(
+        state: &State<Config>,
+        uploads: &State<Uploads>,
+        id: Uuid,
+    ) -> Result<Status, Status> {
+        let _lock = uploads.lock(id).ok_or(Status::Conflict)?;
+        open(state, id)
+            .await?
+            .abort()
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        Ok(Status::NoContent)
+    }
 }
 
-pub fn package_routes() -> Vec<rocket::Route> {
-    rocket::routes![package::get, package::get_all]
+mod trash {
+    use {
+        super::file::FileError,
+        crate::{
+            config::{Access, Config, PanelConfig},
+            trash::{Trash, TrashItem},
+        },
+        rocket::{State, delete, get, http::Status, post, serde::json::Json},
+        std::io,
+        uuid::Uuid,
+    };
+
+    //the trash works on the filesystem directly, keep it off the async workers
+    async fn blocking<T: Send + 'static>(
+        f: impl FnOnce() -> io::Result<T> + Send + 'static,
+    ) -> Result<T, Status> {
+        tokio::task::spawn_blocking(f)
+            .await
+            .map_err(|_| Status::InternalServerError)?
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => Status::NotFound,
+                io::ErrorKind::AlreadyExists => Status::Conflict,
+                _ => {
+                    eprintln!("trash error: {e}");
+                    Status::InternalServerError
+                }
+            })
+    }
+
+    #[get("/trash/<filetype>")]
+    pub async fn list(
+        state: &State<Config>,
+        panel: &State<PanelConfig>,
+        filetype: &str,
+    ) -> Result<Json<Vec<TrashItem>>, FileError> {
+        let trash = Trash::new(&state.filetype(filetype, Access::Read)?.path);
+        //expired items go without waiting for something else to be deleted
+        let limits = panel.trash_limits();
+        let items = blocking(move || {
+            trash.purge(limits, None);
+            Ok(trash.list())
+        })
+        .await?;
+        Ok(Json(items))
+    }
+
+    #[post("/trash/<filetype>/<id>")]
+    pub async fn restore(
+        state: &State<Config>,
+        filetype: &str,
+        id: Uuid,
+    ) -> Result<Json<TrashItem>, FileError> {
+        let target = state.filetype(filetype, Access::Write)?;
+        let trash = Trash::new(&target.path);
+        let item = trash.get(id).map_err(|_| Status::NotFound)?;
+        //links may have appeared where the item used to be
+        let dest = state.sandbox(target).resolve(&item.path)?;
+        blocking(move || trash.restore(id, &dest)).await?;
+        Ok(Json(item))
+    }
+
+    #[delete("/trash/<filetype>/<id>")]
+    pub async fn purge(
+        state: &State<Config>,
+        filetype: &str,
+        id: Uuid,
+    ) -> Result<Status, FileError> {
+        let trash = Trash::new(&state.filetype(filetype, Access::Delete)?.path);
+        blocking(move || trash.remove(id)).await?;
+        Ok(Status::NoContent)
+    }
+
+    #[delete("/trash/<filetype>")]
+    pub async fn empty(state: &State<Config>, filetype: &str) -> Result<Status, FileError> {
+        let trash = Trash::new(&state.filetype(filetype, Access::Delete)?.path);
+        blocking(move || trash.empty()).await?;
+        Ok(Status::NoContent)
+    }
 }
 
-#[cfg(test)]
-mod test {
+mod ops {
     use {
-        rocket::{
-            http::{Accept, ContentType, Status},
-            local::blocking::Client,
+        super::{
+            file::{FileError, GetQuery},
+            job::JobAccepted,
         },
-        rocket_dyn_templates::Template,
+        crate::{
+            analysis, audio,
+            config::{Access, Config},
+            filelist::FileListItem,
+            jobs::{JobError, JobHandle, Jobs},
+            paths::{self, Sandbox},
+            processing::{Operation, Processing},
+            uploads::STAGING_DIR,
+        },
+        rocket::{State, http::Status, post, serde::json::Json, uri},
+        serde::Deserialize,
         std::{
-            collections::{HashMap, HashSet},
-            fs,
+            io,
+            path::{Component, Path, PathBuf},
         },
-        tempdir::TempDir,
+        uuid::Uuid,
     };
 
-    const CURRENT_RNBO_VERSION: &'static str = "1.2.3";
+    //what to do when the destination already exists
+    #[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum OnConflict {
+        #[default]
+        Fail,
+        Overwrite,
+    }
+
+    #[derive(Deserialize)]
+    pub struct TransferRequest {
+        filetype: String,
+        //both relative to the filetype directory
+        from: String,
+        to: String,
+        #[serde(default)]
+        on_conflict: OnConflict,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ProcessRequest {
+        //both relative to the datafiles directory, the result is always a wav file
+        from: String,
+        to: String,
+        #[serde(default)]
+        on_conflict: OnConflict,
+        operations: Vec<Operation>,
+        //write 32 bit float samples instead of keeping the source bit depth
+        #[serde(default)]
+        float: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub struct MkdirRequest {
+        filetype: String,
+        path: String,
+    }
+
+    //a path inside a filetype directory, refusing anything that would leave it or touch
+    //hidden files such as the upload staging directory
+    fn relative_path(path: &str) -> Result<PathBuf, Status> {
+        let mut relative = PathBuf::new();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(c) if !c.to_str().is_some_and(crate::filelist::is_hidden) => {
+                    relative.push(c)
+                }
+                Component::CurDir => {}
+                _ => return Err(Status::BadRequest),
+            }
+        }
+        //the filetype directory itself can't be moved, copied or created
+        if relative.as_os_str().is_empty() {
+            return Err(Status::BadRequest);
+        }
+        Ok(relative)
+    }
+
+    //links inside a copied directory are followed only as far as the sandbox allows,
+    //the ones it refuses are left out of the copy
+    fn copy_recursive(sandbox: &Sandbox, from: &Path, to: &Path) -> io::Result<()> {
+        if from.is_dir() {
+            std::fs::create_dir_all(to)?;
+            for entry in std::fs::read_dir(from)? {
+                let entry = entry?;
+                if sandbox.permits(&entry.path()) {
+                    copy_recursive(sandbox, &entry.path(), &to.join(entry.file_name()))?;
+                }
+            }
+        } else {
+            std::fs::copy(from, to)?;
+        }
+        Ok(())
+    }
+
+    async fn remove(path: &Path) -> io::Result<()> {
+        if path.is_dir() && !paths::is_link(path) {
+            tokio::fs::remove_dir_all(path).await
+        } else {
+            tokio::fs::remove_file(path).await
+        }
+    }
+
+    //a hidden path next to `path`, for things on their way in or out
+    fn sibling(path: &Path) -> PathBuf {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!(".{name}.{}", Uuid::new_v4()))
+    }
+
+    //put `staged` where `dest` is. A file replaces a file in one rename, anything else
+    //moves the destination aside first and only removes it once the new one is in place
+    async fn replace(staged: &Path, dest: &Path) -> io::Result<()> {
+        let is_dir = |path: &Path| path.is_dir() && !paths::is_link(path);
+        if dest.symlink_metadata().is_err() || !(is_dir(staged) || is_dir(dest)) {
+            return tokio::fs::rename(staged, dest).await;
+        }
+        let old = sibling(dest);
+        tokio::fs::rename(dest, &old).await?;
+        if let Err(e) = tokio::fs::rename(staged, dest).await {
+            let _ = tokio::fs::rename(&old, dest).await;
+            return Err(e);
+        }
+        if let Err(e) = remove(&old).await {
+            eprintln!("failed to remove replaced {}: {e}", dest.display());
+        }
+        Ok(())
+    }
+
+    //resolve both ends of a move or copy and make room at the destination,
+    //moving takes the source away so it also needs delete access
+    async fn prepare(
+        state: &Config,
+        req: &TransferRequest,
+        access: Access,
+    ) -> Result<(PathBuf, PathBuf, PathBuf, Sandbox), FileError> {
+        state.filetype(&req.filetype, access)?;
+        let target = state.filetype(&req.filetype, Access::Write)?;
+        let sandbox = state.sandbox(target);
+        let (from, to) = (relative_path(&req.from)?, relative_path(&req.to)?);
+        //neither end may contain the other, overwriting a parent would remove the source
+        if to.starts_with(&from) || from.starts_with(&to) {
+            eprintln!("cannot move or copy a path into itself");
+            return Err(Status::BadRequest.into());
+        }
+        let (src, dest) = (sandbox.resolve(&from)?, sandbox.resolve(&to)?);
+        if !src.exists() {
+            return Err(Status::NotFound.into());
+        }
+        if src.is_file() {
+            target.check_upload(&dest, None)?;
+        }
+        //an existing destination is only replaced once the new one is ready
+        if dest.symlink_metadata().is_ok() && req.on_conflict == OnConflict::Fail {
+            return Err(Status::Conflict.into());
+        }
+        tokio::fs::create_dir_all(dest.parent().expect("to get parent path"))
+            .await
+            .map_err(|_| Status::FailedDependency)?;
+        Ok((src, dest, to, sandbox))
+    }
+
+    fn created(filetype: &str, relative: PathBuf, path: &Path) -> (Status, Json<FileListItem>) {
+        let name = relative
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let uri = uri!("/files", super::file::get_html(filetype, &relative, _)).to_string();
+        (
+            Status::Created,
+            Json(FileListItem::from_path(name, uri, path, false)),
+        )
+    }
+
+    #[post("/move", format = "json", data = "<req>")]
+    pub async fn rename(
+        state: &State<Config>,
+        req: Json<TransferRequest>,
+    ) -> Result<(Status, Json<FileListItem>), FileError> {
+        let (src, dest, to, _) = prepare(state, &req, Access::Delete).await?;
+        replace(&src, &dest)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        //directories take the analyses of what's inside along
+        analysis::move_cache(&src, &dest);
+        Ok(created(&req.filetype, to, &dest))
+    }
+
+    #[post("/copy", format = "json", data = "<req>")]
+    pub async fn copy(
+        state: &State<Config>,
+        req: Json<TransferRequest>,
+    ) -> Result<(Status, Json<FileListItem>), FileError> {
+        let (src, dest, to, sandbox) = prepare(state, &req, Access::Read).await?;
+        //the copy is made next to the destination and then swapped in
+        let staged = sibling(&dest);
+        let mut copied = {
+            let staged = staged.clone();
+            tokio::task::spawn_blocking(move || copy_recursive(&sandbox, &src, &staged))
+                .await
+                .map_err(io::Error::other)
+                .and_then(|copied| copied)
+        };
+        if copied.is_ok() {
+            copied = replace(&staged, &dest).await;
+        }
+        if let Err(e) = copied {
+            eprintln!("failed to copy: {e}");
+            if staged.symlink_metadata().is_ok() {
+                let _ = remove(&staged).await;
+            }
+            return Err(Status::InternalServerError.into());
+        }
+        analysis::remove_cache(&dest);
+        Ok(created(&req.filetype, to, &dest))
+    }
+
+    //runs on a blocking thread, the result is staged and then renamed over the destination.
+    //Aborting the job doesn't stop the thread, so it checks for a cancel as it goes and
+    //leaves the destination alone once there has been one
+    fn process_file(
+        src: &Path,
+        dest: &Path,
+        staging: &Path,
+        req: &ProcessRequest,
+        bit_depth: u16,
+        job: &JobHandle,
+    ) -> Result<(), JobError> {
+        let failed = |e: io::Error| JobError::new(Status::InternalServerError, e.to_string());
+        let cancelled = || JobError::new(Status::Conflict, "cancelled");
+        let progress = |progress| {
+            job.progress(progress);
+            !job.is_cancelled()
+        };
+        let processing = Processing::new(src, &req.operations, progress)
+            .map_err(|e| JobError::new(Status::UnprocessableEntity, e))?;
+
+        std::fs::create_dir_all(staging).map_err(failed)?;
+        let tmp = staging.join(format!("{}.wav", Uuid::new_v4()));
+        let written = processing
+            .write_wav(&tmp, req.float, bit_depth)
+            .map_err(|e| JobError::new(Status::UnprocessableEntity, e))
+            .and_then(|_| {
+                if job.is_cancelled() {
+                    return Err(cancelled());
+                }
+                if dest.exists() && req.on_conflict == OnConflict::Fail {
+                    return Err(JobError::new(Status::Conflict, "destination exists"));
+                }
+                std::fs::create_dir_all(dest.parent().expect("to get parent path"))
+                    .map_err(failed)?;
+                std::fs::rename(&tmp, dest).map_err(failed)
+            });
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        written
+    }
+
+    #[post("/process", format = "json", data = "<req>")]
+    pub async fn process(
+        state: &State<Config>,
+        jobs: &State<Jobs>,
+        req: Json<ProcessRequest>,
+    ) -> Result<JobAccepted, FileError> {
+        let target = state.filetype("datafiles", Access::Write)?;
+        let dir = &target.path;
+        let (from, to) = (relative_path(&req.from)?, relative_path(&req.to)?);
+        if !to
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("wav"))
+        {
+            eprintln!("processed audio can only be written to a .wav file");
+            return Err(Status::BadRequest.into());
+        }
+        target.check_upload(&to, None)?;
+        let sandbox = state.sandbox(target);
+        let (src, dest) = (sandbox.resolve(&from)?, sandbox.resolve(&to)?);
+        if !src.is_file() {
+            return Err(Status::NotFound.into());
+        }
+        //fail early, the job checks again before it replaces anything
+        if dest.exists() && req.on_conflict == OnConflict::Fail {
+            return Err(Status::Conflict.into());
+        }
+        let info = {
+            let src = src.clone();
+            tokio::task::spawn_blocking(move || audio::probe(&src))
+                .await
+                .map_err(|_| Status::InternalServerError)?
+                .map_err(|e| {
+                    eprintln!("cannot process {}: {e}", req.from);
+                    Status::UnprocessableEntity
+                })?
+        };
+        //integer output keeps the source bit depth, rounded up to what wav players expect
+        let bit_depth = match info.bit_depth {
+            Some(b) if b <= 16 => 16,
+            Some(b) if b <= 24 => 24,
+            Some(_) => 32,
+            None => 24,
+        };
+
+        let req = req.into_inner();
+        let staging = dir.join(STAGING_DIR);
+        let status = jobs.spawn("process", move |job| async move {
+            tokio::task::spawn_blocking(move || {
+                process_file(&src, &dest, &staging, &req, bit_depth, &job)
+            })
+            .await
+            .map_err(|e| JobError::new(Status::InternalServerError, e.to_string()))??;
+            Ok(Some(
+                uri!("/files", super::file::get_html("datafiles", &to, _)).to_string(),
+            ))
+        });
+        Ok(JobAccepted::new(status))
+    }
+
+    #[post("/mkdir", format = "json", data = "<req>")]
+    pub async fn mkdir(
+        state: &State<Config>,
+        req: Json<MkdirRequest>,
+    ) -> Result<(Status, Json<FileListItem>), FileError> {
+        let sandbox = state.sandbox(state.filetype(&req.filetype, Access::Write)?);
+        let path = relative_path(&req.path)?;
+        let fullpath = sandbox.resolve(&path)?;
+        if fullpath.symlink_metadata().is_ok() {
+            return Err(Status::Conflict.into());
+        }
+        tokio::fs::create_dir_all(&fullpath)
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        Ok(created(&req.filetype, path, &fullpath))
+    }
+}
+
+mod events {
+    use {
+        crate::watch::{Change, FsWatch},
+        rocket::{
+            Shutdown, State, get,
+            response::stream::{Event, EventStream},
+        },
+        tokio::sync::broadcast::error::RecvError,
+    };
+
+    //server sent events for every change in the filetype directories
+    #[get("/events")]
+    pub fn events(watch: &State<FsWatch>, mut shutdown: Shutdown) -> EventStream![] {
+        let mut rx = watch.subscribe();
+        EventStream! {
+            loop {
+                let change = tokio::select! {
+                    change = rx.recv() => match change {
+                        Ok(change) => change,
+                        //this client fell behind, it has to catch up with a listing
+                        Err(RecvError::Lagged(_)) => Change::rescan(),
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = &mut shutdown => break,
+                };
+                yield Event::json(&change);
+            }
+        }
+    }
+}
+
+mod usage {
+    use {
+        crate::{
+            config::Config,
+            usage::{DirUsage, DiskUsage, Space},
+        },
+        rocket::{FromForm, State, get, http::Status, serde::json::Json},
+        serde::Serialize,
+        std::collections::BTreeMap,
+    };
+
+    #[derive(FromForm)]
+    pub struct UsageQuery {
+        //walk the directories again even if nothing changed
+        refresh: Option<bool>,
+    }
+
+    #[derive(Serialize)]
+    pub struct FiletypeUsage {
+        #[serde(flatten)]
+        usage: DirUsage,
+        //of the filesystem the directory is on
+        #[serde(skip_serializing_if = "Option::is_none")]
+        filesystem: Option<Space>,
+    }
+
+    //sizes of the filetype directories and the space left for them
+    #[get("/usage?<query..>")]
+    pub async fn usage(
+        state: &State<Config>,
+        usage: &State<DiskUsage>,
+        query: UsageQuery,
+    ) -> Result<Json<BTreeMap<String, FiletypeUsage>>, Status> {
+        let mut filetypes = BTreeMap::new();
+        for (filetype, path) in state.filetype_paths() {
+            let cached = if query.refresh.unwrap_or(false) {
+                None
+            } else {
+                usage.cached(&filetype)
+            };
+            let (walked, filesystem) = tokio::task::spawn_blocking(move || {
+                let walked = cached.ok_or_else(|| DirUsage::walk(&path));
+                (walked, Space::of(&path))
+            })
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+            //only fresh walks are stored, so that cached ones still expire
+            let walked = walked.unwrap_or_else(|walked| {
+                usage.store(&filetype, walked.clone());
+                walked
+            });
+            filetypes.insert(
+                filetype,
+                FiletypeUsage {
+                    usage: walked,
+                    filesystem,
+                },
+            );
+        }
+        Ok(Json(filetypes))
+    }
+}
+
+mod cache {
+    use {
+        super::file::FileError,
+        crate::{
+            cache::{self, CACHE_FILETYPES, CacheEntry, GcReport},
+            config::{Access, Config},
+            runner::Runner,
+        },
+        rocket::{State, get, http::Status, post, serde::json::Json},
+        std::{collections::BTreeMap, path::PathBuf},
+    };
+
+    //the cache directories that may be looked at, hidden ones are left alone
+    fn cache_dirs(state: &Config) -> Vec<(String, PathBuf)> {
+        CACHE_FILETYPES
+            .iter()
+            .filter_map(|name| {
+                let filetype = state.filetype(name, Access::Read).ok()?;
+                Some((name.to_string(), filetype.path.clone()))
+            })
+            .collect()
+    }
+
+    //the entries of each cache, marked with whether they are in use if the runner answers
+    #[get("/")]
+    pub async fn list(
+        state: &State<Config>,
+        runner: &State<Runner>,
+    ) -> Result<Json<BTreeMap<String, Vec<CacheEntry>>>, FileError> {
+        let dirs = cache_dirs(state);
+        let mut caches = tokio::task::spawn_blocking(move || cache::all_entries(&dirs))
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        match runner.patchers().await {
+            Ok(patchers) => caches
+                .values_mut()
+                .for_each(|entries| cache::mark_in_use(entries, &patchers)),
+            Err(e) => eprintln!("cannot tell which cache entries are in use: {e}"),
+        }
+        Ok(Json(caches))
+    }
+
+    //remove the entries of patchers the runner no longer knows, only reporting them unless
+    //dry_run=false is given, which needs the caches to be deletable
+    #[post("/gc?<dry_run>")]
+    pub async fn gc(
+        state: &State<Config>,
+        runner: &State<Runner>,
+        dry_run: Option<bool>,
+    ) -> Result<Json<GcReport>, FileError> {
+        let dry_run = dry_run.unwrap_or(true);
+        let dirs = cache_dirs(state);
+        if !dry_run {
+            for (name, _) in &dirs {
+                state.filetype(name, Access::Delete)?;
+            }
+        }
+        //without the runner nothing can be known to be unused
+        let patchers = runner.patchers().await.map_err(Status::from)?;
+        let report = tokio::task::spawn_blocking(move || cache::gc(&dirs, &patchers, dry_run))
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+        Ok(Json(report))
+    }
+}
+
+mod job {
+    use {
+        crate::jobs::{JobStatus, Jobs},
+        futures_util::{Stream, StreamExt},
+        rocket::{
+            Responder, Shutdown, State, delete, get,
+            http::Header,
+            response::stream::{Event, EventStream},
+            serde::json::Json,
+            uri,
+        },
+        uuid::Uuid,
+    };
+
+    // Returned when a job has been started, points the client at the status endpoint.
+    #[derive(Responder)]
+    #[response(status = 202, content_type = "json")]
+    pub struct JobAccepted {
+        status: Json<JobStatus>,
+        location: Header<'static>,
+    }
+
+    impl JobAccepted {
+        pub fn new(status: JobStatus) -> Self {
+            let location = Header::new("Location", uri!("/jobs", get(status.id)).to_string());
+            Self {
+                status: Json(status),
+                location,
+            }
+        }
+    }
+
+    #[get("/<id>")]
+    pub fn get(jobs: &State<Jobs>, id: Uuid) -> Option<Json<JobStatus>> {
+        jobs.status(id).map(Json)
+    }
+
+    //server sent events with the job status every time it changes, ending once the job is done
+    #[get("/<id>/events")]
+    pub fn events(
+        jobs: &State<Jobs>,
+        id: Uuid,
+        shutdown: Shutdown,
+    ) -> Option<EventStream<impl Stream<Item = Event>>> {
+        let updates = jobs.updates(id)?.take_until(shutdown);
+        Some(EventStream::from(
+            updates.map(|status| Event::json(&status)),
+        ))
+    }
+
+    #[delete("/<id>")]
+    pub fn cancel(jobs: &State<Jobs>, id: Uuid) -> Option<Json<JobStatus>> {
+        jobs.cancel(id).map(Json)
+    }
+}
+
+mod error {
+    use {
+        crate::problem::Problem,
+        rocket::{Request, catch, http::Status},
+    };
+
+    //errors that are only a status get their problem details here
+    #[catch(default)]
+    pub fn problem(status: Status, _req: &Request) -> Problem {
+        Problem::new(status)
+    }
+}
+
+pub fn file_routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        file::get_filetypes,
+        file::get_html,
+        file::get_json,
+        file::upload,
+        file::extract,
+        file::delete,
+        upload::create,
+        upload::status,
+        upload::append,
+        upload::finish,
+        upload::abort,
+        trash::list,
+        trash::restore,
+        trash::purge,
+        trash::empty,
+        ops::rename,
+        ops::copy,
+        ops::process,
+        ops::mkdir,
+        events::events,
+        usage::usage
+    ]
+}
+
+pub fn package_routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        package::get,
+        package::get_all,
+        package::post,
+        package::post_all,
+        package::post_selection,
+        package::install
+    ]
+}
+
+pub fn cache_routes() -> Vec<rocket::Route> {
+    rocket::routes![cache::list, cache::gc]
+}
+
+pub fn error_catchers() -> Vec<rocket::Catcher> {
+    rocket::catchers![error::problem]
+}
+
+pub fn job_routes() -> Vec<rocket::Route> {
+    rocket::routes![job::get, job::events, job::cancel]
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        rocket::{
+            http::{Accept, ContentType, Status},
+            local::blocking::Client,
+        },
+        rocket_dyn_templates::Template,
+        std::fs,
+        tempdir::TempDir,
+    };
+
+    const CURRENT_RNBO_VERSION: &str = "1.2.3";
+
+    struct Resources {
+        tempdir: TempDir,
+    }
+
+    impl Resources {
+        fn new() -> Self {
+            Self {
+                tempdir: TempDir::new("runner-panel").expect("to get temp dir"),
+            }
+        }
+    }
+
+    //minimal server, with a runner address that nothing listens on
+    fn setup() -> (Client, Resources) {
+        setup_with(crate::paths::SymlinkPolicy::default(), unused_port())
+    }
+
+    //a local port that was free a moment ago
+    fn unused_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("to get a free port")
+            .port()
+    }
+
+    //poll the job at `location` until it is done
+    fn wait_for_job(client: &Client, location: &str) -> serde_json::Value {
+        loop {
+            let status: serde_json::Value = client
+                .get(location)
+                .dispatch()
+                .into_json()
+                .expect("to get job status");
+            if status["state"] != "running" {
+                return status;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    fn setup_with(symlinks: crate::paths::SymlinkPolicy, runner_port: u16) -> (Client, Resources) {
+        use std::io::prelude::*;
+        let resources = Resources::new();
+
+        let datafiles = resources.tempdir.path().join("datafiles");
+        let source_cache = resources.tempdir.path().join("source_cache");
+        let package_dir = resources.tempdir.path().join("packages");
+        let current_package_dir = package_dir.join(CURRENT_RNBO_VERSION);
+
+        let backup = resources.tempdir.path().join("backup");
+        let recordings = resources.tempdir.path().join("recordings");
+
+        let panel_config = crate::config::PanelConfig {
+            runner_port,
+            runner_timeout: 100,
+            extract_limit: 1,
+            ..Default::default()
+        };
+
+        fs::create_dir_all(&datafiles).expect("to create dir");
+        fs::create_dir_all(&source_cache).expect("to create dir");
+        fs::create_dir_all(&package_dir).expect("to create dir");
+        fs::create_dir_all(&current_package_dir).expect("to create dir");
+        fs::create_dir_all(&backup).expect("to create dir");
+        fs::create_dir_all(&recordings).expect("to create dir");
+
+        //read like runner.json, with a custom filetype that only takes small wav files
+        let runner_config: crate::config::RunnerConfig =
+            serde_json::from_value(serde_json::json!({
+                "datafile_dir": datafiles,
+                "source_cache_dir": source_cache,
+                "compile_cache_dir": resources.tempdir.path().join("compile_cache"),
+                "backup_dir": backup,
+                "package_dir": package_dir,
+                "filetypes": {
+                    "recordings": {
+                        "path": recordings,
+                        "writable": true,
+                        "extensions": ["WAV"],
+                        "max_upload_size": 64
+                    },
+                    "compile_cache": { "readable": false }
+                }
+            }))
+            .expect("valid runner config");
+        let files =
+            crate::config::Config::new(runner_config.filetypes(), symlinks, Some(package_dir));
+        let watch = crate::watch::FsWatch::new(&files.filetype_paths());
+
+        let f = datafiles.join("deleteme.txt");
+        let mut file = fs::File::create(&f).expect("to create");
+        file.write_all(b"Delete the world!").expect("to write");
+
+        let f = datafiles.join("second.txt");
+        let mut file = fs::File::create(&f).expect("to create");
+        file.write_all(b"Fourth World Vol. 1 Possible Musics")
+            .expect("to write");
+
+        let f = backup.join("nodelete.txt");
+        let mut file = fs::File::create(&f).expect("to create");
+        file.write_all(b"Cannot delete world!").expect("to write");
+
+        let f = current_package_dir.join("foo.rnbopack");
+        let mut file = fs::File::create(&f).expect("to create");
+        file.write_all(b"not really a tar file").expect("to write");
+
+        (
+            Client::tracked(
+                rocket::build()
+                    .mount("/files", super::file_routes())
+                    .mount("/packages", super::package_routes())
+                    .mount("/jobs", super::job_routes())
+                    .mount("/cache", super::cache_routes())
+                    .register("/files", super::error_catchers())
+                    .register("/packages", super::error_catchers())
+                    .manage(crate::usage::DiskUsage::new(&watch))
+                    .manage(watch)
+                    .manage(files)
+                    .manage(crate::jobs::Jobs::default())
+                    .manage(crate::uploads::Uploads::default())
+                    .manage(crate::runner::Runner::new(
+                        panel_config.runner_url(),
+                        panel_config.runner_timeout(),
+                    ))
+                    .manage(panel_config)
+                    .attach(Template::fairing()),
+            )
+            .expect("valid rocket instance"),
+            resources,
+        )
+    }
+
+    #[test]
+    fn filetype_list() {
+        let (client, _resources) = setup();
+
+        let response = client.get("/files/").header(Accept::HTML).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+    }
+
+    #[test]
+    fn reserved_filetypes() {
+        let dir = TempDir::new("runner-panel").expect("to get temp dir");
+        let runner_config: crate::config::RunnerConfig =
+            serde_json::from_value(serde_json::json!({
+                "filetypes": {
+                    "trash": { "path": dir.path() },
+                    "usage": { "path": dir.path() },
+                    "samples": { "path": dir.path() }
+                }
+            }))
+            .expect("valid runner config");
+        let filetypes = runner_config.filetypes();
+        assert!(filetypes.contains_key("samples"));
+        assert!(!filetypes.contains_key("trash"));
+        assert!(!filetypes.contains_key("usage"));
+
+        //a runner.json that can't be parsed isn't taken for the defaults
+        use crate::config::RunnerConfig;
+        let path = dir.path().join("runner.json");
+        assert!(RunnerConfig::read_or_default(&path).is_ok());
+        fs::write(&path, r#"{"filetypes": {"samples": {"path": 1}}}"#).expect("to write");
+        assert!(RunnerConfig::read_or_default(&path).is_err());
+    }
+
+    #[test]
+    fn filetype_unknown() {
+        let (client, _resources) = setup();
+        let response = client.get("/files/foo/").header(Accept::JSON).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client.get("/files/foo/").header(Accept::HTML).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn filetype_known() {
+        let (client, _resources) = setup();
+
+        let response = client
+            .get("/files/datafiles/")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+
+        let list: Option<crate::filelist::FileList> = response.into_json();
+        assert!(list.is_some());
+
+        let list = list.unwrap();
+
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(list.filetype.as_str(), "datafiles");
+        assert!(
+            list.items
+                .iter()
+                .find(|f| f.name.as_str() == "deleteme.txt")
+                .is_some()
+        );
+        assert!(
+            list.items
+                .iter()
+                .find(|f| f.name.as_str() == "second.txt")
+                .is_some()
+        );
+
+        let response = client
+            .get("/files/datafiles/")
+            .header(Accept::HTML)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+
+        let response = client.get("/files/datafiles/second.txt").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().unwrap().as_str(),
+            "Fourth World Vol. 1 Possible Musics"
+        );
+
+        let response = client
+            .get(format!(
+                "/files/packages/{}/foo.rnbopack",
+                CURRENT_RNBO_VERSION
+            ))
+            .header(Accept::HTML)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::TAR));
+    }
+
+    #[test]
+    fn recursive_listing() {
+        let (client, resources) = setup();
+
+        let nested = resources.tempdir.path().join("datafiles/kit/snares");
+        fs::create_dir_all(&nested).expect("to create dir");
+        fs::write(nested.join("snare.wav"), b"RIFF").expect("to write");
+        fs::write(nested.join(".hidden"), b"secret").expect("to write");
+
+        let find = |items: &Vec<crate::filelist::FileListItem>, name: &str| {
+            items
+                .iter()
+                .position(|i| i.name == name)
+                .expect("to find item")
+        };
+
+        //without recursion directories have no contents
+        let list: crate::filelist::FileList = client
+            .get("/files/datafiles/")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get list");
+        assert_eq!(list.items.len(), 3);
+        let kit = &list.items[find(&list.items, "kit")];
+        assert!(kit.dir);
+        assert!(kit.items.is_none());
+        let second = &list.items[find(&list.items, "second.txt")];
+        assert_eq!(second.size, Some(35));
+        assert_eq!(second.mime.as_deref(), Some("text/plain; charset=utf-8"));
+        assert!(second.modified.is_some());
+        assert!(second.hash.is_none());
+
+        let list: crate::filelist::FileList = client
+            .get("/files/datafiles/?recursive=true&hash=true")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get list");
+        let kit = &list.items[find(&list.items, "kit")];
+        let kit_items = kit.items.as_ref().expect("to get contents");
+        let snares = &kit_items[find(kit_items, "snares")];
+        let snares_items = snares.items.as_ref().expect("to get contents");
+        assert_eq!(snares_items.len(), 1);
+        assert_eq!(snares_items[0].name, "snare.wav");
+        assert_eq!(snares_items[0].uri, "/files/datafiles/kit/snares/snare.wav");
+        assert_eq!(snares_items[0].size, Some(4));
+        let second = &list.items[find(&list.items, "second.txt")];
+        assert_eq!(
+            second.hash.as_deref(),
+            Some("4343e698fd2bb3c778a68c43f1ebaf018627b499c313a4fc482cd003b43c73f8")
+        );
+
+        //depth limits the recursion
+        let list: crate::filelist::FileList = client
+            .get("/files/datafiles/?depth=2")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get list");
+        let kit = &list.items[find(&list.items, "kit")];
+        let kit_items = kit.items.as_ref().expect("to get contents");
+        assert!(kit_items[find(kit_items, "snares")].items.is_none());
+
+        let response = client
+            .get("/files/datafiles/?recursive=true")
+            .header(Accept::HTML)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let html = response.into_string().expect("to get body");
+        assert!(html.contains("/files/datafiles/kit/snares/snare.wav"));
+        assert!(html.contains("35 bytes"));
+    }
+
+    #[test]
+    fn delete() {
+        let (client, resources) = setup();
+
+        let p = resources.tempdir.path().join("datafiles/deleteme.txt");
+        assert_eq!(Some(true), fs::exists(&p).ok());
+        let response = client.delete("/files/datafiles/deleteme.txt").dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(Some(false), fs::exists(&p).ok());
+
+        let response = client
+            .get("/files/datafiles/")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+
+        let list: Option<crate::filelist::FileList> = response.into_json();
+        assert!(list.is_some());
+
+        let list = list.unwrap();
+
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.filetype.as_str(), "datafiles");
+        assert!(
+            list.items
+                .iter()
+                .find(|f| f.name.as_str() == "deleteme.txt")
+                .is_none()
+        );
+        assert!(
+            list.items
+                .iter()
+                .find(|f| f.name.as_str() == "second.txt")
+                .is_some()
+        );
+
+        let p = resources.tempdir.path().join("backup/nodelete.txt");
+        assert_eq!(Some(true), fs::exists(&p).ok());
+        let response = client.delete("/files/backup/nodelete.txt").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(Some(true), fs::exists(&p).ok());
+    }
+
+    #[test]
+    fn trash() {
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+        fs::create_dir_all(datafiles.join("kit")).expect("to create dir");
+        fs::write(datafiles.join("kit/kick.wav"), b"KICK").expect("to write");
+
+        //deleted files and directories can be listed and restored
+        for name in ["deleteme.txt", "kit"] {
+            let response = client.delete(format!("/files/datafiles/{name}")).dispatch();
+            assert_eq!(response.status(), Status::NoContent);
+            assert!(!datafiles.join(name).exists());
+        }
+        let response = client.get("/files/trash/datafiles").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let items: Vec<crate::trash::TrashItem> = response.into_json().expect("to get items");
+        assert_eq!(items.len(), 2);
+        let kit = items.iter().find(|i| i.dir).expect("to find the directory");
+        assert_eq!(kit.path, std::path::Path::new("kit"));
+        assert_eq!(kit.size, 4);
+        let file = items.iter().find(|i| !i.dir).expect("to find the file");
+
+        let response = client
+            .post(format!("/files/trash/datafiles/{}", kit.id))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            fs::read(datafiles.join("kit/kick.wav")).expect("to read"),
+            b"KICK"
+        );
+        let response = client
+            .post(format!("/files/trash/datafiles/{}", kit.id))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        //restoring doesn't replace what has taken the item's place
+        fs::write(datafiles.join("deleteme.txt"), b"new").expect("to write");
+        let response = client
+            .post(format!("/files/trash/datafiles/{}", file.id))
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        assert_eq!(
+            fs::read(datafiles.join("deleteme.txt")).expect("to read"),
+            b"new"
+        );
+
+        //purged items are gone for good
+        let response = client
+            .delete(format!("/files/trash/datafiles/{}", file.id))
+            .dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        client.delete("/files/datafiles/second.txt").dispatch();
+        let response = client.delete("/files/trash/datafiles").dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.get("/files/trash/datafiles").dispatch();
+        let items: Vec<crate::trash::TrashItem> = response.into_json().expect("to get items");
+        assert!(items.is_empty());
+
+        //the trash follows the filetype's permissions
+        let response = client.delete("/files/trash/backup").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        //what was just deleted is kept even when it doesn't fit, older items make room
+        let trash = crate::trash::Trash::new(&datafiles);
+        let limits = crate::trash::TrashLimits {
+            max_age: std::time::Duration::from_secs(60),
+            max_size: 8,
+        };
+        let put = |name: &str, data: &[u8]| {
+            fs::write(datafiles.join(name), data).expect("to write");
+            trash
+                .put(&datafiles.join(name), std::path::Path::new(name), limits)
+                .expect("to trash")
+        };
+        let big = put("big.txt", b"0123456789");
+        assert_eq!(trash.list().len(), 1);
+        let small = put("small.txt", b"0123");
+        let ids: Vec<_> = trash.list().iter().map(|i| i.id).collect();
+        assert_eq!(ids, [small.id]);
+        assert!(trash.get(big.id).is_err());
+
+        //and expired ones go when the trash is listed, without anything else being deleted
+        let expire = |id: uuid::Uuid| {
+            let meta = datafiles.join(format!(".trash/{id}.json"));
+            let mut item: serde_json::Value =
+                serde_json::from_slice(&fs::read(&meta).expect("to read")).expect("to parse");
+            item["deleted"] = "2000-01-01T00:00:00Z".into();
+            fs::write(&meta, item.to_string()).expect("to write");
+        };
+        expire(small.id);
+        let response = client.get("/files/trash/datafiles").dispatch();
+        let items: Vec<crate::trash::TrashItem> = response.into_json().expect("to get items");
+        assert!(items.is_empty());
+        assert!(trash.get(small.id).is_err());
+
+        //or the next time something is deleted
+        let old = put("old.txt", b"1");
+        expire(old.id);
+        let other = put("other.txt", b"1");
+        let ids: Vec<_> = trash.list().iter().map(|i| i.id).collect();
+        assert_eq!(ids, [other.id]);
+    }
+
+    #[test]
+    fn disk_usage() {
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+
+        let response = client.get("/files/usage").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let usage: serde_json::Value = response.into_json().expect("to get usage");
+        assert_eq!(usage["datafiles"]["files"], 2);
+        assert_eq!(usage["datafiles"]["size"], 52);
+        assert_eq!(usage["datafiles"]["dirs"], 0);
+        let filesystem = &usage["datafiles"]["filesystem"];
+        assert!(filesystem["free"].as_u64() <= filesystem["total"].as_u64());
+        assert!(usage.get("compile_cache").is_none());
+
+        //hidden files aren't reported by the watcher, so the walk is reused until asked to refresh,
+        //once the changes made by setup have come through and no longer drop it
+        let disk = client
+            .rocket()
+            .state::<crate::usage::DiskUsage>()
+            .expect("to get disk usage");
+        for _ in 0..300 {
+            client.get("/files/usage").dispatch();
+            if disk.cached("datafiles").is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(disk.cached("datafiles").is_some());
+        fs::write(datafiles.join(".hidden"), b"1234").expect("to write");
+        let response = client.get("/files/usage").dispatch();
+        let usage: serde_json::Value = response.into_json().expect("to get usage");
+        assert_eq!(usage["datafiles"]["files"], 2);
+        let response = client.get("/files/usage?refresh=true").dispatch();
+        let usage: serde_json::Value = response.into_json().expect("to get usage");
+        assert_eq!(usage["datafiles"]["files"], 3);
+        assert_eq!(usage["datafiles"]["size"], 56);
+    }
+
+    #[test]
+    fn put_insufficient_storage() {
+        use rocket::http::Header;
+
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+
+        //an announced length that can't fit is refused before the body is read
+        let response = client
+            .put("/files/datafiles/huge.wav")
+            .header(Header::new("Content-Length", (1u64 << 60).to_string()))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::InsufficientStorage);
+        let body: serde_json::Value = response.into_json().expect("to get json");
+        assert_eq!(body["required"], 1u64 << 60);
+        assert_eq!(body["reserve"], 256 * 1024 * 1024);
+        assert!(body["free"].is_u64());
+        assert!(!datafiles.join("huge.wav").exists());
+        assert!(!datafiles.join(".uploads").exists());
+
+        //as are archives to extract and chunks of resumable uploads
+        let response = client
+            .put("/files/datafiles/huge?extract=zip")
+            .header(Header::new("Content-Length", (1u64 << 60).to_string()))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::InsufficientStorage);
+        let response = client.post("/files/datafiles/huge.wav").dispatch();
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let response = client
+            .patch(location.as_str())
+            .header(Header::new("Upload-Offset", "0"))
+            .header(Header::new("Content-Length", (1u64 << 60).to_string()))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::InsufficientStorage);
+        let session: serde_json::Value = client
+            .get(location.as_str())
+            .dispatch()
+            .into_json()
+            .expect("to get session");
+        assert_eq!(session["offset"], 0);
+
+        let response = client
+            .put("/files/datafiles/small.txt")
+            .header(Header::new("Content-Length", "3"))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+    }
+
+    #[test]
+    fn cache_entries() {
+        let (client, resources) = setup();
+        let source_cache = resources.tempdir.path().join("source_cache");
+        fs::create_dir_all(source_cache.join("1.3.0")).expect("to create dir");
+        fs::write(source_cache.join("1.3.0/synth.cpp"), b"int main;").expect("to write");
+        fs::write(
+            source_cache.join("1.3.0/synth.json"),
+            br#"{"meta": {"name": "poly synth", "rnboversion": "1.3.1"}}"#,
+        )
+        .expect("to write");
+        fs::write(source_cache.join("libdrums.so"), b"ELF").expect("to write");
+        fs::write(source_cache.join("drums.cpp"), b"int drums;").expect("to write");
+
+        //there is no runner to ask which patchers it knows
+        let response = client.get("/cache/").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let caches: std::collections::HashMap<String, Vec<crate::cache::CacheEntry>> =
+            response.into_json().expect("to get entries");
+        assert!(!caches.contains_key("compile_cache"));
+        let entries = &caches["source_cache"];
+        assert_eq!(entries.len(), 2);
+        let synth = entries
+            .iter()
+            .find(|e| e.patcher == "poly synth")
+            .expect("to find synth");
+        assert_eq!(synth.rnbo_version.as_deref(), Some("1.3.1"));
+        assert_eq!(synth.files, ["1.3.0/synth.cpp", "1.3.0/synth.json"]);
+        assert!(synth.last_used.is_some() && synth.in_use.is_none());
+        let drums = entries
+            .iter()
+            .find(|e| e.patcher == "drums")
+            .expect("to find drums");
+        assert_eq!(drums.rnbo_version, None);
+        assert_eq!(drums.size, 13);
+
+        //nothing is collected without the runner, or from caches that aren't deletable
+        let response = client.post("/cache/gc").dispatch();
+        assert_eq!(response.status(), Status::FailedDependency);
+        let response = client.post("/cache/gc?dry_run=false").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(source_cache.join("libdrums.so").exists());
+    }
+
+    #[test]
+    fn cache_gc() {
+        use crate::cache::{self, CacheEntry};
+
+        let tempdir = TempDir::new("runner-panel").expect("to get temp dir");
+        let (so, src) = (tempdir.path().join("so"), tempdir.path().join("src"));
+        for dir in [so.join("1.3.1"), src.join("1.3.1")] {
+            fs::create_dir_all(dir).expect("to create dir");
+        }
+        let description = |name: &str| format!(r#"{{"meta": {{"name": "{name}"}}}}"#);
+        fs::write(src.join("1.3.1/synth.cpp"), b"int synth;").expect("to write");
+        fs::write(src.join("1.3.1/synth.json"), description("poly synth")).expect("to write");
+        fs::write(so.join("1.3.1/libsynth.so"), b"ELF").expect("to write");
+        fs::write(src.join("keep.cpp"), b"int keep;").expect("to write");
+        fs::write(src.join("keep.json"), description("keeper")).expect("to write");
+        //nothing says which patcher this is
+        fs::write(src.join("drums.cpp"), b"int drums;").expect("to write");
+        let dirs = [
+            ("compile_cache".to_string(), so.clone()),
+            ("source_cache".to_string(), src.clone()),
+        ];
+        let patchers = std::collections::HashSet::from(["keeper".to_string()]);
+
+        let mut caches = cache::all_entries(&dirs);
+        let find = |entries: &[CacheEntry], file: &str| {
+            entries
+                .iter()
+                .find(|e| e.files.iter().any(|f| f.ends_with(file)))
+                .cloned()
+                .expect("to find entry")
+        };
+        //the compiled patcher is named after its sources
+        let compiled = find(&caches["compile_cache"], "libsynth.so");
+        assert_eq!(compiled.patcher, "poly synth");
+        assert_eq!(compiled.rnbo_version.as_deref(), Some("1.3.1"));
+        for entries in caches.values_mut() {
+            cache::mark_in_use(entries, &patchers);
+        }
+        let sources = &caches["source_cache"];
+        assert_eq!(find(sources, "synth.cpp").in_use, Some(false));
+        assert_eq!(find(sources, "keep.cpp").in_use, Some(true));
+        assert_eq!(find(sources, "drums.cpp").in_use, None);
+        assert_eq!(
+            find(&caches["compile_cache"], "libsynth.so").in_use,
+            Some(false)
+        );
+
+        let count = |report: &cache::GcReport| report.removed.values().flatten().count();
+        let report = cache::gc(&dirs, &patchers, true);
+        assert!(report.dry_run);
+        assert_eq!(count(&report), 2);
+        assert_eq!(
+            report.freed,
+            10 + description("poly synth").len() as u64 + 3
+        );
+        assert!(src.join("1.3.1/synth.cpp").exists());
+        assert!(so.join("1.3.1/libsynth.so").exists());
+
+        let report = cache::gc(&dirs, &patchers, false);
+        assert!(!report.dry_run);
+        assert_eq!(count(&report), 2);
+        assert!(!src.join("1.3.1/synth.cpp").exists());
+        assert!(!src.join("1.3.1/synth.json").exists());
+        assert!(!so.join("1.3.1/libsynth.so").exists());
+        assert!(src.join("keep.cpp").exists());
+        assert!(src.join("drums.cpp").exists());
+
+        let entry = find(&cache::all_entries(&dirs)["source_cache"], "keep.cpp");
+        cache::remove(&src, &entry).expect("to remove entry");
+        assert!(!src.join("keep.json").exists());
+        assert_eq!(count(&cache::gc(&dirs, &patchers, true)), 0);
+    }
+
+    #[test]
+    fn cache_with_runner() {
+        let fake = crate::fake_runner::FakeRunner::start(CURRENT_RNBO_VERSION);
+        fake.set_patchers(&["drums"]);
+        let (client, resources) = setup_with(crate::paths::SymlinkPolicy::default(), fake.port());
+        let source_cache = resources.tempdir.path().join("source_cache");
+        fs::write(source_cache.join("synth.cpp"), b"int synth;").expect("to write");
+        fs::write(
+            source_cache.join("synth.json"),
+            br#"{"meta": {"name": "poly synth"}}"#,
+        )
+        .expect("to write");
+        fs::write(source_cache.join("drums.cpp"), b"int drums;").expect("to write");
+
+        let caches: std::collections::HashMap<String, Vec<crate::cache::CacheEntry>> = client
+            .get("/cache/")
+            .dispatch()
+            .into_json()
+            .expect("to get entries");
+        let in_use = |name: &str| {
+            caches["source_cache"]
+                .iter()
+                .find(|e| e.patcher == name)
+                .expect("to find entry")
+                .in_use
+        };
+        assert_eq!(in_use("poly synth"), Some(false));
+        assert_eq!(in_use("drums"), Some(true));
+
+        let response = client.post("/cache/gc").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let report: crate::cache::GcReport = response.into_json().expect("to get report");
+        assert!(report.dry_run);
+        let removed: Vec<&str> = report.removed["source_cache"]
+            .iter()
+            .map(|e| e.patcher.as_str())
+            .collect();
+        assert_eq!(removed, ["poly synth"]);
+        assert!(source_cache.join("synth.cpp").exists());
+    }
+
+    #[test]
+    fn put() {
+        let (client, resources) = setup();
+
+        let p = resources.tempdir.path().join("datafiles/blah.txt");
+        assert_eq!(Some(false), fs::exists(&p).ok());
+
+        let response = client
+            .put("/files/datafiles/blah.txt")
+            .body("FOO")
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(Some(true), fs::exists(&p).ok());
+        let contents = fs::read_to_string(&p).expect("Should have been able to read the file");
+        assert_eq!("FOO", contents.as_str());
+
+        let response = client.put("/files/NOEXIST/blah.txt").body("FOO").dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn put_digest() {
+        use rocket::http::Header;
+
+        let (client, resources) = setup();
+        let p = resources.tempdir.path().join("datafiles/digest.txt");
+        //sha-256 of "FOO"
+        let sha256 = "9520437ce8902eb379a7d8aaa98fc4c94eeb07b6684854868fa6f72bf34b0fd3";
+        let header = "sha-256=:lSBDfOiQLrN5p9iqqY/EyU7rB7ZoSFSGj6b3K/NLD9M=:";
+
+        //the stored file's digest is returned whether or not one was given
+        let response = client
+            .put("/files/datafiles/digest.txt")
+            .header(Header::new("Content-Digest", header))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.headers().get_one("Content-Digest"), Some(header));
+        let body: serde_json::Value = response.into_json().expect("to get json");
+        assert_eq!(body["sha256"], sha256);
+
+        //a mismatch leaves the previous file in place and nothing staged
+        let response = client
+            .put("/files/datafiles/digest.txt")
+            .header(Header::new("Content-Digest", header))
+            .body("BAR")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: serde_json::Value = response.into_json().expect("to get json");
+        assert_eq!(body["expected"], sha256);
+        assert_ne!(body["sha256"], sha256);
+        assert_eq!(fs::read_to_string(&p).expect("to read file"), "FOO");
+        let datafiles = resources.tempdir.path().join("datafiles");
+        let staged = fs::read_dir(datafiles)
+            .expect("to read dir")
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with('.'))
+            .count();
+        assert_eq!(staged, 0);
+
+        //other algorithms are ignored, malformed sha-256 isn't
+        let response = client
+            .put("/files/datafiles/digest.txt")
+            .header(Header::new("Content-Digest", "sha-512=:AAAA:"))
+            .body("BAR")
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let response = client
+            .put("/files/datafiles/digest.txt")
+            .header(Header::new("Content-Digest", "sha-256=:AAAA:"))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(fs::read_to_string(&p).expect("to read file"), "BAR");
+    }
+
+    #[test]
+    fn filetype_permissions() {
+        use rocket::http::Header;
+
+        let (client, resources) = setup();
+
+        //the runner's own directories are read only
+        let response = client.put("/files/backup/new.txt").body("FOO").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(!resources.tempdir.path().join("backup/new.txt").exists());
+        let response = client
+            .post("/files/mkdir")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "source_cache", "path": "new"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        //filetypes can be hidden altogether
+        let response = client
+            .get("/files/compile_cache/")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client.get("/files/").header(Accept::HTML).dispatch();
+        let body = response.into_string().expect("to get body");
+        assert!(body.contains("recordings"));
+        assert!(!body.contains("compile_cache"));
+
+        //custom filetypes restrict extensions and sizes
+        let response = client
+            .put("/files/recordings/take.WAV")
+            .body(wav_bytes(8000, 1, &[0; 8]))
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert!(
+            resources
+                .tempdir
+                .path()
+                .join("recordings/take.WAV")
+                .is_file()
+        );
+        let response = client
+            .put("/files/recordings/notes.txt")
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        let response = client
+            .put("/files/recordings/long.wav")
+            .body(wav_bytes(8000, 1, &[0; 64]))
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let response = client
+            .post("/files/recordings/long.wav")
+            .header(Header::new("Upload-Length", "1000"))
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let response = client
+            .post("/files/copy")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "recordings", "from": "take.WAV", "to": "take.txt"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+
+        //but aren't deletable unless declared so
+        let response = client.delete("/files/recordings/take.WAV").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .post("/files/move")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "recordings", "from": "take.WAV", "to": "moved.wav"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn symlinks() {
+        use {crate::paths::SymlinkPolicy, std::os::unix::fs::symlink};
+
+        let links = |resources: &Resources| {
+            let datafiles = resources.tempdir.path().join("datafiles");
+            symlink("../backup", datafiles.join("escape")).expect("to link");
+            symlink("second.txt", datafiles.join("inside.txt")).expect("to link");
+            symlink("nothere.txt", datafiles.join("dangling.txt")).expect("to link");
+        };
+
+        let (client, resources) = setup();
+        links(&resources);
+        let backup = resources.tempdir.path().join("backup");
+
+        //links leading out of the filetype directory are refused, whatever the route
+        let response = client
+            .get("/files/datafiles/escape/nodelete.txt")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "problem+json"))
+        );
+        let error: serde_json::Value = response.into_json().expect("to get error");
+        assert_eq!(error["path"], "escape/nodelete.txt");
+        assert_eq!(error["kind"], "path_escape");
+        assert!(error["detail"].as_str().unwrap().contains("outside"));
+        let response = client
+            .delete("/files/datafiles/escape/nodelete.txt")
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(backup.join("nodelete.txt").is_file());
+        let response = client
+            .put("/files/datafiles/escape/new.txt")
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(!backup.join("new.txt").exists());
+        let response = client
+            .put("/files/datafiles/dangling.txt")
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+        let response = client
+            .post("/files/copy")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "from": "escape", "to": "copied"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        //and left out of listings
+        let list: crate::filelist::FileList = client
+            .get("/files/datafiles/")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get list");
+        let names: Vec<_> = list.items.iter().map(|i| i.name.as_str()).collect();
+        assert!(names.contains(&"inside.txt"));
+        assert!(!names.contains(&"escape"));
+        assert!(!names.contains(&"dangling.txt"));
+
+        //links within the directory are followed, deleting one removes only the link
+        let response = client.get("/files/datafiles/inside.txt").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().as_deref(),
+            Some("Fourth World Vol. 1 Possible Musics")
+        );
+        let response = client.delete("/files/datafiles/inside.txt").dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert!(
+            resources
+                .tempdir
+                .path()
+                .join("datafiles/second.txt")
+                .is_file()
+        );
+
+        let (client, resources) = setup_with(SymlinkPolicy::Deny, unused_port());
+        links(&resources);
+        let response = client.get("/files/datafiles/inside.txt").dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let (client, resources) = setup_with(SymlinkPolicy::Allow, unused_port());
+        links(&resources);
+        let response = client
+            .get("/files/datafiles/escape/nodelete.txt")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[test]
+    fn conditional_requests() {
+        use rocket::http::Header;
+
+        let (client, resources) = setup();
+
+        let response = client
+            .get("/files/datafiles/second.txt")
+            .header(Header::new("Range", "bytes=7-11"))
+            .dispatch();
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(
+            response.headers().get_one("Content-Range"),
+            Some("bytes 7-11/35")
+        );
+        assert_eq!(response.into_string().unwrap().as_str(), "World");
+
+        let response = client
+            .get("/files/datafiles/second.txt")
+            .header(Header::new("Range", "bytes=-7"))
+            .dispatch();
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(response.into_string().unwrap().as_str(), " Musics");
+
+        let response = client
+            .get("/files/datafiles/second.txt")
+            .header(Header::new("Range", "bytes=100-"))
+            .dispatch();
+        assert_eq!(response.status(), Status::RangeNotSatisfiable);
+        assert_eq!(
+            response.headers().get_one("Content-Range"),
+            Some("bytes */35")
+        );
+        let problem: crate::problem::Problem = response.into_json().expect("to get problem");
+        assert_eq!(problem.status, 416);
+
+        let response = client.get("/files/datafiles/second.txt").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Accept-Ranges"), Some("bytes"));
+        let etag = response
+            .headers()
+            .get_one("ETag")
+            .expect("to get etag")
+            .to_string();
+        let modified = response
+            .headers()
+            .get_one("Last-Modified")
+            .expect("to get last modified")
+            .to_string();
+
+        let response = client
+            .get("/files/datafiles/second.txt")
+            .header(Header::new("If-None-Match", etag.clone()))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+        let response = client
+            .get("/files/datafiles/second.txt")
+            .header(Header::new("If-Modified-Since", modified))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+
+        //a stale If-Range gets the whole file
+        let response = client
+            .get("/files/datafiles/second.txt")
+            .header(Header::new("Range", "bytes=0-5"))
+            .header(Header::new("If-Range", "\"stale\""))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get(format!(
+                "/files/packages/{}/foo.rnbopack",
+                CURRENT_RNBO_VERSION
+            ))
+            .header(Header::new("Range", "bytes=0-2"))
+            .dispatch();
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(response.content_type(), Some(ContentType::TAR));
+        assert!(response.headers().get_one("Content-Disposition").is_some());
+        assert_eq!(response.into_string().unwrap().as_str(), "not");
+
+        //refusals are problems, not packages
+        let response = client
+            .get(format!(
+                "/files/packages/{}/foo.rnbopack",
+                CURRENT_RNBO_VERSION
+            ))
+            .header(Header::new("If-Match", "\"stale\""))
+            .dispatch();
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "problem+json"))
+        );
+        assert!(response.headers().get_one("Content-Disposition").is_none());
+        let problem: crate::problem::Problem = response.into_json().expect("to get problem");
+        assert_eq!(problem.kind, crate::problem::ErrorKind::PreconditionFailed);
+        let response = client
+            .get(format!(
+                "/files/packages/{}/foo.rnbopack",
+                CURRENT_RNBO_VERSION
+            ))
+            .header(Header::new("Range", "bytes=100-"))
+            .dispatch();
+        assert_eq!(response.status(), Status::RangeNotSatisfiable);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "problem+json"))
+        );
+
+        //writes only happen if the client has seen the current version
+        let p = resources.tempdir.path().join("datafiles/second.txt");
+        let response = client
+            .put("/files/datafiles/second.txt")
+            .header(Header::new("If-Match", "\"stale\""))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let response = client
+            .put("/files/datafiles/second.txt")
+            .header(Header::new("If-None-Match", "*"))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let response = client
+            .delete("/files/datafiles/second.txt")
+            .header(Header::new("If-Match", "\"stale\""))
+            .dispatch();
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        assert_eq!(Some(true), fs::exists(&p).ok());
+
+        let response = client
+            .put("/files/datafiles/second.txt")
+            .header(Header::new("If-Match", etag))
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let etag = client
+            .get("/files/datafiles/second.txt")
+            .dispatch()
+            .headers()
+            .get_one("ETag")
+            .expect("to get etag")
+            .to_string();
+        let response = client
+            .delete("/files/datafiles/second.txt")
+            .header(Header::new("If-Match", etag))
+            .dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert_eq!(Some(false), fs::exists(&p).ok());
+    }
+
+    #[test]
+    fn archive_download() {
+        use std::io::Read;
+
+        let (client, resources) = setup();
+
+        let nested = resources.tempdir.path().join("datafiles/kit/snares");
+        fs::create_dir_all(&nested).expect("to create dir");
+        fs::write(nested.join("snare.wav"), b"RIFF").expect("to write");
+        fs::write(nested.join(".hidden"), b"secret").expect("to write");
+
+        let tar_names = |bytes: Vec<u8>| -> Vec<String> {
+            let mut archive = tar::Archive::new(bytes.as_slice());
+            archive
+                .entries()
+                .expect("to read tar")
+                .map(|e| {
+                    e.expect("to read entry")
+                        .path()
+                        .expect("to get path")
+                        .to_string_lossy()
+                        .trim_end_matches('/')
+                        .to_string()
+                })
+                .collect()
+        };
+
+        let response = client.get("/files/datafiles/kit?archive=tar").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::TAR));
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            Some("attachment; filename=\"kit.tar\"")
+        );
+        let names = tar_names(response.into_bytes().expect("to get body"));
+        assert_eq!(names, vec!["kit", "kit/snares", "kit/snares/snare.wav"]);
+
+        let response = client
+            .get("/files/datafiles/?archive=tar.gz")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::GZIP));
+        let mut bytes = Vec::new();
+        flate2::read::GzDecoder::new(response.into_bytes().expect("to get body").as_slice())
+            .read_to_end(&mut bytes)
+            .expect("to decompress");
+        let names = tar_names(bytes);
+        assert!(names.contains(&"datafiles/second.txt".to_string()));
+        assert!(names.contains(&"datafiles/kit/snares/snare.wav".to_string()));
+        assert!(!names.iter().any(|n| n.contains(".hidden")));
+
+        let response = client.get("/files/datafiles/kit?archive=zip").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::ZIP));
+        let bytes = response.into_bytes().expect("to get body");
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes)).expect("to read zip");
+        assert_eq!(zip.len(), 3);
+        let mut contents = String::new();
+        zip.by_name("kit/snares/snare.wav")
+            .expect("to find entry")
+            .read_to_string(&mut contents)
+            .expect("to read entry");
+        assert_eq!(contents, "RIFF");
+
+        //an invalid option is refused rather than ignored
+        for query in ["archive=rar", "depth=deep", "info=maybe"] {
+            let response = client
+                .get(format!("/files/datafiles/kit?{query}"))
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest, "{query}");
+            let problem: serde_json::Value = response.into_json().expect("to get problem");
+            assert_eq!(problem["status"], 400);
+        }
+    }
+
+    #[test]
+    fn extract_upload() {
+        use std::io::Write;
+
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("kit/", options).expect("to add dir");
+        zip.start_file("kit/kick.wav", options)
+            .expect("to start file");
+        zip.write_all(b"RIFF").expect("to write");
+        zip.start_file("../evil.txt", options)
+            .expect("to start file");
+        zip.write_all(b"escaped").expect("to write");
+        zip.start_file("kit/.DS_Store", options)
+            .expect("to start file");
+        zip.write_all(b"junk").expect("to write");
+        let zip = zip.finish().expect("to finish").into_inner();
+
+        let response = client
+            .put("/files/datafiles/samples?extract=zip")
+            .body(zip)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let report: crate::archive::ExtractReport = response.into_json().expect("to get report");
+        let status = |name: &str| {
+            report
+                .entries
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| e.status)
+                .expect("to find entry")
+        };
+        use crate::archive::EntryStatus;
+        assert_eq!(status("kit/"), EntryStatus::Extracted);
+        assert_eq!(status("kit/kick.wav"), EntryStatus::Extracted);
+        assert_eq!(status("../evil.txt"), EntryStatus::Rejected);
+        assert_eq!(status("kit/.DS_Store"), EntryStatus::Skipped);
+        assert_eq!(report.size, 4);
+        assert_eq!(
+            "RIFF",
+            fs::read_to_string(datafiles.join("samples/kit/kick.wav"))
+                .expect("to read file")
+                .as_str()
+        );
+        assert_eq!(Some(false), fs::exists(datafiles.join("evil.txt")).ok());
+        assert_eq!(
+            Some(false),
+            fs::exists(datafiles.join("samples/kit/.DS_Store")).ok()
+        );
+
+        //files already there are left as they are and reported
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("kit/kick.wav", options)
+            .expect("to start file");
+        zip.write_all(b"NEWER").expect("to write");
+        zip.start_file("kit/snare.wav", options)
+            .expect("to start file");
+        zip.write_all(b"RIFF").expect("to write");
+        let zip = zip.finish().expect("to finish").into_inner();
+        let response = client
+            .put("/files/datafiles/samples?extract=zip")
+            .body(zip)
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let report: crate::archive::ExtractReport = response.into_json().expect("to get report");
+        assert_eq!(report.entries[0].status, EntryStatus::Conflict);
+        assert_eq!(report.entries[1].status, EntryStatus::Extracted);
+        assert_eq!(
+            fs::read(datafiles.join("samples/kit/kick.wav")).expect("to read file"),
+            b"RIFF"
+        );
+        assert!(datafiles.join("samples/kit/snare.wav").exists());
+
+        //anything past the size limit is rejected
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::fast(),
+        ));
+        let big = vec![0u8; 2 * 1024 * 1024];
+        let mut header = tar::Header::new_gnu();
+        header.set_size(big.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "big.raw", big.as_slice())
+            .expect("to append");
+        let tgz = builder
+            .into_inner()
+            .expect("to finish tar")
+            .finish()
+            .expect("to finish gzip");
+        let response = client
+            .put("/files/datafiles/?extract=tar.gz")
+            .body(tgz)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let report: crate::archive::ExtractReport = response.into_json().expect("to get report");
+        assert!(report.truncated);
+        assert_eq!(Some(false), fs::exists(datafiles.join("big.raw")).ok());
+
+        let response = client
+            .put("/files/datafiles/samples?extract=rar")
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        //archives count against the filetype's upload size
+        let response = client
+            .put("/files/recordings/?extract=zip")
+            .header(rocket::http::Header::new("Content-Length", "65"))
+            .body(vec![0u8; 65])
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+
+        //the staged archives are cleaned up
+        let list: crate::filelist::FileList = client
+            .get("/files/datafiles/")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get list");
+        assert_eq!(list.items.len(), 3);
+        assert_eq!(
+            fs::read_dir(datafiles.join(".uploads"))
+                .expect("to read staging")
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn move_copy_mkdir() {
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+
+        let response = client
+            .post("/files/mkdir")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "path": "kit/snares"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let item: crate::filelist::FileListItem = response.into_json().expect("to get item");
+        assert!(item.dir);
+        assert_eq!(item.uri, "/files/datafiles/kit/snares");
+        assert!(datafiles.join("kit/snares").is_dir());
+
+        let response = client
+            .post("/files/mkdir")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "path": "kit"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let response = client
+            .post("/files/move")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "from": "second.txt", "to": "kit/renamed.txt"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(Some(false), fs::exists(datafiles.join("second.txt")).ok());
+        assert_eq!(
+            "Fourth World Vol. 1 Possible Musics",
+            fs::read_to_string(datafiles.join("kit/renamed.txt"))
+                .expect("to read file")
+                .as_str()
+        );
+
+        let response = client
+            .post("/files/copy")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "from": "kit", "to": "kit2"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert!(datafiles.join("kit2/snares").is_dir());
+        assert!(datafiles.join("kit/renamed.txt").is_file());
+        assert!(datafiles.join("kit2/renamed.txt").is_file());
+
+        //conflicts fail unless asked to overwrite
+        let response = client
+            .post("/files/copy")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "from": "deleteme.txt", "to": "kit/renamed.txt"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let response = client
+            .post("/files/copy")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"filetype": "datafiles", "from": "deleteme.txt", "to": "kit/renamed.txt", "on_conflict": "overwrite"}"#,
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(
+            "Delete the world!",
+            fs::read_to_string(datafiles.join("kit/renamed.txt"))
+                .expect("to read file")
+                .as_str()
+        );
+
+        //directories are swapped in whole, the old one is only removed afterwards
+        fs::create_dir(datafiles.join("kit3")).expect("to create dir");
+        fs::write(datafiles.join("kit3/only.txt"), "old").expect("to write");
+        let response = client
+            .post("/files/copy")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"filetype": "datafiles", "from": "kit", "to": "kit3", "on_conflict": "overwrite"}"#,
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert!(!datafiles.join("kit3/only.txt").exists());
+        assert!(datafiles.join("kit3/renamed.txt").is_file());
+        let response = client
+            .post("/files/move")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"filetype": "datafiles", "from": "kit/renamed.txt", "to": "kit2", "on_conflict": "overwrite"}"#,
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert!(datafiles.join("kit2").is_file());
+        //nothing staged is left behind
+        let leftover = fs::read_dir(&datafiles)
+            .expect("to list")
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(".kit"))
+            .count();
+        assert_eq!(leftover, 0);
+
+        for body in [
+            r#"{"filetype": "datafiles", "from": "deleteme.txt", "to": "../escaped.txt"}"#,
+            r#"{"filetype": "datafiles", "from": "kit", "to": "kit/inside"}"#,
+            r#"{"filetype": "datafiles", "from": "deleteme.txt", "to": ".uploads/x"}"#,
+        ] {
+            let response = client
+                .post("/files/move")
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest);
+        }
+
+        let response = client
+            .post("/files/move")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "from": "nothere.txt", "to": "there.txt"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client
+            .post("/files/move")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "backup", "from": "nodelete.txt", "to": "moved.txt"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    //a 16 bit pcm wav file holding `samples`, interleaved
+    fn wav_bytes(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+        let data_len = (samples.len() * 2) as u32;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&channels.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+        wav.extend_from_slice(&(channels * 2).to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for s in samples {
+            wav.extend_from_slice(&s.to_le_bytes());
+        }
+        wav
+    }
+
+    #[test]
+    fn audio_metadata() {
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+
+        //half a second of stereo silence
+        let response = client
+            .put("/files/datafiles/kit/silence.wav")
+            .body(wav_bytes(48000, 2, &vec![0; 48000]))
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let info: crate::audio::AudioInfo = response.into_json().expect("to get info");
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.bit_depth, Some(16));
+        assert_eq!(info.frames, Some(24000));
+        assert_eq!(info.duration, Some(0.5));
+
+        let response = client
+            .get("/files/datafiles/kit/silence.wav?info=true")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let info: crate::audio::AudioInfo = response.into_json().expect("to get info");
+        assert_eq!(info.frames, Some(24000));
+
+        //unreadable audio is stored unless asked to validate
+        let response = client
+            .put("/files/datafiles/broken.wav")
+            .body("not really a wav file")
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let check: serde_json::Value = response.into_json().expect("to get check");
+        assert!(check["error"].is_string());
+        assert!(datafiles.join("broken.wav").is_file());
+
+        let response = client
+            .put("/files/datafiles/rejected.wav?validate=true")
+            .body("not really a wav file")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(Some(false), fs::exists(datafiles.join("rejected.wav")).ok());
+
+        let response = client
+            .get("/files/datafiles/broken.wav?info=true")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        //other files are stored as they are
+        let response = client
+            .put("/files/datafiles/notes.txt?validate=true")
+            .body("FOO")
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let stored: serde_json::Value = response.into_json().expect("to get json");
+        assert!(stored["sha256"].is_string());
+        assert!(stored.get("error").is_none() && stored.get("sample_rate").is_none());
+    }
+
+    #[test]
+    fn audio_analysis() {
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+
+        //a second of a half scale square wave, mono
+        let samples: Vec<i16> = (0..44100)
+            .map(|i| if (i / 50) % 2 == 0 { 16384 } else { -16384 })
+            .collect();
+        fs::write(datafiles.join("square.wav"), wav_bytes(44100, 1, &samples)).expect("to write");
+
+        let response = client
+            .get("/files/datafiles/square.wav?analysis=true")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let analysis: serde_json::Value = response.into_json().expect("to get analysis");
+        assert_eq!(analysis["frames"], 44100);
+        assert_eq!(analysis["channels"], 1);
+        let peak = analysis["peak_dbfs"].as_f64().expect("to get peak");
+        let rms = analysis["rms_dbfs"].as_f64().expect("to get rms");
+        assert!((peak + 6.02).abs() < 0.01, "peak {peak}");
+        assert!((rms + 6.02).abs() < 0.01, "rms {rms}");
+        assert!(analysis["integrated_lufs"].as_f64().is_some());
+
+        let levels = analysis["levels"].as_array().expect("to get levels");
+        assert_eq!(levels[0]["samples_per_peak"], 256);
+        assert_eq!(levels[1]["samples_per_peak"], 1024);
+        let peaks = levels[0]["peaks"][0].as_array().expect("to get peaks");
+        assert_eq!(peaks.len(), 44100usize.div_ceil(256));
+        assert_eq!(peaks[0][0], -0.5);
+        assert_eq!(peaks[0][1], 0.5);
+
+        //the result is cached next to the file, hidden from listings
+        let cache = datafiles.join(".square.wav.analysis.json");
+        assert!(cache.is_file());
+        let list: crate::filelist::FileList = client
+            .get("/files/datafiles/")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get list");
+        assert_eq!(list.items.len(), 3);
+
+        //a changed file is analyzed again
+        fs::write(datafiles.join("square.wav"), wav_bytes(44100, 1, &[0; 100])).expect("to write");
+        let analysis: serde_json::Value = client
+            .get("/files/datafiles/square.wav?analysis=true")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get analysis");
+        assert_eq!(analysis["frames"], 100);
+        assert!(analysis["peak_dbfs"].is_null());
+
+        let response = client
+            .get("/files/datafiles/second.txt?analysis=true")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        //a moved file takes its analysis along
+        let response = client
+            .post("/files/move")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "from": "square.wav", "to": "moved.wav"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        assert!(!cache.exists());
+        let cache = datafiles.join(".moved.wav.analysis.json");
+        assert!(cache.is_file());
+
+        let response = client.delete("/files/datafiles/moved.wav").dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        assert!(!cache.exists());
+
+        //nothing is written into filetypes that are only read
+        let backup = resources.tempdir.path().join("backup");
+        fs::write(backup.join("square.wav"), wav_bytes(44100, 1, &samples)).expect("to write");
+        let response = client
+            .get("/files/backup/square.wav?analysis=true")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(!backup.join(".square.wav.analysis.json").exists());
+    }
+
+    #[test]
+    fn audio_processing() {
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+
+        //one second of stereo at 8kHz, left loud and right quiet
+        let samples: Vec<i16> = (0..8000).flat_map(|_| [16000, 1000]).collect();
+        fs::write(datafiles.join("stereo.wav"), wav_bytes(8000, 2, &samples)).expect("to write");
+
+        let request = serde_json::json!({
+            "from": "stereo.wav",
+            "to": "out/left.wav",
+            "float": true,
+            "operations": [
+                { "op": "trim", "start": 0.25, "end": 0.75 },
+                { "op": "channel", "channel": 0 },
+                { "op": "normalize" },
+                { "op": "fade", "in": 0.1, "out": 0.1 },
+                { "op": "resample", "sample_rate": 16000 }
+            ]
+        });
+        let response = client
+            .post("/files/process")
+            .header(ContentType::JSON)
+            .body(request.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+
+        let status = wait_for_job(&client, &location);
+        assert_eq!(status["kind"], "process");
+        assert_eq!(status["state"], "finished");
+        assert_eq!(status["uri"], "/files/datafiles/out/left.wav");
+
+        let info: crate::audio::AudioInfo = client
+            .get("/files/datafiles/out/left.wav?info=true")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get info");
+        assert_eq!(info.sample_rate, 16000);
+        assert_eq!(info.channels, 1);
+        assert_eq!(info.bit_depth, Some(32));
+        assert_eq!(info.frames, Some(8000));
+        let analysis: serde_json::Value = client
+            .get("/files/datafiles/out/left.wav?analysis=true")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get analysis");
+        assert!(analysis["peak_dbfs"].as_f64().unwrap().abs() < 0.5);
+
+        //the source is untouched and existing files are only replaced when asked to
+        assert!(datafiles.join("stereo.wav").is_file());
+        let response = client
+            .post("/files/process")
+            .header(ContentType::JSON)
+            .body(request.to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let response = client
+            .post("/files/process")
+            .header(ContentType::JSON)
+            .body(r#"{"from": "second.txt", "to": "second.wav", "operations": []}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let response = client
+            .post("/files/process")
+            .header(ContentType::JSON)
+            .body(r#"{"from": "stereo.wav", "to": "stereo.flac", "operations": []}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        //a cancelled job leaves neither its result nor its staged file behind
+        let samples: Vec<i16> = (0..48000 * 10).map(|i| (i % 1000) as i16).collect();
+        fs::write(datafiles.join("long.wav"), wav_bytes(48000, 1, &samples)).expect("to write");
+        let response = client
+            .post("/files/process")
+            .header(ContentType::JSON)
+            .body(
+                r#"{"from": "long.wav", "to": "long-out.wav",
+                "operations": [{"op": "normalize"}, {"op": "resample", "sample_rate": 44100}]}"#,
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let status: serde_json::Value = client
+            .delete(location.as_str())
+            .dispatch()
+            .into_json()
+            .expect("to get job status");
+        assert_eq!(status["state"], "cancelled");
+        //uncancelled, the job takes well under this
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        assert!(!datafiles.join("long-out.wav").exists());
+        let staging = datafiles.join(crate::uploads::STAGING_DIR);
+        assert!(fs::read_dir(&staging).is_ok_and(|mut d| d.next().is_none()));
+    }
+
+    #[test]
+    fn change_notifications() {
+        use crate::watch::{ChangeKind, FsWatch};
+        let (client, resources) = setup();
+        let datafiles = resources.tempdir.path().join("datafiles");
+        let mut rx = client
+            .rocket()
+            .state::<FsWatch>()
+            .expect("to get watch")
+            .subscribe();
+        //wait for a change, skipping the ones that aren't of interest
+        let mut expect = |kind: ChangeKind, path: &str| {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            loop {
+                match rx.try_recv() {
+                    Ok(change) if change.kind == kind && change.path.as_deref() == Some(path) => {
+                        assert_eq!(change.filetype.as_deref(), Some("datafiles"));
+                        return change;
+                    }
+                    Ok(_) => {}
+                    Err(_) => {
+                        assert!(
+                            std::time::Instant::now() < deadline,
+                            "no {kind:?} for {path}"
+                        );
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                    }
+                }
+            }
+        };
+
+        //through the api, the upload is staged in a hidden directory and then renamed into place
+        let response = client
+            .put("/files/datafiles/kit/new.txt")
+            .body("new")
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        expect(ChangeKind::Created, "kit/new.txt");
+
+        let response = client
+            .post("/files/move")
+            .header(ContentType::JSON)
+            .body(r#"{"filetype": "datafiles", "from": "kit/new.txt", "to": "old.txt"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let change = expect(ChangeKind::Renamed, "old.txt");
+        assert_eq!(change.from.as_deref(), Some("kit/new.txt"));
+
+        let response = client.delete("/files/datafiles/old.txt").dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        expect(ChangeKind::Deleted, "old.txt");
+
+        //and from elsewhere
+        fs::write(datafiles.join("second.txt"), "changed").expect("to write");
+        expect(ChangeKind::Modified, "second.txt");
+        fs::rename(
+            datafiles.join("second.txt"),
+            resources.tempdir.path().join("gone.txt"),
+        )
+        .expect("to move");
+        expect(ChangeKind::Deleted, "second.txt");
+    }
+
+    #[test]
+    fn package_job() {
+        let (client, _resources) = setup();
+
+        let response = client.post("/packages/foo/bar").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client
+            .get(format!("/jobs/{}", uuid::Uuid::new_v4()))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        //there is no runner to talk to, so the job should start and then fail
+        let response = client.post("/packages/all?include_presets=true").dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let status: serde_json::Value = response.into_json().expect("to get job status");
+        assert_eq!(status["kind"], "package");
+        assert_eq!(
+            location,
+            format!("/jobs/{}", status["id"].as_str().unwrap())
+        );
+
+        let status = wait_for_job(&client, &location);
+        assert_eq!(status["state"], "failed");
+        assert_eq!(status["error"]["status"], 424);
+        assert_eq!(status["error"]["kind"], "runner_unavailable");
+
+        //cancelling a finished job leaves it as it was
+        let response = client.delete(location.as_str()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let status: serde_json::Value = response.into_json().expect("to get job status");
+        assert_eq!(status["state"], "failed");
+    }
+
+    #[test]
+    fn package_install() {
+        let (client, resources) = setup();
+        let packages = resources.tempdir.path().join("packages");
+
+        let response = client
+            .post("/packages/install/notes.txt")
+            .body("not a package")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        //without a runner there is no version to store the package under or anyone to install it
+        let response = client
+            .post("/packages/install/new.rnbopack")
+            .body("not really a tar file")
+            .dispatch();
+        assert_eq!(response.status(), Status::FailedDependency);
+        assert!(
+            !packages
+                .join(CURRENT_RNBO_VERSION)
+                .join("new.rnbopack")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn package_install_runner() {
+        let fake = crate::fake_runner::FakeRunner::start(CURRENT_RNBO_VERSION);
+        let (client, resources) = setup_with(crate::paths::SymlinkPolicy::default(), fake.port());
+        let dir = resources
+            .tempdir
+            .path()
+            .join("packages")
+            .join(CURRENT_RNBO_VERSION);
+        let dest = dir.join("demo.rnbopack");
+        //the runner finds the package under the name it is given, before it takes its place
+        {
+            let (dir, dest) = (dir.clone(), dest.clone());
+            fake.on("package_install", move |params| {
+                let filename = params["filename"].as_str().unwrap_or_default();
+                if !dir.join(filename).is_file() || dest.exists() {
+                    return vec![serde_json::json!({ "error": "no such package" })];
+                }
+                vec![
+                    serde_json::json!({ "result": { "progress": 50 } }),
+                    serde_json::json!({ "result": { "message": "completed", "progress": 100 } }),
+                ]
+            });
+        }
+        let staged = || {
+            fs::read_dir(&dir)
+                .expect("to read dir")
+                .flatten()
+                .filter(|e| e.file_name().to_string_lossy().starts_with('.') && e.path().is_file())
+                .count()
+        };
+        let uri = format!("/files/packages/{CURRENT_RNBO_VERSION}/demo.rnbopack");
+
+        //a line of json for each change of the job
+        let response = client
+            .post("/packages/install/demo.rnbopack")
+            .body(test_package(CURRENT_RNBO_VERSION, &[]))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "x-ndjson"))
+        );
+        assert!(response.headers().get_one("Location").is_some());
+        assert!(response.headers().get_one("Content-Digest").is_some());
+        let body = response.into_string().expect("to get body");
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("to parse line"))
+            .collect();
+        let last = lines.last().expect("to get a line");
+        assert_eq!(last["kind"], "install");
+        assert_eq!(last["state"], "finished");
+        assert_eq!(last["uri"], uri.as_str());
+        assert!(lines.iter().all(|l| l["state"] != "failed"));
+        assert!(dest.exists());
+        assert_eq!(staged(), 0);
+        let (method, params) = &fake.commands()[0];
+        assert_eq!(method, "package_install");
+        assert!(
+            params["filename"]
+                .as_str()
+                .unwrap()
+                .ends_with(".demo.rnbopack")
+        );
+        fs::remove_file(&dest).expect("to remove");
+
+        //or as server sent events
+        let response = client
+            .post("/packages/install/demo.rnbopack")
+            .header(Accept::EventStream)
+            .body(test_package(CURRENT_RNBO_VERSION, &[]))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::EventStream));
+        let body = response.into_string().expect("to get body");
+        let events: Vec<serde_json::Value> = body
+            .lines()
+            .filter_map(|l| l.strip_prefix("data:"))
+            .map(|data| serde_json::from_str(data).expect("to parse event"))
+            .collect();
+        assert_eq!(events.last().expect("to get an event")["state"], "finished");
+
+        //an answer without a result fails the install rather than waiting for the timeout,
+        //and leaves the stored package alone
+        fs::write(&dest, b"installed").expect("to write");
+        fake.on("package_install", |_| vec![serde_json::json!({})]);
+        let started = std::time::Instant::now();
+        let response = client
+            .post("/packages/install/demo.rnbopack")
+            .body(test_package(CURRENT_RNBO_VERSION, &[]))
+            .dispatch();
+        let body = response.into_string().expect("to get body");
+        let last: serde_json::Value =
+            serde_json::from_str(body.lines().last().expect("to get a line")).expect("to parse");
+        assert_eq!(last["state"], "failed");
+        assert_eq!(last["error"]["status"], 424);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(fs::read(&dest).expect("to read"), b"installed");
+        assert_eq!(staged(), 0);
+    }
+
+    //a small package laid out the way the runner exports them, with `extra` entries appended
+    fn test_package(version: &str, extra: &[(&str, &[u8])]) -> Vec<u8> {
+        let info = serde_json::json!({
+            "name": "demo",
+            "rnbo_version": version,
+            "runner_version": CURRENT_RNBO_VERSION,
+            "schema_version": 1,
+            "sets": [{"name": "main", "location": "sets/main.json", "created_at": "2025-01-01"}],
+            "patchers": [{
+                "name": "synth",
+                "patcher": "patchers/synth.json",
+                "config": "patchers/synth.conf.json",
+                "presets": "patchers/synth.presets.json",
+                "binaries": {"aarch64-linux-gnu": "patchers/aarch64-linux-gnu/libsynth.so"},
+                "created_at": "2025-01-01",
+            }],
+            "datafiles": [{"name": "kick.wav", "location": "datafiles/kick.wav"}],
+            "targets": {"aarch64-linux-gnu": {
+                "compiler_id": "GNU",
+                "compiler_version": "12.2.0",
+                "dir": "aarch64-linux-gnu",
+                "system_name": "Linux",
+                "system_processor": "aarch64",
+            }},
+        });
+        let set = serde_json::json!({"views": {"0": {"name": "Mixer"}}});
+        let presets = serde_json::json!([{"name": "init"}, {"name": "loud"}]);
+        let entries: [(&str, Vec<u8>); 7] = [
+            ("demo/info.json", info.to_string().into_bytes()),
+            ("demo/sets/main.json", set.to_string().into_bytes()),
+            ("demo/patchers/synth.json", b"{}".to_vec()),
+            ("demo/patchers/synth.conf.json", b"{}".to_vec()),
+            (
+                "demo/patchers/synth.presets.json",
+                presets.to_string().into_bytes(),
+            ),
+            (
+                "demo/patchers/aarch64-linux-gnu/libsynth.so",
+                vec![0u8; 1000],
+            ),
+            ("demo/datafiles/kick.wav", b"RIFF".to_vec()),
+        ];
+        let entries: Vec<(&str, &[u8])> = entries
+            .iter()
+            .map(|(name, data)| (*name, data.as_slice()))
+            .chain(extra.iter().copied())
+            .collect();
+        test_tar(&entries)
+    }
+
+    fn test_tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in entries.iter().copied() {
+            //set the name directly, the builder refuses the paths that are tested for
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, data).expect("to append");
+        }
+        builder.into_inner().expect("to finish tar")
+    }
+
+    #[test]
+    fn package_manifest() {
+        let (client, resources) = setup();
+        let dir = resources
+            .tempdir
+            .path()
+            .join("packages")
+            .join(CURRENT_RNBO_VERSION);
+        let package = test_package(CURRENT_RNBO_VERSION, &[]);
+        fs::write(dir.join("demo.rnbopack"), &package).expect("to write");
+
+        let response = client
+            .get(format!(
+                "/files/packages/{CURRENT_RNBO_VERSION}/demo.rnbopack?manifest=true"
+            ))
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let manifest: crate::rnbopack::Manifest = response.into_json().expect("to get manifest");
+        assert_eq!(manifest.name, "demo");
+        assert_eq!(manifest.rnbo_version.as_deref(), Some(CURRENT_RNBO_VERSION));
+        assert_eq!(manifest.sets.len(), 1);
+        assert_eq!(manifest.patchers.len(), 1);
+        assert_eq!(manifest.patchers[0].size, 4);
+        assert_eq!(manifest.presets[0].names, vec!["init", "loud"]);
+        assert_eq!(manifest.views[0].set, "main");
+        assert_eq!(manifest.views[0].name, "Mixer");
+        assert_eq!(manifest.datafiles[0].size, 4);
+        assert_eq!(manifest.binaries[0].target, "aarch64-linux-gnu");
+        assert_eq!(manifest.binaries[0].size, 1000);
+        assert_eq!(
+            manifest.targets["aarch64-linux-gnu"]
+                .system_processor
+                .as_deref(),
+            Some("aarch64")
+        );
+        assert_eq!(manifest.sizes.binaries, 1000);
+        assert!(manifest.sizes.total > 1000);
+        assert_eq!(manifest.file_size, package.len() as u64);
+
+        //the fixture package isn't a tar
+        let response = client
+            .get(format!(
+                "/files/packages/{CURRENT_RNBO_VERSION}/foo.rnbopack?manifest=true"
+            ))
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
 
-    struct Resources {
-        tempdir: TempDir,
-    }
+        //the test config allows packages to unpack to a mebibyte
+        let big = vec![0u8; 2 * 1024 * 1024];
+        fs::write(
+            dir.join("big.rnbopack"),
+            test_package(CURRENT_RNBO_VERSION, &[("demo/big.raw", &big)]),
+        )
+        .expect("to write");
+        let response = client
+            .get(format!(
+                "/files/packages/{CURRENT_RNBO_VERSION}/big.rnbopack?manifest=true"
+            ))
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
 
-    impl Resources {
-        fn new() -> Self {
-            Self {
-                tempdir: TempDir::new("runner-panel").expect("to get temp dir"),
-            }
-        }
+        let response = client
+            .get("/files/datafiles/second.txt?manifest=true")
+            .header(Accept::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
     }
 
-    //minimal server
-    fn setup() -> (Client, Resources) {
-        use std::io::prelude::*;
-        let resources = Resources::new();
-        let mut filetype_paths = HashMap::new();
-        let mut deleteable_filetypes = HashSet::new();
+    #[test]
+    fn package_validation() {
+        use crate::rnbopack::{PackageReport, ProblemKind};
 
-        let datafiles = resources.tempdir.path().join("datafiles");
-        let source_cache = resources.tempdir.path().join("source_cache");
-        let package_dir = resources.tempdir.path().join("packages");
-        let current_package_dir = package_dir.join(CURRENT_RNBO_VERSION);
+        let (client, resources) = setup();
+        let dir = resources
+            .tempdir
+            .path()
+            .join("packages")
+            .join(CURRENT_RNBO_VERSION);
+        let put = |version: &str, body: Vec<u8>| {
+            client
+                .put(format!("/files/packages/{version}/demo.rnbopack"))
+                .body(body)
+                .dispatch()
+        };
+        let kinds =
+            |report: &PackageReport| report.problems.iter().map(|p| p.kind).collect::<Vec<_>>();
 
-        let backup = resources.tempdir.path().join("backup");
+        let response = put(
+            CURRENT_RNBO_VERSION,
+            test_package(CURRENT_RNBO_VERSION, &[]),
+        );
+        assert_eq!(response.status(), Status::Created);
+        assert!(dir.join("demo.rnbopack").exists());
 
-        fs::create_dir_all(&datafiles).expect("to create dir");
-        fs::create_dir_all(&source_cache).expect("to create dir");
-        fs::create_dir_all(&package_dir).expect("to create dir");
-        fs::create_dir_all(&current_package_dir).expect("to create dir");
-        fs::create_dir_all(&backup).expect("to create dir");
+        //stored for another version than it was exported for
+        let response = put("0.9.0", test_package(CURRENT_RNBO_VERSION, &[]));
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let report: PackageReport = response.into_json().expect("to get report");
+        assert!(!report.valid);
+        assert_eq!(kinds(&report), vec![ProblemKind::Version]);
+        assert_eq!(report.manifest.expect("to get manifest").name, "demo");
 
-        filetype_paths.insert("datafiles".to_owned(), datafiles.clone());
-        filetype_paths.insert("source_cache".to_owned(), source_cache);
-        filetype_paths.insert("backup".to_owned(), backup.clone());
-        filetype_paths.insert("packages".to_owned(), package_dir.clone());
+        let package = test_package(
+            CURRENT_RNBO_VERSION,
+            &[("demo/../evil.txt", b"evil"), ("other/file.txt", b"stray")],
+        );
+        let response = put(CURRENT_RNBO_VERSION, package);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let report: PackageReport = response.into_json().expect("to get report");
+        assert_eq!(kinds(&report), vec![ProblemKind::Path, ProblemKind::Path]);
+        assert_eq!(
+            report.problems[0].entry.as_deref(),
+            Some("demo/../evil.txt")
+        );
 
-        deleteable_filetypes.insert("datafiles".to_owned());
-        deleteable_filetypes.insert("packages".to_owned());
+        //cut off in the middle of the binary
+        let mut package = test_package(CURRENT_RNBO_VERSION, &[]);
+        package.truncate(4096);
+        let response = put(CURRENT_RNBO_VERSION, package);
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let report: PackageReport = response.into_json().expect("to get report");
+        assert_eq!(kinds(&report), vec![ProblemKind::Integrity]);
+        assert!(report.manifest.is_none());
 
-        let f = datafiles.join("deleteme.txt");
-        let mut file = fs::File::create(&f).expect("to create");
-        file.write_all(b"Delete the world!").expect("to write");
+        //the test config allows archives to unpack to a mebibyte
+        let big = vec![0u8; 2 * 1024 * 1024];
+        let response = put(
+            CURRENT_RNBO_VERSION,
+            test_package(CURRENT_RNBO_VERSION, &[("demo/big.raw", &big)]),
+        );
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let report: PackageReport = response.into_json().expect("to get report");
+        assert_eq!(kinds(&report), vec![ProblemKind::Size]);
 
-        let f = datafiles.join("second.txt");
-        let mut file = fs::File::create(&f).expect("to create");
-        file.write_all(b"Fourth World Vol. 1 Possible Musics")
-            .expect("to write");
+        //a pax sparse file, small as stored but not when unpacked
+        let mut package = test_package(CURRENT_RNBO_VERSION, &[]);
+        package.truncate(package.len() - 1024);
+        let mut builder = tar::Builder::new(package);
+        let mut records = Vec::new();
+        for (key, value) in [
+            ("GNU.sparse.major", "1"),
+            ("GNU.sparse.minor", "0"),
+            ("GNU.sparse.realsize", "1073741824"),
+        ] {
+            //the length of a record counts its own digits
+            let rest = format!(" {key}={value}\n");
+            let mut len = rest.len() + 1;
+            while len.to_string().len() + rest.len() != len {
+                len += 1;
+            }
+            records.extend(format!("{len}{rest}").into_bytes());
+        }
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::XHeader);
+        header.set_size(records.len() as u64);
+        builder
+            .append_data(
+                &mut header,
+                "demo/PaxHeaders/sparse.raw",
+                records.as_slice(),
+            )
+            .expect("to append");
+        let mut header = tar::Header::new_ustar();
+        header.set_size(512);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "demo/sparse.raw", [0u8; 512].as_slice())
+            .expect("to append");
+        let response = put(
+            CURRENT_RNBO_VERSION,
+            builder.into_inner().expect("to finish tar"),
+        );
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let report: PackageReport = response.into_json().expect("to get report");
+        assert_eq!(kinds(&report), vec![ProblemKind::Entry]);
+        assert_eq!(report.problems[0].entry.as_deref(), Some("demo/sparse.raw"));
 
-        let f = backup.join("nodelete.txt");
-        let mut file = fs::File::create(&f).expect("to create");
-        file.write_all(b"Cannot delete world!").expect("to write");
+        let response = put(CURRENT_RNBO_VERSION, b"not really a tar file".to_vec());
+        assert_eq!(response.status(), Status::UnprocessableEntity);
 
-        let f = current_package_dir.join("foo.rnbopack");
-        let mut file = fs::File::create(&f).expect("to create");
-        file.write_all(b"not really a tar file").expect("to write");
+        //resumable uploads are validated when they are finished
+        let mut package = test_package(CURRENT_RNBO_VERSION, &[]);
+        package.truncate(4096);
+        let response = client
+            .post(format!(
+                "/files/packages/{CURRENT_RNBO_VERSION}/demo.rnbopack"
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let response = client
+            .patch(location.as_str())
+            .header(rocket::http::Header::new("Upload-Offset", "0"))
+            .body(package)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.post(location.as_str()).dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let report: PackageReport = response.into_json().expect("to get report");
+        assert_eq!(kinds(&report), vec![ProblemKind::Integrity]);
+        assert_eq!(
+            client.get(location.as_str()).dispatch().status(),
+            Status::NotFound
+        );
 
-        (
-            Client::tracked(
-                rocket::build()
-                    .mount("/files", super::file_routes())
-                    .mount("/packages", super::package_routes())
-                    .manage(crate::config::Config::new(
-                        filetype_paths,
-                        deleteable_filetypes,
-                        Some(package_dir),
-                    ))
-                    .attach(Template::fairing()),
-            )
-            .expect("valid rocket instance"),
-            resources,
-        )
+        //the valid package is still the stored one
+        let stored = fs::read(dir.join("demo.rnbopack")).expect("to read package");
+        assert_eq!(stored, test_package(CURRENT_RNBO_VERSION, &[]));
     }
 
     #[test]
-    fn filetype_list() {
-        let (client, _resources) = setup();
+    fn package_selection() {
+        let (client, resources) = setup();
 
-        let response = client.get("/files/").header(Accept::HTML).dispatch();
-        assert_eq!(response.status(), Status::Ok);
-        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let response = client
+            .post("/packages/")
+            .header(ContentType::JSON)
+            .body(r#"{"include_presets": true}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client
+            .post("/packages/")
+            .header(ContentType::JSON)
+            .body(r#"{"sets": ["main"], "patchers": ["synth"], "name": "../up"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        //there is no runner to say where the packages go
+        let response = client
+            .post("/packages/")
+            .header(ContentType::JSON)
+            .body(r#"{"sets": ["main"], "patchers": ["synth"], "include_binaries": false}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::FailedDependency);
+
+        //what the runner would have made for a second patcher
+        let info = serde_json::json!({
+            "name": "bass",
+            "rnbo_version": CURRENT_RNBO_VERSION,
+            "sets": [],
+            "patchers": [{"name": "bass", "patcher": "patchers/bass.json", "binaries": {}}],
+            "datafiles": [{"name": "kick.wav", "location": "datafiles/kick.wav"}],
+            "targets": {"x86_64-linux-gnu": {"system_processor": "x86_64"}},
+        })
+        .to_string();
+        let bass = test_tar(&[
+            ("bass/info.json", info.as_bytes()),
+            ("bass/patchers/bass.json", b"{}"),
+            ("bass/datafiles/kick.wav", b"RIFF RIFF"),
+        ]);
+        let dir = resources
+            .tempdir
+            .path()
+            .join("packages")
+            .join(CURRENT_RNBO_VERSION);
+        let packages = [dir.join("demo.rnbopack"), dir.join("bass.rnbopack")];
+        fs::write(&packages[0], test_package(CURRENT_RNBO_VERSION, &[])).expect("to write");
+        fs::write(&packages[1], bass).expect("to write");
+        crate::rnbopack::merge(&packages, "both", &dir.join("both.rnbopack"), u64::MAX)
+            .expect("to merge");
+
+        let manifest: crate::rnbopack::Manifest = client
+            .get(format!(
+                "/files/packages/{CURRENT_RNBO_VERSION}/both.rnbopack?manifest=true"
+            ))
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get manifest");
+        assert_eq!(manifest.name, "both");
+        let names: Vec<&str> = manifest.patchers.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["synth", "bass"]);
+        assert_eq!(manifest.patchers[1].size, 2);
+        //the first package's copy of a shared file is kept
+        assert_eq!(manifest.datafiles.len(), 1);
+        assert_eq!(manifest.datafiles[0].size, 4);
+        assert_eq!(manifest.targets.len(), 2);
+        assert_eq!(manifest.sets.len(), 1);
     }
 
     #[test]
-    fn filetype_unknown() {
-        let (client, _resources) = setup();
-        let response = client.get("/files/foo/").header(Accept::JSON).dispatch();
-        assert_eq!(response.status(), Status::NotFound);
+    fn package_jobs_runner() {
+        let fake = crate::fake_runner::FakeRunner::start(CURRENT_RNBO_VERSION);
+        let (client, resources) = setup_with(crate::paths::SymlinkPolicy::default(), fake.port());
+        let dir = resources
+            .tempdir
+            .path()
+            .join("packages")
+            .join(CURRENT_RNBO_VERSION);
+        //what the runner makes for a set and a patcher, written where it says it put them
+        let bass = serde_json::json!({
+            "name": "bass",
+            "rnbo_version": CURRENT_RNBO_VERSION,
+            "sets": [],
+            "patchers": [{"name": "bass", "patcher": "patchers/bass.json", "binaries": {}}],
+            "datafiles": [],
+            "targets": {},
+        })
+        .to_string();
+        let bass = test_tar(&[
+            ("bass/info.json", bass.as_bytes()),
+            ("bass/patchers/bass.json", b"{}"),
+        ]);
+        {
+            let dir = dir.clone();
+            fake.on("package_create", move |params| {
+                let (name, package) = match params["set"].as_str() {
+                    Some(_) => ("demo", test_package(CURRENT_RNBO_VERSION, &[])),
+                    None => ("bass", bass.clone()),
+                };
+                fs::write(dir.join(format!("{name}.rnbopack")), package).expect("to write");
+                let filename = format!("{CURRENT_RNBO_VERSION}/{name}.rnbopack");
+                vec![
+                    serde_json::json!({ "result": { "progress": 50 } }),
+                    serde_json::json!({ "result": { "progress": 100, "filename": filename } }),
+                ]
+            });
+        }
 
-        let response = client.get("/files/foo/").header(Accept::HTML).dispatch();
-        assert_eq!(response.status(), Status::NotFound);
+        let response = client.post("/packages/patchers/bass").dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let status = wait_for_job(&client, &location);
+        assert_eq!(status["state"], "finished");
+        assert_eq!(status["progress"], 100.0);
+        assert_eq!(
+            status["uri"],
+            format!("/files/packages/{CURRENT_RNBO_VERSION}/bass.rnbopack")
+        );
+        assert_eq!(fake.commands()[0].1["patcher"], "bass");
+
+        let response = client
+            .post("/packages/")
+            .header(ContentType::JSON)
+            .body(r#"{"sets": ["main"], "patchers": ["bass"], "name": "both"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let status = wait_for_job(&client, &location);
+        assert_eq!(status["state"], "finished");
+        assert_eq!(
+            status["uri"],
+            format!("/files/packages/{CURRENT_RNBO_VERSION}/both.rnbopack")
+        );
+        let manifest: crate::rnbopack::Manifest = client
+            .get(format!("{}?manifest=true", status["uri"].as_str().unwrap()))
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get manifest");
+        let names: Vec<&str> = manifest.patchers.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["synth", "bass"]);
+        //only the combined package is kept, along with the package of bass made before
+        assert!(!dir.join("demo.rnbopack").exists());
+        assert!(dir.join("bass.rnbopack").exists());
+
+        let select = |spec: &str| {
+            let response = client
+                .post("/packages/")
+                .header(ContentType::JSON)
+                .body(spec)
+                .dispatch();
+            let status = response.status();
+            let location = response.headers().get_one("Location").map(str::to_string);
+            (status, location)
+        };
+        //nothing is packaged over an existing package
+        let (status, _) = select(r#"{"sets": ["main"], "patchers": ["bass"], "name": "both"}"#);
+        assert_eq!(status, Status::Conflict);
+
+        //or over another item
+        let (status, location) =
+            select(r#"{"sets": ["main"], "patchers": ["bass", "bass"], "name": "twice"}"#);
+        assert_eq!(status, Status::Accepted);
+        let status = wait_for_job(&client, &location.expect("to get location"));
+        assert_eq!(status["state"], "failed");
+        assert_eq!(status["error"]["status"], 409);
+        assert!(!dir.join("twice.rnbopack").exists());
+        assert!(!dir.join("demo.rnbopack").exists());
+        assert!(dir.join("bass.rnbopack").exists());
+
+        //a cancelled selection doesn't leave the packages it has made so far
+        {
+            let dir = dir.clone();
+            fake.on("package_create", move |params| {
+                if params["set"].is_string() {
+                    fs::write(dir.join("demo.rnbopack"), b"package").expect("to write");
+                    let filename = format!("{CURRENT_RNBO_VERSION}/demo.rnbopack");
+                    return vec![serde_json::json!({
+                        "result": { "progress": 100, "filename": filename }
+                    })];
+                }
+                //never finishes
+                vec![serde_json::json!({ "result": { "progress": 50 } })]
+            });
+        }
+        let (status, location) =
+            select(r#"{"sets": ["main"], "patchers": ["bass"], "name": "cancelled"}"#);
+        assert_eq!(status, Status::Accepted);
+        let location = location.expect("to get location");
+        let eventually = |done: &dyn Fn() -> bool| {
+            (0..300).any(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                done()
+            })
+        };
+        //waiting for the patcher, after the set
+        assert!(eventually(&|| fake.commands().len() == 8));
+        assert!(dir.join("demo.rnbopack").exists());
+        assert_eq!(client.delete(&location).dispatch().status(), Status::Ok);
+        assert_eq!(wait_for_job(&client, &location)["state"], "cancelled");
+        assert!(eventually(&|| !dir.join("demo.rnbopack").exists()));
     }
 
     #[test]
-    fn filetype_known() {
+    fn problem_details() {
+        use crate::problem::{ErrorKind, Problem};
+
         let (client, _resources) = setup();
+        let problem = |response: rocket::local::blocking::LocalResponse| -> Problem {
+            assert_eq!(
+                response.content_type(),
+                Some(ContentType::new("application", "problem+json"))
+            );
+            response.into_json().expect("to get problem")
+        };
 
+        //bare statuses are described by the catcher
         let response = client
-            .get("/files/datafiles/")
+            .get("/files/NOEXIST/foo.txt")
             .header(Accept::JSON)
             .dispatch();
-        assert_eq!(response.status(), Status::Ok);
-        assert_eq!(response.content_type(), Some(ContentType::JSON));
-
-        let list: Option<crate::filelist::FileList> = response.into_json();
-        assert!(list.is_some());
+        assert_eq!(response.status(), Status::NotFound);
+        let body = problem(response);
+        assert_eq!(body.status, 404);
+        assert_eq!(body.kind, ErrorKind::NotFound);
+        assert_eq!(body.title, "Not Found");
+        assert_eq!(body.instance.as_deref(), Some("/files/NOEXIST/foo.txt"));
 
-        let list = list.unwrap();
+        let response = client.delete("/files/backup/nodelete.txt").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(problem(response).kind, ErrorKind::AccessDenied);
 
-        assert_eq!(list.items.len(), 2);
-        assert_eq!(list.filetype.as_str(), "datafiles");
-        assert!(
-            list.items
-                .iter()
-                .find(|f| f.name.as_str() == "deleteme.txt")
-                .is_some()
-        );
-        assert!(
-            list.items
-                .iter()
-                .find(|f| f.name.as_str() == "second.txt")
-                .is_some()
-        );
+        let response = client.post("/packages/foo/bar").dispatch();
+        assert_eq!(problem(response).kind, ErrorKind::NotFound);
 
+        //runner errors say so
         let response = client
-            .get("/files/datafiles/")
-            .header(Accept::HTML)
+            .post("/packages/install/new.rnbopack")
+            .body("not really a tar file")
             .dispatch();
-        assert_eq!(response.status(), Status::Ok);
-        assert_eq!(response.content_type(), Some(ContentType::HTML));
-
-        let response = client.get("/files/datafiles/second.txt").dispatch();
-        assert_eq!(response.status(), Status::Ok);
-        assert_eq!(
-            response.into_string().unwrap().as_str(),
-            "Fourth World Vol. 1 Possible Musics"
-        );
+        assert_eq!(response.status(), Status::FailedDependency);
+        let body = problem(response);
+        assert_eq!(body.kind, ErrorKind::RunnerUnavailable);
+        assert!(body.detail.is_some());
+        //packaging through a GET doesn't wait for the job either
+        let response = client.get("/packages/all").dispatch();
+        assert_eq!(response.status(), Status::Accepted);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let status = wait_for_job(&client, &location);
+        assert_eq!(status["state"], "failed");
+        assert_eq!(status["error"]["kind"], "runner_unavailable");
 
+        //and anything with more to say keeps its members
         let response = client
-            .get(format!(
-                "/files/packages/{}/foo.rnbopack",
-                CURRENT_RNBO_VERSION
+            .put("/files/datafiles/second.txt")
+            .header(rocket::http::Header::new(
+                "Content-Digest",
+                format!("sha-256=:{}:", "A".repeat(43) + "="),
             ))
-            .header(Accept::HTML)
+            .body("changed")
             .dispatch();
-        assert_eq!(response.status(), Status::Ok);
-        assert_eq!(response.content_type(), Some(ContentType::TAR));
+        assert_eq!(response.status(), Status::BadRequest);
+        let body = problem(response);
+        assert_eq!(body.kind, ErrorKind::DigestMismatch);
+        assert!(body.extensions["sha256"].is_string());
     }
 
     #[test]
-    fn delete() {
+    fn resumable_upload() {
+        use rocket::http::Header;
+
         let (client, resources) = setup();
+        let dest = resources.tempdir.path().join("datafiles/samples/big.wav");
 
-        let p = resources.tempdir.path().join("datafiles/deleteme.txt");
-        assert_eq!(Some(true), fs::exists(&p).ok());
-        let response = client.delete("/files/datafiles/deleteme.txt").dispatch();
-        assert_eq!(response.status(), Status::NoContent);
-        assert_eq!(Some(false), fs::exists(&p).ok());
+        let response = client
+            .post("/files/NOEXIST/big.wav")
+            .header(Header::new("Upload-Length", "10"))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
 
         let response = client
-            .get("/files/datafiles/")
-            .header(Accept::JSON)
+            .post("/files/datafiles/samples/big.wav")
+            .header(Header::new("Upload-Length", "10"))
             .dispatch();
-        assert_eq!(response.status(), Status::Ok);
-        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(response.status(), Status::Created);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let session: serde_json::Value = response.into_json().expect("to get session");
+        assert_eq!(session["offset"], 0);
+        assert_eq!(session["length"], 10);
+        assert_eq!(
+            location,
+            format!("/files/uploads/{}", session["id"].as_str().unwrap())
+        );
 
-        let list: Option<crate::filelist::FileList> = response.into_json();
-        assert!(list.is_some());
+        let response = client
+            .patch(location.as_str())
+            .header(Header::new("Upload-Offset", "0"))
+            .body("0123")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Upload-Offset"), Some("4"));
 
-        let list = list.unwrap();
+        //the wrong offset is rejected, as is more data than was announced
+        let response = client
+            .patch(location.as_str())
+            .header(Header::new("Upload-Offset", "2"))
+            .body("23456789")
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let response = client
+            .patch(location.as_str())
+            .header(Header::new("Upload-Offset", "4"))
+            .body("456789ABCD")
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
 
-        assert_eq!(list.items.len(), 1);
-        assert_eq!(list.filetype.as_str(), "datafiles");
-        assert!(
-            list.items
-                .iter()
-                .find(|f| f.name.as_str() == "deleteme.txt")
-                .is_none()
-        );
-        assert!(
-            list.items
-                .iter()
-                .find(|f| f.name.as_str() == "second.txt")
-                .is_some()
-        );
+        //incomplete uploads can't be finished
+        let response = client.post(location.as_str()).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
 
-        let p = resources.tempdir.path().join("backup/nodelete.txt");
-        assert_eq!(Some(true), fs::exists(&p).ok());
-        let response = client.delete("/files/backup/nodelete.txt").dispatch();
-        assert_eq!(response.status(), Status::Unauthorized);
-        assert_eq!(Some(true), fs::exists(&p).ok());
-    }
+        let response = client.get(location.as_str()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let session: serde_json::Value = response.into_json().expect("to get session");
+        assert_eq!(session["offset"], 4);
 
-    #[test]
-    fn put() {
-        let (client, resources) = setup();
+        let response = client
+            .patch(location.as_str())
+            .header(Header::new("Upload-Offset", "4"))
+            .body("456789")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(Some(false), fs::exists(&dest).ok());
 
-        let p = resources.tempdir.path().join("datafiles/blah.txt");
-        assert_eq!(Some(false), fs::exists(&p).ok());
+        //staged data doesn't show up in listings
+        let list: crate::filelist::FileList = client
+            .get("/files/datafiles/")
+            .header(Accept::JSON)
+            .dispatch()
+            .into_json()
+            .expect("to get list");
+        assert_eq!(list.items.len(), 2);
 
+        //finishing checks the digest like a PUT does, a mismatch abandons the upload
         let response = client
-            .put("/files/datafiles/blah.txt")
-            .body("FOO")
+            .post(location.as_str())
+            .header(Header::new(
+                "Content-Digest",
+                format!("sha-256=:{}=:", "A".repeat(43)),
+            ))
             .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let problem: crate::problem::Problem = response.into_json().expect("to get problem");
+        assert_eq!(problem.kind, crate::problem::ErrorKind::DigestMismatch);
+        assert_eq!(
+            client.get(location.as_str()).dispatch().status(),
+            Status::NotFound
+        );
+        assert_eq!(Some(false), fs::exists(&dest).ok());
 
+        let response = client
+            .post("/files/datafiles/samples/big.wav")
+            .header(Header::new("Upload-Length", "10"))
+            .dispatch();
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        client
+            .patch(location.as_str())
+            .header(Header::new("Upload-Offset", "0"))
+            .body("0123456789")
+            .dispatch();
+        let response = client.post(location.as_str()).dispatch();
         assert_eq!(response.status(), Status::Created);
-        assert_eq!(Some(true), fs::exists(&p).ok());
-        let contents = fs::read_to_string(&p).expect("Should have been able to read the file");
-        assert_eq!("FOO", contents.as_str());
+        assert!(response.headers().get_one("Content-Digest").is_some());
+        assert_eq!(
+            "0123456789",
+            fs::read_to_string(&dest).expect("to read file").as_str()
+        );
 
-        let response = client.put("/files/NOEXIST/blah.txt").body("FOO").dispatch();
+        let response = client.get(location.as_str()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
 
-        assert_eq!(response.status(), Status::BadRequest);
+        //aborting removes the staged data
+        let response = client.post("/files/datafiles/aborted.wav").dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let location = response
+            .headers()
+            .get_one("Location")
+            .expect("to get location")
+            .to_string();
+        let response = client.delete(location.as_str()).dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client.get(location.as_str()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
     }
 }
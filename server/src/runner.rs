@@ -0,0 +1,416 @@
+use {
+    futures_util::{SinkExt, StreamExt},
+    reqwest_websocket::{Message, RequestBuilderExt},
+    rocket::http::Status,
+    rosc::{OscMessage, OscPacket, OscType},
+    serde::{Deserialize, Serialize, de::DeserializeOwned},
+    std::{
+        collections::{HashMap, HashSet},
+        fmt,
+        sync::{Arc, Mutex, Once},
+        time::Duration,
+    },
+    tokio::sync::{mpsc, watch},
+    uuid::Uuid,
+};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunnerError {
+    //couldn't connect or the runner responded with something we couldn't use
+    Unavailable,
+    //the connection dropped while waiting for a response
+    Disconnected,
+    Timeout,
+}
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunnerError::Unavailable => write!(f, "could not communicate with the runner"),
+            RunnerError::Disconnected => write!(f, "lost the connection to the runner"),
+            RunnerError::Timeout => write!(f, "timed out waiting for the runner"),
+        }
+    }
+}
+
+impl From<RunnerError> for Status {
+    fn from(e: RunnerError) -> Self {
+        match e {
+            RunnerError::Unavailable | RunnerError::Disconnected => Status::FailedDependency,
+            RunnerError::Timeout => Status::GatewayTimeout,
+        }
+    }
+}
+
+impl From<RunnerError> for crate::jobs::JobError {
+    fn from(e: RunnerError) -> Self {
+        Self::new(e.into(), e.to_string()).kind(e.into())
+    }
+}
+
+#[derive(Serialize)]
+struct Cmd<'a, P: Serialize> {
+    id: Uuid,
+    jsonrpc: &'static str,
+    method: &'a str,
+    params: P,
+}
+
+/// A message sent to `/rnbo/resp` in response to a `/rnbo/cmd`.
+#[derive(Deserialize)]
+pub struct CmdResponse {
+    id: Uuid,
+    pub error: Option<serde_json::Value>,
+    pub result: Option<serde_json::Value>,
+}
+
+/// The error the runner answered a command with.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunnerFault {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i64>,
+    pub message: String,
+}
+
+//the runner may send a JSON-RPC error object or something simpler
+impl From<serde_json::Value> for RunnerFault {
+    fn from(error: serde_json::Value) -> Self {
+        match error {
+            serde_json::Value::String(message) => Self {
+                code: None,
+                message,
+            },
+            error => Self {
+                code: error.get("code").and_then(serde_json::Value::as_i64),
+                message: error
+                    .get("message")
+                    .and_then(serde_json::Value::as_str)
+                    .map_or_else(|| error.to_string(), str::to_string),
+            },
+        }
+    }
+}
+
+//helper struct to get OSCQuery values
+#[derive(Deserialize)]
+struct ValueBody<T> {
+    #[serde(rename = "VALUE")]
+    value: T,
+}
+
+//helper struct to get the children of an OSCQuery node
+#[derive(Deserialize)]
+struct ContentsBody {
+    #[serde(rename = "CONTENTS", default)]
+    contents: HashMap<String, serde_json::Value>,
+}
+
+/// The responses to a single command, in the order the runner sent them.
+pub struct CmdResponses {
+    id: Uuid,
+    rx: mpsc::UnboundedReceiver<CmdResponse>,
+    shared: Arc<Shared>,
+}
+
+impl CmdResponses {
+    pub async fn next(&mut self) -> Result<CmdResponse, RunnerError> {
+        self.rx.recv().await.ok_or(RunnerError::Disconnected)
+    }
+}
+
+impl Drop for CmdResponses {
+    fn drop(&mut self) {
+        self.shared.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+struct Shared {
+    //responses are routed to the command that is waiting for them by id
+    pending: Mutex<HashMap<Uuid, mpsc::UnboundedSender<CmdResponse>>>,
+    //present while there is an open websocket
+    sender: watch::Sender<Option<mpsc::UnboundedSender<Message>>>,
+    version: Mutex<Option<String>>,
+}
+
+impl Shared {
+    fn dispatch(&self, packet: OscPacket) {
+        match packet {
+            OscPacket::Message(m) => {
+                if m.addr == "/rnbo/resp"
+                    && let Some(OscType::String(resp)) = m.args.first()
+                    && let Ok(resp) = serde_json::from_str::<CmdResponse>(resp.as_str())
+                    && let Some(tx) = self.pending.lock().unwrap().get(&resp.id)
+                {
+                    let _ = tx.send(resp);
+                }
+            }
+            OscPacket::Bundle(b) => {
+                for p in b.content {
+                    self.dispatch(p);
+                }
+            }
+        }
+    }
+
+    fn disconnected(&self) {
+        self.sender.send_replace(None);
+        //dropping the senders ends any in flight command with RunnerError::Disconnected
+        self.pending.lock().unwrap().clear();
+        //the runner may have been restarted, or upgraded, by the time we reconnect
+        *self.version.lock().unwrap() = None;
+    }
+}
+
+struct Inner {
+    url: String,
+    //how long to wait for the runner when it isn't connected or doesn't answer a query
+    timeout: Duration,
+    http: reqwest::Client,
+    shared: Arc<Shared>,
+    started: Once,
+}
+
+/// A long lived connection to the runner, shared by all the routes via rocket managed state.
+#[derive(Clone)]
+pub struct Runner {
+    inner: Arc<Inner>,
+}
+
+impl Runner {
+    pub fn new<T: Into<String>>(url: T, timeout: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                url: url.into(),
+                timeout,
+                http: reqwest::Client::new(),
+                shared: Arc::new(Shared {
+                    pending: Mutex::new(HashMap::new()),
+                    sender: watch::Sender::new(None),
+                    version: Mutex::new(None),
+                }),
+                started: Once::new(),
+            }),
+        }
+    }
+
+    /// Send a command to the runner and get a stream of its responses.
+    pub async fn cmd<P: Serialize>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<CmdResponses, RunnerError> {
+        self.start();
+        let shared = &self.inner.shared;
+        let sender = {
+            let mut rx = shared.sender.subscribe();
+            tokio::time::timeout(self.inner.timeout, rx.wait_for(|s| s.is_some()))
+                .await
+                .map_err(|_| RunnerError::Unavailable)?
+                .map_err(|_| RunnerError::Unavailable)?
+                .clone()
+                .ok_or(RunnerError::Unavailable)?
+        };
+
+        let id = Uuid::new_v4();
+        let cmd = serde_json::to_string(&Cmd {
+            id,
+            jsonrpc: "2.0",
+            method,
+            params,
+        })
+        .map_err(|_| RunnerError::Unavailable)?;
+        let packet = rosc::encoder::encode(&OscPacket::Message(OscMessage {
+            addr: "/rnbo/cmd".to_string(),
+            args: vec![OscType::String(cmd)],
+        }))
+        .map_err(|_| RunnerError::Unavailable)?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        shared.pending.lock().unwrap().insert(id, tx);
+        let responses = CmdResponses {
+            id,
+            rx,
+            shared: shared.clone(),
+        };
+        sender
+            .send(Message::Binary(packet.into()))
+            .map_err(|_| RunnerError::Disconnected)?;
+        Ok(responses)
+    }
+
+    /// Get the VALUE of an OSCQuery node, eg `/rnbo/info/version`.
+    pub async fn value<T: DeserializeOwned>(&self, path: &str) -> Result<T, RunnerError> {
+        let body: ValueBody<T> = self.query(&format!("{path}?VALUE")).await?;
+        Ok(body.value)
+    }
+
+    /// The names of the patchers the runner knows, the children of `/rnbo/patchers`.
+    pub async fn patchers(&self) -> Result<HashSet<String>, RunnerError> {
+        let node: ContentsBody = self.query("/rnbo/patchers").await?;
+        Ok(node.contents.into_keys().collect())
+    }
+
+    async fn query<T: DeserializeOwned>(&self, path: &str) -> Result<T, RunnerError> {
+        let url = format!("{}{}", self.inner.url, path);
+        tokio::time::timeout(self.inner.timeout, async {
+            self.inner
+                .http
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+        })
+        .await
+        .map_err(|_| RunnerError::Timeout)?
+        .map_err(|_| RunnerError::Unavailable)
+    }
+
+    /// The runner's RNBO version, cached until the connection to the runner drops.
+    pub async fn version(&self) -> Result<String, RunnerError> {
+        //start the connection so that we notice when the runner restarts
+        self.start();
+        if let Some(version) = self.inner.shared.version.lock().unwrap().clone() {
+            return Ok(version);
+        }
+        let version: String = self.value("/rnbo/info/version").await?;
+        *self.inner.shared.version.lock().unwrap() = Some(version.clone());
+        Ok(version)
+    }
+
+    fn start(&self) {
+        self.inner.started.call_once(|| {
+            tokio::spawn(maintain(self.inner.clone()));
+        });
+    }
+}
+
+//keep a websocket open to the runner, reconnecting with backoff when it goes away
+async fn maintain(inner: Arc<Inner>) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        if let Ok(res) = inner.http.get(&inner.url).upgrade().send().await
+            && let Ok(ws) = res.into_websocket().await
+        {
+            backoff = MIN_BACKOFF;
+            let (mut sink, mut stream) = ws.split();
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            inner.shared.sender.send_replace(Some(tx));
+            loop {
+                tokio::select! {
+                    Some(msg) = rx.recv() => {
+                        if sink.send(msg).await.is_err() {
+                            break;
+                        }
+                    }
+                    msg = stream.next() => match msg {
+                        Some(Ok(Message::Binary(vec))) => {
+                            if let Ok((_, packet)) = rosc::decoder::decode_udp(vec.as_ref()) {
+                                inner.shared.dispatch(packet);
+                            }
+                        }
+                        Some(Ok(_)) => (),
+                        _ => break,
+                    }
+                }
+            }
+            inner.shared.disconnected();
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{Runner, RunnerError},
+        crate::fake_runner::FakeRunner,
+        serde_json::json,
+        std::time::Duration,
+    };
+
+    //poll until `f` is true, or give up after a few seconds
+    async fn eventually<F: AsyncFn() -> bool>(f: F) -> bool {
+        for _ in 0..300 {
+            if f().await {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn responses_by_id() {
+        let fake = FakeRunner::start("1.2.3");
+        //answer for another command first, then echo the params twice
+        fake.on("echo", |params| {
+            vec![
+                json!({ "id": uuid::Uuid::new_v4(), "result": "stray" }),
+                json!({ "result": params }),
+                json!({ "result": params }),
+            ]
+        });
+        let runner = Runner::new(fake.url(), Duration::from_secs(2));
+
+        let (mut a, mut b) = (
+            runner.cmd("echo", "a").await.expect("to send"),
+            runner.cmd("echo", "b").await.expect("to send"),
+        );
+        for _ in 0..2 {
+            assert_eq!(a.next().await.expect("to get a").result, Some(json!("a")));
+            assert_eq!(b.next().await.expect("to get b").result, Some(json!("b")));
+        }
+
+        let mut missing = runner.cmd("missing", ()).await.expect("to send");
+        let resp = missing.next().await.expect("to get error");
+        assert!(resp.result.is_none());
+        assert_eq!(resp.error.expect("to get error")["code"], -32601);
+        assert_eq!(fake.commands().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn reconnect() {
+        let fake = FakeRunner::start("1.2.3");
+        fake.on("wait", |_| vec![json!({ "result": { "progress": 50 } })]);
+        fake.on("echo", |params| vec![json!({ "result": params })]);
+        let runner = Runner::new(fake.url(), Duration::from_secs(2));
+
+        let mut waiting = runner.cmd("wait", ()).await.expect("to send");
+        assert!(waiting.next().await.is_ok());
+        fake.disconnect();
+        //in flight commands end with the connection
+        assert_eq!(waiting.next().await.err(), Some(RunnerError::Disconnected));
+
+        //and new ones wait for the next
+        let mut echo = runner.cmd("echo", 1).await.expect("to send");
+        assert_eq!(echo.next().await.expect("to get").result, Some(json!(1)));
+        assert_eq!(fake.connections(), 2);
+
+        //the runner isn't there at all
+        let runner = Runner::new("http://127.0.0.1:1".to_string(), Duration::from_millis(100));
+        assert_eq!(
+            runner.cmd("echo", 1).await.err(),
+            Some(RunnerError::Unavailable)
+        );
+    }
+
+    #[tokio::test]
+    async fn version_cache() {
+        let fake = FakeRunner::start("1.2.3");
+        let runner = Runner::new(fake.url(), Duration::from_secs(2));
+        assert_eq!(runner.version().await, Ok("1.2.3".to_string()));
+        assert!(eventually(async || fake.connections() == 1).await);
+
+        //an upgrade is only noticed once the runner restarts
+        fake.set_version("1.3.0");
+        assert_eq!(runner.version().await, Ok("1.2.3".to_string()));
+        fake.disconnect();
+        assert!(eventually(async || runner.version().await == Ok("1.3.0".to_string())).await);
+    }
+}
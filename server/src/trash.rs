@@ -0,0 +1,165 @@
+use {
+    crate::filelist,
+    rocket::serde::{Deserialize, Serialize},
+    std::{
+        fs, io,
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    },
+    uuid::Uuid,
+};
+
+//deleted files are kept in a hidden directory inside the filetype directory so that they
+//don't show up in listings and can be moved there and back without crossing filesystems
+pub const TRASH_DIR: &str = ".trash";
+
+/// When trashed items are purged for good.
+#[derive(Clone, Copy, Debug)]
+pub struct TrashLimits {
+    pub max_age: Duration,
+    //bytes per filetype, the oldest items go first
+    pub max_size: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct TrashItem {
+    pub id: Uuid,
+    //where it was deleted from, relative to the filetype directory
+    pub path: PathBuf,
+    pub dir: bool,
+    //in bytes, everything inside for directories
+    pub size: u64,
+    //RFC 3339
+    pub deleted: String,
+}
+
+impl TrashItem {
+    fn deleted_at(&self) -> SystemTime {
+        humantime::parse_rfc3339(&self.deleted).unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+}
+
+/// The trash of one filetype directory. Everything here blocks, so callers keep it off the
+/// async workers.
+pub struct Trash {
+    dir: PathBuf,
+}
+
+impl Trash {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            dir: root.join(TRASH_DIR),
+        }
+    }
+
+    /// Move `path`, which was at `relative` in the filetype directory, into the trash.
+    pub fn put(&self, path: &Path, relative: &Path, limits: TrashLimits) -> io::Result<TrashItem> {
+        fs::create_dir_all(&self.dir)?;
+        let item = TrashItem {
+            id: Uuid::new_v4(),
+            path: relative.to_path_buf(),
+            //a link is trashed, not what it points to
+            dir: path.symlink_metadata()?.is_dir(),
+            size: filelist::tree_size(path),
+            deleted: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        };
+        fs::write(self.meta_path(item.id), serde_json::to_vec(&item)?)?;
+        if let Err(e) = fs::rename(path, self.data_path(item.id)) {
+            let _ = fs::remove_file(self.meta_path(item.id));
+            return Err(e);
+        }
+        self.purge(limits, Some(item.id));
+        Ok(item)
+    }
+
+    /// Everything in the trash, most recently deleted first.
+    pub fn list(&self) -> Vec<TrashItem> {
+        let mut items: Vec<TrashItem> = fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|e| e == "json"))
+            .filter_map(|entry| serde_json::from_slice(&fs::read(entry.path()).ok()?).ok())
+            .filter(|item: &TrashItem| self.data_path(item.id).symlink_metadata().is_ok())
+            .collect();
+        items.sort_by_key(|item| std::cmp::Reverse(item.deleted_at()));
+        items
+    }
+
+    pub fn get(&self, id: Uuid) -> io::Result<TrashItem> {
+        let item: TrashItem = serde_json::from_slice(&fs::read(self.meta_path(id))?)?;
+        self.data_path(id).symlink_metadata()?;
+        Ok(item)
+    }
+
+    /// Move an item back to `dest`, which must not exist.
+    pub fn restore(&self, id: Uuid, dest: &Path) -> io::Result<()> {
+        if dest.symlink_metadata().is_ok() {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        fs::create_dir_all(dest.parent().expect("to get parent path"))?;
+        fs::rename(self.data_path(id), dest)?;
+        let _ = fs::remove_file(self.meta_path(id));
+        Ok(())
+    }
+
+    /// Delete an item for good.
+    pub fn remove(&self, id: Uuid) -> io::Result<()> {
+        let data = self.data_path(id);
+        if data.symlink_metadata()?.is_dir() {
+            fs::remove_dir_all(&data)?;
+        } else {
+            fs::remove_file(&data)?;
+        }
+        fs::remove_file(self.meta_path(id))
+    }
+
+    /// Delete everything in the trash for good, including anything left half trashed.
+    pub fn empty(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove items that are older than allowed, then the oldest ones until the rest fit. `keep`
+    /// is the item that was just trashed, which is never removed.
+    pub fn purge(&self, limits: TrashLimits, keep: Option<Uuid>) {
+        let now = SystemTime::now();
+        let mut items = self.list();
+        //the newest item counts first, whatever the clock said when the others were deleted
+        if let Some(i) = items.iter().position(|item| Some(item.id) == keep) {
+            let kept = items.remove(i);
+            items.insert(0, kept);
+        }
+        let mut size = 0;
+        let mut full = false;
+        for item in items {
+            if Some(item.id) == keep {
+                size += item.size;
+                continue;
+            }
+            let expired = now
+                .duration_since(item.deleted_at())
+                .is_ok_and(|age| age > limits.max_age);
+            //once an item doesn't fit, it and everything older goes
+            full = full || size + item.size > limits.max_size;
+            if expired || full {
+                if let Err(e) = self.remove(item.id) {
+                    eprintln!("failed to purge {} from trash: {e}", item.path.display());
+                }
+            } else {
+                size += item.size;
+            }
+        }
+    }
+
+    fn data_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    fn meta_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+}
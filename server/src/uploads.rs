@@ -0,0 +1,315 @@
+use {
+    crate::{
+        digest,
+        usage::{SPACE_CHECK_INTERVAL, Space},
+    },
+    rocket::{
+        Request,
+        data::{Data, ToByteUnit},
+        http::Status,
+        request::{FromRequest, Outcome},
+        serde::{Deserialize, Serialize},
+    },
+    std::{
+        collections::HashSet,
+        io,
+        path::{Path, PathBuf},
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    },
+    tokio::io::{AsyncReadExt, AsyncWriteExt},
+    uuid::Uuid,
+};
+
+//partial uploads live in a hidden directory inside the filetype directory so that they
+//don't show up in listings and can be renamed into place without crossing filesystems
+pub const STAGING_DIR: &str = ".uploads";
+
+//sessions that haven't received data for this long are removed
+const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub filetype: String,
+    //destination, relative to the filetype directory
+    pub path: PathBuf,
+    //bytes received so far, derived from the staged data
+    #[serde(default)]
+    pub offset: u64,
+    //total size, if the client told us up front
+    pub length: Option<u64>,
+}
+
+pub enum ReceiveError {
+    //the body was larger than allowed
+    TooLarge,
+    //the free space fell below the reserve, with what was left
+    Full(Space),
+    Io(io::Error),
+}
+
+/// The announced length of a request body and the most the "file" limit lets it be.
+pub struct BodySize {
+    pub length: Option<u64>,
+    pub limit: u64,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BodySize {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let length = match req
+            .headers()
+            .get_one("Content-Length")
+            .map(|v| v.trim().parse())
+        {
+            Some(Ok(length)) => Some(length),
+            Some(Err(_)) => return Outcome::Error((Status::BadRequest, ())),
+            None => None,
+        };
+        Outcome::Success(Self {
+            length,
+            limit: req.limits().get("file").unwrap_or(1.mebibytes()).as_u64(),
+        })
+    }
+}
+
+/// Stream a request body to `path`, giving up once it grows past `limit` or the free space of
+/// the filesystem falls below `reserve`. Nothing is left behind when it fails.
+pub async fn receive(
+    data: Data<'_>,
+    path: &Path,
+    limit: u64,
+    reserve: u64,
+) -> Result<u64, ReceiveError> {
+    let received = match tokio::fs::File::create(path).await {
+        Ok(file) => receive_into(data, file, path, limit, reserve).await,
+        Err(e) => Err(ReceiveError::Io(e)),
+    };
+    if received.is_err() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    received
+}
+
+//write the body to the end of `file`, which is at `path`
+async fn receive_into(
+    data: Data<'_>,
+    mut file: tokio::fs::File,
+    path: &Path,
+    limit: u64,
+    reserve: u64,
+) -> Result<u64, ReceiveError> {
+    let dir = path.parent().expect("to get parent path");
+    //one byte more than allowed tells a body that is too large from one that just fits
+    let mut stream = data.open(limit.saturating_add(1).bytes());
+    let mut buf = vec![0; 64 * 1024];
+    let mut received = 0;
+    let mut next_check = 0;
+    loop {
+        if received >= next_check {
+            if let Some(space) = Space::of(dir)
+                && space.free < reserve
+            {
+                return Err(ReceiveError::Full(space));
+            }
+            next_check = received + SPACE_CHECK_INTERVAL;
+        }
+        let n = stream.read(&mut buf).await.map_err(ReceiveError::Io)?;
+        if n == 0 {
+            break;
+        }
+        received += n as u64;
+        if received > limit {
+            return Err(ReceiveError::TooLarge);
+        }
+        file.write_all(&buf[..n]).await.map_err(ReceiveError::Io)?;
+    }
+    file.flush().await.map_err(ReceiveError::Io)?;
+    Ok(received)
+}
+
+/// A resumable upload, staged in the filetype directory until it is finished.
+pub struct Upload {
+    root: PathBuf,
+    pub session: UploadSession,
+}
+
+impl Upload {
+    pub async fn create(
+        root: &Path,
+        filetype: &str,
+        path: PathBuf,
+        length: Option<u64>,
+    ) -> io::Result<Self> {
+        let staging = root.join(STAGING_DIR);
+        tokio::fs::create_dir_all(&staging).await?;
+        remove_expired(&staging).await;
+
+        let upload = Self {
+            root: root.to_path_buf(),
+            session: UploadSession {
+                id: Uuid::new_v4(),
+                filetype: filetype.to_string(),
+                path,
+                offset: 0,
+                length,
+            },
+        };
+        tokio::fs::File::create(upload.part_path()).await?;
+        let meta = serde_json::to_vec(&upload.session)?;
+        tokio::fs::write(upload.meta_path(), meta).await?;
+        Ok(upload)
+    }
+
+    /// Find an upload session in any of the filetype directories.
+    pub async fn open<'a, I: Iterator<Item = &'a PathBuf>>(roots: I, id: Uuid) -> Option<Self> {
+        for root in roots {
+            let meta = root.join(STAGING_DIR).join(format!("{id}.json"));
+            if let Ok(meta) = tokio::fs::read(meta).await
+                && let Ok(session) = serde_json::from_slice::<UploadSession>(&meta)
+                && session.id == id
+            {
+                let mut upload = Self {
+                    root: root.clone(),
+                    session,
+                };
+                upload.session.offset = tokio::fs::metadata(upload.part_path()).await.ok()?.len();
+                return Some(upload);
+            }
+        }
+        None
+    }
+
+    /// Append a chunk to the staged data, held to `limit` and the free space to `reserve` like
+    /// [`receive`]. Unless the whole chunk arrives and fits nothing is kept.
+    pub async fn append(
+        &mut self,
+        data: Data<'_>,
+        limit: u64,
+        reserve: u64,
+    ) -> Result<u64, ReceiveError> {
+        let limit = match self.session.length {
+            Some(length) => limit.min(length.saturating_sub(self.session.offset)),
+            None => limit,
+        };
+        let path = self.part_path();
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(ReceiveError::Io)?;
+        let received = receive_into(data, file, &path, limit, reserve).await;
+        match received {
+            Ok(n) => {
+                self.session.offset += n;
+                Ok(self.session.offset)
+            }
+            Err(e) => {
+                //drop what we got of this chunk so that the offset stays where the client expects
+                let file = tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .await
+                    .map_err(ReceiveError::Io)?;
+                file.set_len(self.session.offset)
+                    .await
+                    .map_err(ReceiveError::Io)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Whether all the data the client announced has arrived.
+    pub fn is_complete(&self) -> bool {
+        self.session
+            .length
+            .is_none_or(|length| length == self.session.offset)
+    }
+
+    /// Move the staged data to its destination, returning the full path.
+    pub async fn finish(self) -> io::Result<PathBuf> {
+        let dest = self.root.join(&self.session.path);
+        let parent = dest.parent().expect("to get parent path").to_path_buf();
+        tokio::fs::create_dir_all(&parent).await?;
+        tokio::fs::File::open(self.part_path())
+            .await?
+            .sync_all()
+            .await?;
+        tokio::fs::rename(self.part_path(), &dest).await?;
+        let _ = tokio::task::spawn_blocking(move || digest::sync_dir(&parent)).await;
+        let _ = tokio::fs::remove_file(self.meta_path()).await;
+        Ok(dest)
+    }
+
+    pub async fn abort(self) -> io::Result<()> {
+        //the data is gone already if it was refused when finishing
+        match tokio::fs::remove_file(self.part_path()).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        tokio::fs::remove_file(self.meta_path()).await
+    }
+
+    /// Where the data received so far is staged.
+    pub fn part_path(&self) -> PathBuf {
+        self.root
+            .join(STAGING_DIR)
+            .join(format!("{}.part", self.session.id))
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        self.root
+            .join(STAGING_DIR)
+            .join(format!("{}.json", self.session.id))
+    }
+}
+
+async fn remove_expired(staging: &Path) {
+    let Ok(mut entries) = tokio::fs::read_dir(staging).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "part")
+            && let Ok(modified) = entry.metadata().await.and_then(|m| m.modified())
+            && SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|age| age > SESSION_TTL)
+        {
+            let _ = tokio::fs::remove_file(&path).await;
+            let _ = tokio::fs::remove_file(path.with_extension("json")).await;
+        }
+    }
+}
+
+/// Tracks which uploads are currently receiving data, so that two requests can't append
+/// to the same upload at once.
+#[derive(Default)]
+pub struct Uploads {
+    busy: Mutex<HashSet<Uuid>>,
+}
+
+pub struct UploadLock<'a> {
+    uploads: &'a Uploads,
+    id: Uuid,
+}
+
+impl Uploads {
+    pub fn lock(&self, id: Uuid) -> Option<UploadLock<'_>> {
+        if self.busy.lock().unwrap().insert(id) {
+            Some(UploadLock { uploads: self, id })
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for UploadLock<'_> {
+    fn drop(&mut self) {
+        self.uploads.busy.lock().unwrap().remove(&self.id);
+    }
+}
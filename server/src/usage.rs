@@ -0,0 +1,128 @@
+use {
+    crate::watch::{Change, FsWatch},
+    rocket::serde::Serialize,
+    std::{
+        collections::HashMap,
+        path::Path,
+        sync::Mutex,
+        time::{Duration, Instant, SystemTime},
+    },
+    tokio::sync::broadcast::{self, error::TryRecvError},
+};
+
+//walks are redone after this long even without reported changes, hidden files and
+//changes the watcher missed don't invalidate them
+const MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// What a directory holds, hidden files included.
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct DirUsage {
+    //in bytes
+    pub size: u64,
+    pub files: u64,
+    pub dirs: u64,
+    //RFC 3339, when the directory was walked
+    pub scanned: String,
+}
+
+impl DirUsage {
+    /// Walk `path` without following links. This blocks.
+    pub fn walk(path: &Path) -> Self {
+        let mut usage = Self {
+            size: 0,
+            files: 0,
+            dirs: 0,
+            scanned: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        };
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    usage.dirs += 1;
+                    dirs.push(entry.path());
+                } else {
+                    usage.files += 1;
+                    usage.size += metadata.len();
+                }
+            }
+        }
+        usage
+    }
+}
+
+/// How much is written between checks of the free space.
+pub const SPACE_CHECK_INTERVAL: u64 = 4 * 1024 * 1024;
+
+/// Free and total bytes of the filesystem a path is on.
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Space {
+    //available to the panel, without the blocks reserved for root
+    pub free: u64,
+    pub total: u64,
+}
+
+impl Space {
+    pub fn of(path: &Path) -> Option<Self> {
+        let stat = rustix::fs::statvfs(path).ok()?;
+        Some(Self {
+            free: stat.f_bavail.saturating_mul(stat.f_frsize),
+            total: stat.f_blocks.saturating_mul(stat.f_frsize),
+        })
+    }
+}
+
+struct Walks {
+    changes: broadcast::Receiver<Change>,
+    walks: HashMap<String, (Instant, DirUsage)>,
+}
+
+/// Caches the walks of the filetype directories until the watcher reports a change in them.
+pub struct DiskUsage {
+    walks: Mutex<Walks>,
+}
+
+impl DiskUsage {
+    pub fn new(watch: &FsWatch) -> Self {
+        Self {
+            walks: Mutex::new(Walks {
+                changes: watch.subscribe(),
+                walks: HashMap::new(),
+            }),
+        }
+    }
+
+    /// The last walk of a filetype, unless something changed in it since.
+    pub fn cached(&self, filetype: &str) -> Option<DirUsage> {
+        let mut walks = self.walks.lock().expect("to lock walks");
+        loop {
+            match walks.changes.try_recv() {
+                Ok(change) => match change.filetype {
+                    Some(filetype) => {
+                        walks.walks.remove(&filetype);
+                    }
+                    None => walks.walks.clear(),
+                },
+                Err(TryRecvError::Lagged(_)) => walks.walks.clear(),
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+        walks
+            .walks
+            .get(filetype)
+            .filter(|(at, _)| at.elapsed() < MAX_AGE)
+            .map(|(_, usage)| usage.clone())
+    }
+
+    pub fn store(&self, filetype: &str, usage: DirUsage) {
+        self.walks
+            .lock()
+            .expect("to lock walks")
+            .walks
+            .insert(filetype.to_string(), (Instant::now(), usage));
+    }
+}
@@ -0,0 +1,275 @@
+use {
+    crate::filelist::is_hidden,
+    notify::{
+        Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+        event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+    },
+    rocket::serde::Serialize,
+    std::{
+        collections::{HashMap, HashSet},
+        path::{Component, Path, PathBuf},
+        sync::mpsc,
+        time::{Duration, Instant},
+    },
+    tokio::sync::broadcast,
+};
+
+//changes buffered per subscriber before it is told to rescan
+const CHANNEL_CAPACITY: usize = 256;
+//how long the second half of a rename may take before the first is taken as a removal,
+//and how long a new directory is given before its contents are scanned
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Renamed,
+    //changes were lost, listings should be fetched again
+    Rescan,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct Change {
+    pub kind: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filetype: Option<String>,
+    //relative to the filetype directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    //the previous path of a rename
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+}
+
+impl Change {
+    pub fn rescan() -> Self {
+        Self {
+            kind: ChangeKind::Rescan,
+            filetype: None,
+            path: None,
+            from: None,
+        }
+    }
+
+    fn new(kind: ChangeKind, (filetype, path): (String, String)) -> Self {
+        Self {
+            kind,
+            filetype: Some(filetype),
+            path: Some(path),
+            from: None,
+        }
+    }
+}
+
+/// Watches the filetype directories and broadcasts the changes in them,
+/// whether they come through the api or from elsewhere.
+pub struct FsWatch {
+    changes: broadcast::Sender<Change>,
+    //dropping the watcher stops the watch
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl FsWatch {
+    pub fn new(filetype_paths: &HashMap<String, PathBuf>) -> Self {
+        let (changes, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let (tx, rx) = mpsc::channel();
+        let watcher = RecommendedWatcher::new(tx, notify::Config::default())
+            .map_err(|e| eprintln!("cannot watch for file changes: {e}"))
+            .ok()
+            .map(|mut watcher| {
+                for path in filetype_paths.values() {
+                    if let Err(e) = watcher.watch(path, RecursiveMode::Recursive) {
+                        eprintln!("cannot watch {}: {e}", path.display());
+                    }
+                }
+                watcher
+            });
+
+        let mut roots: Vec<(String, PathBuf)> = filetype_paths
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        //the most specific directory wins if one filetype lives inside another
+        roots.sort_by_key(|(_, path)| std::cmp::Reverse(path.components().count()));
+        {
+            let changes = changes.clone();
+            std::thread::spawn(move || translate(rx, roots, changes));
+        }
+        Self {
+            changes,
+            _watcher: watcher,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+        self.changes.subscribe()
+    }
+}
+
+//the filetype and relative path of `path`, None for paths that aren't listed
+fn locate(roots: &[(String, PathBuf)], path: &Path) -> Option<(String, String)> {
+    let (filetype, relative) = roots
+        .iter()
+        .find_map(|(filetype, root)| Some((filetype, path.strip_prefix(root).ok()?)))?;
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(c) => {
+                let c = c.to_str()?;
+                if is_hidden(c) {
+                    return None;
+                }
+                parts.push(c);
+            }
+            _ => return None,
+        }
+    }
+    //changes to the filetype directory itself aren't reported
+    if parts.is_empty() {
+        return None;
+    }
+    Some((filetype.clone(), parts.join("/")))
+}
+
+//turn raw notifications into changes, pairing up the halves of renames
+fn translate(
+    rx: mpsc::Receiver<notify::Result<Event>>,
+    roots: Vec<(String, PathBuf)>,
+    changes: broadcast::Sender<Change>,
+) {
+    let send = |kind, path: &Path| {
+        if let Some(located) = locate(&roots, path) {
+            let _ = changes.send(Change::new(kind, located));
+        }
+    };
+    //whatever lands in a new directory before the watcher gets to watch it would go
+    //unnoticed, so its contents are reported once it has had time to settle. Entries
+    //already reported are skipped, as are directories that are waiting for a scan of their own
+    let scan = |dir: PathBuf, new_dirs: &[(Instant, PathBuf)], seen: &mut HashSet<PathBuf>| {
+        let mut dirs = vec![dir];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if entry.file_type().is_ok_and(|t| t.is_dir())
+                    && !new_dirs.iter().any(|(_, d)| *d == path)
+                {
+                    dirs.push(path.clone());
+                }
+                if seen.insert(path.clone()) {
+                    send(ChangeKind::Created, &path);
+                }
+            }
+        }
+    };
+    let created =
+        |path: &Path, new_dirs: &mut Vec<(Instant, PathBuf)>, seen: &mut HashSet<PathBuf>| {
+            send(ChangeKind::Created, path);
+            seen.insert(path.to_path_buf());
+            if path.is_dir() {
+                new_dirs.push((Instant::now() + SETTLE_TIME, path.to_path_buf()));
+            }
+        };
+    let mut new_dirs = Vec::new();
+    //paths reported as created while scans are pending, forgotten once none are left
+    let mut seen = HashSet::new();
+    //the source of a rename whose destination hasn't been seen yet
+    let mut pending: Option<(Option<usize>, PathBuf)> = None;
+    loop {
+        let received = rx.recv_timeout(SETTLE_TIME);
+        let now = Instant::now();
+        let due: Vec<_> = new_dirs.extract_if(.., |(at, _)| *at <= now).collect();
+        for (_, dir) in due {
+            scan(dir, &new_dirs, &mut seen);
+        }
+        if new_dirs.is_empty() {
+            seen.clear();
+        }
+        let event = match received {
+            Ok(Ok(event)) => event,
+            Ok(Err(e)) => {
+                eprintln!("file watch error: {e}");
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                //moved out of the watched directories
+                if let Some((_, path)) = pending.take() {
+                    send(ChangeKind::Deleted, &path);
+                }
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        if event.need_rescan() {
+            let _ = changes.send(Change::rescan());
+            continue;
+        }
+
+        let tracker = event.tracker();
+        let paired = pending
+            .as_ref()
+            .is_some_and(|(t, _)| t.is_some() && *t == tracker);
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) if paired => {
+                //the Both event that follows carries both paths
+                continue;
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                pending = None;
+                if let [from, to] = &event.paths[..] {
+                    match (locate(&roots, from), locate(&roots, to)) {
+                        (Some(from), Some(to)) if from.0 == to.0 => {
+                            let mut change = Change::new(ChangeKind::Renamed, to);
+                            change.from = Some(from.1);
+                            let _ = changes.send(change);
+                        }
+                        //moved between filetypes or in or out of hidden directories
+                        (from, _) => {
+                            if let Some(from) = from {
+                                let _ = changes.send(Change::new(ChangeKind::Deleted, from));
+                            }
+                            created(to, &mut new_dirs, &mut seen);
+                        }
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some((_, path)) = pending.take() {
+            send(ChangeKind::Deleted, &path);
+        }
+        let kind = match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                if let Some(path) = event.paths.first() {
+                    pending = Some((tracker, path.clone()));
+                }
+                continue;
+            }
+            //moved in from outside the watched directories
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) | EventKind::Create(_) => {
+                event
+                    .paths
+                    .iter()
+                    .for_each(|p| created(p, &mut new_dirs, &mut seen));
+                continue;
+            }
+            //reported once the writer is done rather than for every write
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => ChangeKind::Modified,
+            EventKind::Remove(_) => {
+                event.paths.iter().for_each(|p| {
+                    seen.remove(p);
+                });
+                ChangeKind::Deleted
+            }
+            _ => continue,
+        };
+        for path in &event.paths {
+            send(kind, path);
+        }
+    }
+}